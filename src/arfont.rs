@@ -0,0 +1,420 @@
+//! Writer for the binary `.arfont` format read by `artery_font::ArteryFont`,
+//! and PNG helpers for atlas images stored outside of it.
+
+use std::io::{self, Read, Write};
+
+use artery_font::{
+    ArteryFont, CodepointType, FontVariant, Image, ImageType, MetadataFormat,
+    PixelFormat,
+};
+
+const HEADER_TAG: &[u8; 16] = b"ARTERY/FONT\0\0\0\0\0";
+const HEADER_MAGIC: u32 = 0x4d276a5c;
+const FOOTER_MAGIC: u32 = 0x55ccb363;
+const VERSION: u32 = 1;
+/// Reals are stored as 32-bit floats.
+const REAL_TYPE: u32 = 0x14;
+
+/// How atlas images are stored inside the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageEncoding {
+    /// Uncompressed pixels, bottom row first.
+    Raw,
+    Png,
+}
+
+/// Writes `font` in the `.arfont` format.
+pub fn write<W: Write>(
+    font: &ArteryFont,
+    encoding: ImageEncoding,
+    mut writer: W,
+) -> io::Result<()> {
+    let (metadata_format, metadata) = match &font.metadata_format {
+        MetadataFormat::None => (0, ""),
+        MetadataFormat::PlainText(text) => (1, text.as_str()),
+        MetadataFormat::Json(json) => (2, json.as_str()),
+    };
+
+    let mut variants = Vec::new();
+    for variant in &font.variants {
+        let m = &variant.metrics;
+        let mut metrics = [0f32; 32];
+        metrics[..8].copy_from_slice(&[
+            m.font_size,
+            m.distance_range,
+            m.em_size,
+            m.ascender,
+            m.descender,
+            m.line_height,
+            m.underline_y,
+            m.underline_thickness,
+        ]);
+
+        push_u32s(
+            &mut variants,
+            &[
+                variant.flags,
+                variant.weight,
+                codepoint_type_code(variant.codepoint_type),
+                image_type_code(variant.image_type),
+                variant.fallback_variant,
+                variant.fallback_glyph,
+            ],
+        );
+        push_u32s(&mut variants, &[0; 6]);
+        variants.extend_from_slice(bytemuck::cast_slice(&metrics));
+        push_u32s(
+            &mut variants,
+            &[
+                variant.name.len() as u32,
+                variant.metadata.len() as u32,
+                variant.glyphs.len() as u32,
+                variant.kern_pairs.len() as u32,
+            ],
+        );
+        push_string(&mut variants, &variant.name);
+        push_string(&mut variants, &variant.metadata);
+        variants.extend_from_slice(bytemuck::cast_slice(&variant.glyphs));
+        variants.extend_from_slice(bytemuck::cast_slice(&variant.kern_pairs));
+    }
+
+    let mut images = Vec::new();
+    for image in &font.images {
+        let row_length =
+            image.width * image.channels * image.pixel_format.bits() as u32 / 8;
+        let (encoding_code, orientation, data) = match encoding {
+            ImageEncoding::Raw => (1, -1i32, image.data.clone()),
+            ImageEncoding::Png => {
+                let mut data = Vec::new();
+                write_png(image, &mut data)?;
+                (8, 1, data)
+            }
+        };
+
+        push_u32s(
+            &mut images,
+            &[
+                image.flags,
+                encoding_code,
+                image.width,
+                image.height,
+                image.channels,
+                image.pixel_format.bits() as u32,
+                image_type_code(image.image_type),
+                row_length,
+                orientation as u32,
+                image.child_images,
+                image.texture_flags,
+                0,
+                0,
+                0,
+                image.metadata.len() as u32,
+                data.len() as u32,
+            ],
+        );
+        push_string(&mut images, &image.metadata);
+        images.extend_from_slice(&data);
+        realign(&mut images);
+    }
+
+    let mut appendices = Vec::new();
+    for appendix in &font.appendices {
+        push_u32s(
+            &mut appendices,
+            &[appendix.metadata.len() as u32, appendix.data.len() as u32],
+        );
+        push_string(&mut appendices, &appendix.metadata);
+        appendices.extend_from_slice(&appendix.data);
+        realign(&mut appendices);
+    }
+
+    let mut file = Vec::new();
+    file.extend_from_slice(HEADER_TAG);
+    push_u32s(
+        &mut file,
+        &[HEADER_MAGIC, VERSION, 0, REAL_TYPE, 0, 0, 0, 0],
+    );
+    push_u32s(
+        &mut file,
+        &[
+            metadata_format,
+            metadata.len() as u32,
+            font.variants.len() as u32,
+            variants.len() as u32,
+            font.images.len() as u32,
+            images.len() as u32,
+            font.appendices.len() as u32,
+            appendices.len() as u32,
+        ],
+    );
+    push_u32s(&mut file, &[0; 8]);
+    push_string(&mut file, metadata);
+    file.extend_from_slice(&variants);
+    file.extend_from_slice(&images);
+    file.extend_from_slice(&appendices);
+
+    // The total length includes the footer and the checksum following it.
+    let total_length = file.len() + 8 * 4;
+    push_u32s(&mut file, &[0, FOOTER_MAGIC, 0, 0, 0, 0]);
+    push_u32s(&mut file, &[total_length as u32]);
+
+    // CRC-32 without the final inversion.
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&file);
+    push_u32s(&mut file, &[!hasher.finalize()]);
+
+    writer.write_all(&file)
+}
+
+/// Encodes an 8-bit atlas image as a PNG. Rows are written top to bottom as
+/// PNG requires, while `image.data` holds them bottom row first.
+pub fn write_png<W: Write>(image: &Image, writer: W) -> io::Result<()> {
+    let color = match (image.pixel_format, image.channels) {
+        (PixelFormat::Unsigned8, 1) => png::ColorType::Grayscale,
+        (PixelFormat::Unsigned8, 2) => png::ColorType::GrayscaleAlpha,
+        (PixelFormat::Unsigned8, 3) => png::ColorType::Rgb,
+        (PixelFormat::Unsigned8, 4) => png::ColorType::Rgba,
+        (format, channels) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Can't encode a {:?} image with {} channels as PNG",
+                    format, channels
+                ),
+            ))
+        }
+    };
+
+    let mut encoder = png::Encoder::new(writer, image.width, image.height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let row = (image.width * image.channels) as usize;
+    let flipped: Vec<u8> = image
+        .data
+        .chunks_exact(row)
+        .rev()
+        .flatten()
+        .copied()
+        .collect();
+    encoder.write_header()?.write_image_data(&flipped)?;
+    Ok(())
+}
+
+/// Decodes a PNG into an 8-bit atlas image, bottom row first. Palette and
+/// 16-bit images are converted to 8 bits per channel.
+pub fn read_png<R: Read>(
+    reader: R,
+    image_type: ImageType,
+) -> Result<Image, png::DecodingError> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    data.truncate(info.buffer_size());
+
+    let data = data
+        .chunks_exact(info.line_size)
+        .rev()
+        .flatten()
+        .copied()
+        .collect();
+    Ok(Image {
+        flags: 0,
+        width: info.width,
+        height: info.height,
+        channels: info.color_type.samples() as u32,
+        pixel_format: PixelFormat::Unsigned8,
+        image_type,
+        child_images: 0,
+        texture_flags: 0,
+        metadata: String::new(),
+        data,
+    })
+}
+
+/// The variant exporters of single variant formats describe.
+pub fn first_variant(font: &ArteryFont) -> io::Result<&FontVariant> {
+    font.variants.first().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "The font has no variants")
+    })
+}
+
+fn push_u32s(buffer: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Strings are NUL terminated and padded to 4 bytes, empty strings are
+/// omitted entirely.
+fn push_string(buffer: &mut Vec<u8>, string: &str) {
+    if !string.is_empty() {
+        buffer.extend_from_slice(string.as_bytes());
+        buffer.push(0);
+        realign(buffer);
+    }
+}
+
+fn realign(buffer: &mut Vec<u8>) {
+    buffer.resize((buffer.len() + 3) & !3, 0);
+}
+
+fn codepoint_type_code(codepoint_type: CodepointType) -> u32 {
+    match codepoint_type {
+        CodepointType::Unspecified => 0,
+        CodepointType::Unicode => 1,
+        CodepointType::Indexed => 2,
+        CodepointType::Iconographic => 14,
+    }
+}
+
+fn image_type_code(image_type: ImageType) -> u32 {
+    match image_type {
+        ImageType::None => 0,
+        ImageType::SrgbImage => 1,
+        ImageType::LinearMask => 2,
+        ImageType::MaskedSrgbImage => 3,
+        ImageType::Sdf => 4,
+        ImageType::Psdf => 5,
+        ImageType::Msdf => 6,
+        ImageType::Mtsdf => 7,
+        ImageType::MixedContent => 255,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use artery_font::{
+        Advance, FontMetric, Glyph, KernPair, MetadataFormat, Rect,
+    };
+
+    use super::*;
+
+    fn image(width: u32, height: u32, channels: u32) -> Image {
+        Image {
+            flags: 0,
+            width,
+            height,
+            channels,
+            pixel_format: PixelFormat::Unsigned8,
+            image_type: ImageType::Msdf,
+            child_images: 0,
+            texture_flags: 0,
+            metadata: "odd".to_owned(),
+            // Every row differs, so flipped rows show
+            data: (0..width * height * channels).map(|i| i as u8).collect(),
+        }
+    }
+
+    fn font() -> ArteryFont {
+        let rect = |left, bottom, right, top| Rect {
+            left,
+            bottom,
+            right,
+            top,
+        };
+        let variant = FontVariant {
+            flags: 0,
+            weight: 400,
+            codepoint_type: CodepointType::Unicode,
+            image_type: ImageType::Msdf,
+            fallback_variant: 0,
+            fallback_glyph: 0,
+            metrics: FontMetric {
+                font_size: 32.0,
+                distance_range: 4.0,
+                em_size: 1.0,
+                ascender: 0.9,
+                descender: -0.25,
+                line_height: 1.2,
+                underline_y: -0.1,
+                underline_thickness: 0.05,
+            },
+            name: "Test".to_owned(),
+            metadata: String::new(),
+            glyphs: vec![
+                Glyph {
+                    codepoint: 'A' as u32,
+                    image: 0,
+                    plane_bounds: rect(-0.1, -0.1, 0.7, 0.8),
+                    image_bounds: rect(0.0, 0.0, 2.0, 2.0),
+                    advance: Advance {
+                        horizontal: 0.6,
+                        vertical: 0.0,
+                    },
+                },
+                Glyph {
+                    codepoint: ' ' as u32,
+                    image: 1,
+                    plane_bounds: rect(0.0, 0.0, 0.0, 0.0),
+                    image_bounds: rect(0.0, 0.0, 0.0, 0.0),
+                    advance: Advance {
+                        horizontal: 0.25,
+                        vertical: 0.0,
+                    },
+                },
+            ],
+            kern_pairs: vec![KernPair {
+                codepoint1: 'A' as u32,
+                codepoint2: ' ' as u32,
+                advance: Advance {
+                    horizontal: -0.05,
+                    vertical: 0.0,
+                },
+            }],
+        };
+        ArteryFont {
+            metadata_format: MetadataFormat::Json("{\"a\":1}".to_owned()),
+            variants: vec![variant],
+            images: vec![image(3, 2, 4), image(5, 3, 1)],
+            // artery-font checks the length of the appendix section against
+            // the number of appendices, so it can't read any back.
+            appendices: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let font = font();
+        for encoding in [ImageEncoding::Raw, ImageEncoding::Png] {
+            let mut file = Vec::new();
+            write(&font, encoding, &mut file).unwrap();
+            let read = ArteryFont::read(&file[..]).unwrap();
+            // artery-font types don't implement PartialEq
+            assert_eq!(format!("{:?}", read), format!("{:?}", font));
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        for channels in 1..=4 {
+            let image = image(7, 5, channels);
+            let mut png = Vec::new();
+            write_png(&image, &mut png).unwrap();
+            let read = read_png(&png[..], ImageType::Msdf).unwrap();
+            assert_eq!((read.width, read.height), (7, 5));
+            assert_eq!(read.channels, channels);
+            assert_eq!(read.data, image.data);
+        }
+    }
+
+    #[test]
+    fn png_rejects_float_images() {
+        let mut image = image(1, 1, 4);
+        image.pixel_format = PixelFormat::Float32;
+        let err = write_png(&image, Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn fonts_without_variants_have_no_first_variant() {
+        let font = ArteryFont {
+            variants: Vec::new(),
+            ..font()
+        };
+        assert!(first_variant(&font).is_err());
+        assert_eq!(first_variant(&self::font()).unwrap().name, "Test");
+    }
+}
@@ -0,0 +1,1019 @@
+//! AngelCode BMFont descriptors. BMFont measures everything in pixels with
+//! the origin at the top left of the page, while artery-font uses ems and a
+//! bottom left origin.
+//!
+//! Distance field atlases get the `distanceField` line used by libGDX and
+//! other engines, so the distance range isn't lost.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use artery_font::{
+    Advance, ArteryFont, CodepointType, FontMetric, FontVariant, Glyph, Image,
+    ImageType, KernPair, MetadataFormat, PixelFormat, Rect,
+};
+
+use crate::arfont;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BmFontFormat {
+    Text,
+    Xml,
+    Binary,
+}
+
+/// Kind of distance field stored in the pages. Used when loading
+/// descriptors without a `distanceField` line, like binary ones.
+#[derive(Debug, Clone, Copy)]
+pub struct DistanceField {
+    pub image_type: ImageType,
+    pub distance_range: f32,
+}
+
+impl Default for DistanceField {
+    fn default() -> Self {
+        Self {
+            image_type: ImageType::Sdf,
+            distance_range: 6.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BmFontError {
+    Io(io::Error),
+    Xml(xml::reader::Error),
+    Png(png::DecodingError),
+    /// The descriptor is malformed.
+    Parse(String),
+}
+
+impl std::fmt::Display for BmFontError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BmFontError::Io(err) => write!(f, "IO Error: {}", err),
+            BmFontError::Xml(err) => write!(f, "XML Error: {}", err),
+            BmFontError::Png(err) => write!(f, "PNG Error: {}", err),
+            BmFontError::Parse(msg) => write!(f, "BMFont Error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BmFontError {}
+
+#[derive(Default)]
+struct Char {
+    id: u32,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    x_offset: i32,
+    y_offset: i32,
+    x_advance: i32,
+    page: u32,
+    /// Bit mask of the page channels holding the glyph: 1 blue, 2 green,
+    /// 4 red, 8 alpha.
+    channel: u8,
+}
+
+/// Contents of a descriptor, in BMFont units.
+#[derive(Default)]
+struct Descriptor {
+    face: String,
+    size: i32,
+    line_height: i32,
+    base: i32,
+    width: u32,
+    height: u32,
+    field_type: Option<String>,
+    distance_range: f32,
+    pages: Vec<String>,
+    chars: Vec<Char>,
+    kernings: Vec<(u32, u32, i32)>,
+}
+
+/// Writes a descriptor for the first variant of `font`. `page_files` are the
+/// file names of the atlas images, in the order of `font.images`. Binary
+/// descriptors can't be written.
+pub fn write<W: Write>(
+    font: &ArteryFont,
+    format: BmFontFormat,
+    page_files: &[&str],
+    writer: W,
+) -> io::Result<()> {
+    let descriptor = Descriptor::from_artery(font, page_files)?;
+    match format {
+        BmFontFormat::Text => write_text(&descriptor, writer),
+        BmFontFormat::Xml => write_xml(&descriptor, writer),
+        BmFontFormat::Binary => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Writing binary BMFont descriptors isn't supported",
+        )),
+    }
+}
+
+impl Descriptor {
+    fn from_artery(font: &ArteryFont, page_files: &[&str]) -> io::Result<Self> {
+        let variant = arfont::first_variant(font)?;
+        let m = &variant.metrics;
+        let scale = m.font_size / m.em_size;
+        let (width, height) = font
+            .images
+            .iter()
+            .fold((0, 0), |(w, h), i| (i.width.max(w), i.height.max(h)));
+
+        let chars = variant
+            .glyphs
+            .iter()
+            .map(|g| {
+                let x_advance = (g.advance.horizontal * scale).round() as i32;
+                if !g.is_drawable() {
+                    return Char {
+                        id: g.codepoint,
+                        x_advance,
+                        page: g.image,
+                        channel: 15,
+                        ..Default::default()
+                    };
+                }
+
+                // Grow fractional image bounds to whole pixels and move the
+                // offsets by the same amount.
+                let (p, b) = (g.plane_bounds, g.image_bounds);
+                let page_height = font.images[g.image as usize].height as f32;
+                let (left, top) =
+                    (b.left.floor(), (page_height - b.top).floor());
+                let right = b.right.ceil();
+                let bottom = (page_height - b.bottom).ceil();
+                let x_offset = p.left * scale - (b.left - left);
+                let y_offset =
+                    (m.ascender - p.top) * scale - (page_height - b.top - top);
+
+                Char {
+                    id: g.codepoint,
+                    x: left as i32,
+                    y: top as i32,
+                    width: (right - left) as i32,
+                    height: (bottom - top) as i32,
+                    x_offset: x_offset.round() as i32,
+                    y_offset: y_offset.round() as i32,
+                    x_advance,
+                    page: g.image,
+                    channel: 15,
+                }
+            })
+            .collect();
+
+        let kernings = variant
+            .kern_pairs
+            .iter()
+            .map(|k| {
+                let amount = (k.advance.horizontal * scale).round() as i32;
+                (k.codepoint1, k.codepoint2, amount)
+            })
+            .collect();
+
+        Ok(Self {
+            face: variant.name.clone(),
+            // Negative, the size is the em size and not the cell height.
+            size: -(m.font_size.round() as i32),
+            line_height: (m.line_height * scale).round() as i32,
+            base: (m.ascender * scale).round() as i32,
+            width,
+            height,
+            field_type: field_type_name(variant.image_type).map(str::to_owned),
+            distance_range: m.distance_range,
+            pages: page_files.iter().map(|&f| f.to_owned()).collect(),
+            chars,
+            kernings,
+        })
+    }
+}
+
+fn field_type_name(image_type: ImageType) -> Option<&'static str> {
+    match image_type {
+        ImageType::Sdf => Some("sdf"),
+        ImageType::Psdf => Some("psdf"),
+        ImageType::Msdf => Some("msdf"),
+        ImageType::Mtsdf => Some("mtsdf"),
+        _ => None,
+    }
+}
+
+fn field_type_from_name(name: &str) -> Option<ImageType> {
+    match name {
+        "sdf" => Some(ImageType::Sdf),
+        "psdf" => Some(ImageType::Psdf),
+        "msdf" => Some(ImageType::Msdf),
+        "mtsdf" => Some(ImageType::Mtsdf),
+        _ => None,
+    }
+}
+
+fn write_text<W: Write>(d: &Descriptor, mut w: W) -> io::Result<()> {
+    writeln!(
+        w,
+        "info face=\"{}\" size={} bold=0 italic=0 charset=\"\" unicode=1 \
+         stretchH=100 smooth=1 aa=1 padding=0,0,0,0 spacing=0,0",
+        d.face.replace('"', "'"),
+        d.size
+    )?;
+    writeln!(
+        w,
+        "common lineHeight={} base={} scaleW={} scaleH={} pages={} packed=0 \
+         alphaChnl=0 redChnl=0 greenChnl=0 blueChnl=0",
+        d.line_height,
+        d.base,
+        d.width,
+        d.height,
+        d.pages.len()
+    )?;
+    if let Some(field_type) = &d.field_type {
+        writeln!(
+            w,
+            "distanceField fieldType={} distanceRange={}",
+            field_type, d.distance_range
+        )?;
+    }
+    for (id, file) in d.pages.iter().enumerate() {
+        writeln!(w, "page id={} file=\"{}\"", id, file)?;
+    }
+
+    writeln!(w, "chars count={}", d.chars.len())?;
+    for c in &d.chars {
+        writeln!(
+            w,
+            "char id={} x={} y={} width={} height={} xoffset={} yoffset={} \
+             xadvance={} page={} chnl={}",
+            c.id,
+            c.x,
+            c.y,
+            c.width,
+            c.height,
+            c.x_offset,
+            c.y_offset,
+            c.x_advance,
+            c.page,
+            c.channel
+        )?;
+    }
+
+    if !d.kernings.is_empty() {
+        writeln!(w, "kernings count={}", d.kernings.len())?;
+        for (first, second, amount) in &d.kernings {
+            writeln!(
+                w,
+                "kerning first={} second={} amount={}",
+                first, second, amount
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_xml<W: Write>(d: &Descriptor, mut w: W) -> io::Result<()> {
+    writeln!(w, "<?xml version=\"1.0\"?>")?;
+    writeln!(w, "<font>")?;
+    writeln!(
+        w,
+        "  <info face=\"{}\" size=\"{}\" bold=\"0\" italic=\"0\" charset=\"\" \
+         unicode=\"1\" stretchH=\"100\" smooth=\"1\" aa=\"1\" \
+         padding=\"0,0,0,0\" spacing=\"0,0\"/>",
+        escape_xml(&d.face),
+        d.size
+    )?;
+    writeln!(
+        w,
+        "  <common lineHeight=\"{}\" base=\"{}\" scaleW=\"{}\" scaleH=\"{}\" \
+         pages=\"{}\" packed=\"0\" alphaChnl=\"0\" redChnl=\"0\" \
+         greenChnl=\"0\" blueChnl=\"0\"/>",
+        d.line_height,
+        d.base,
+        d.width,
+        d.height,
+        d.pages.len()
+    )?;
+    if let Some(field_type) = &d.field_type {
+        writeln!(
+            w,
+            "  <distanceField fieldType=\"{}\" distanceRange=\"{}\"/>",
+            field_type, d.distance_range
+        )?;
+    }
+
+    writeln!(w, "  <pages>")?;
+    for (id, file) in d.pages.iter().enumerate() {
+        writeln!(w, "    <page id=\"{}\" file=\"{}\"/>", id, escape_xml(file))?;
+    }
+    writeln!(w, "  </pages>")?;
+
+    writeln!(w, "  <chars count=\"{}\">", d.chars.len())?;
+    for c in &d.chars {
+        writeln!(
+            w,
+            "    <char id=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+             xoffset=\"{}\" yoffset=\"{}\" xadvance=\"{}\" page=\"{}\" \
+             chnl=\"{}\"/>",
+            c.id,
+            c.x,
+            c.y,
+            c.width,
+            c.height,
+            c.x_offset,
+            c.y_offset,
+            c.x_advance,
+            c.page,
+            c.channel
+        )?;
+    }
+    writeln!(w, "  </chars>")?;
+
+    if !d.kernings.is_empty() {
+        writeln!(w, "  <kernings count=\"{}\">", d.kernings.len())?;
+        for (first, second, amount) in &d.kernings {
+            writeln!(
+                w,
+                "    <kerning first=\"{}\" second=\"{}\" amount=\"{}\"/>",
+                first, second, amount
+            )?;
+        }
+        writeln!(w, "  </kernings>")?;
+    }
+    writeln!(w, "</font>")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Loads a text, XML or binary descriptor, detected from its first bytes.
+/// `load_page` opens the PNG of a page given its file name as written in
+/// the descriptor. The result can be passed to `Requisites::init` like a
+/// font loaded from an `.arfont` file.
+///
+/// `field` is used when the descriptor doesn't say which kind of distance
+/// field its pages hold.
+///
+/// `em_size` is the em size in pixels the font was rendered at. It is only
+/// needed for a positive `size` in the descriptor, which is the height of
+/// the character cell. Without it, the em is estimated as the part of the
+/// cell above the baseline, so glyphs come out roughly but not exactly at
+/// the size of other fonts.
+pub fn read<R, F>(
+    data: &[u8],
+    field: DistanceField,
+    em_size: Option<f32>,
+    mut load_page: F,
+) -> Result<ArteryFont, BmFontError>
+where
+    R: Read,
+    F: FnMut(&str) -> io::Result<R>,
+{
+    let descriptor = match detect_format(data) {
+        BmFontFormat::Binary => parse_binary(data)?,
+        BmFontFormat::Xml => {
+            let elements = xml_elements(data)?;
+            Descriptor::from_elements(elements)?
+        }
+        BmFontFormat::Text => {
+            let text = std::str::from_utf8(data)
+                .map_err(|e| BmFontError::Parse(e.to_string()))?;
+            Descriptor::from_elements(text_elements(text))?
+        }
+    };
+
+    let image_type = descriptor
+        .field_type
+        .as_deref()
+        .and_then(field_type_from_name)
+        .unwrap_or(field.image_type);
+    let distance_range = match descriptor.field_type {
+        Some(_) => descriptor.distance_range,
+        None => field.distance_range,
+    };
+
+    let mut images = Vec::with_capacity(descriptor.pages.len());
+    for (page, file) in descriptor.pages.iter().enumerate() {
+        let reader = load_page(file).map_err(BmFontError::Io)?;
+        let mut image =
+            arfont::read_png(reader, image_type).map_err(BmFontError::Png)?;
+        // Single channel fields may sit in any channel of the page, the
+        // chars on the page tell which one.
+        let channel = descriptor
+            .chars
+            .iter()
+            .find(|c| c.page == page as u32)
+            .map_or(15, |c| c.channel);
+        expand_to_rgba(&mut image, image_type, channel);
+        images.push(image);
+    }
+
+    descriptor.into_artery(images, image_type, distance_range, em_size)
+}
+
+fn detect_format(data: &[u8]) -> BmFontFormat {
+    if data.starts_with(b"BMF") {
+        return BmFontFormat::Binary;
+    }
+    let text = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    match text.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'<') => BmFontFormat::Xml,
+        _ => BmFontFormat::Text,
+    }
+}
+
+/// A descriptor line or XML element, e.g. `char id=65 x=3 ...`.
+type Element = (String, HashMap<String, String>);
+
+fn text_elements(text: &str) -> Vec<Element> {
+    text.lines()
+        .filter_map(|line| {
+            let mut tokens = tokenize(line).into_iter();
+            let tag = tokens.next()?;
+            let attributes = tokens
+                .filter_map(|t| {
+                    let (key, value) = t.split_once('=')?;
+                    let value = value.trim_matches('"');
+                    Some((key.to_owned(), value.to_owned()))
+                })
+                .collect();
+            Some((tag, attributes))
+        })
+        .collect()
+}
+
+/// Splits a line at whitespace outside of quotes.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn xml_elements(data: &[u8]) -> Result<Vec<Element>, BmFontError> {
+    let mut elements = Vec::new();
+    for event in xml::EventReader::new(data) {
+        if let xml::reader::XmlEvent::StartElement {
+            name, attributes, ..
+        } = event.map_err(BmFontError::Xml)?
+        {
+            let attributes = attributes
+                .into_iter()
+                .map(|a| (a.name.local_name, a.value))
+                .collect();
+            elements.push((name.local_name, attributes));
+        }
+    }
+    Ok(elements)
+}
+
+/// Parses an attribute, missing attributes are zero.
+fn attr<T: FromStr + Default>(
+    attributes: &HashMap<String, String>,
+    key: &str,
+) -> Result<T, BmFontError> {
+    match attributes.get(key) {
+        Some(value) => value.trim().parse().map_err(|_| {
+            BmFontError::Parse(format!("Invalid value of {}: {}", key, value))
+        }),
+        None => Ok(T::default()),
+    }
+}
+
+impl Descriptor {
+    fn from_elements(elements: Vec<Element>) -> Result<Self, BmFontError> {
+        let mut d = Descriptor::default();
+        let mut pages = Vec::new();
+        for (tag, a) in &elements {
+            match tag.as_str() {
+                "info" => {
+                    d.face = a.get("face").cloned().unwrap_or_default();
+                    d.size = attr(a, "size")?;
+                }
+                "common" => {
+                    d.line_height = attr(a, "lineHeight")?;
+                    d.base = attr(a, "base")?;
+                    d.width = attr(a, "scaleW")?;
+                    d.height = attr(a, "scaleH")?;
+                }
+                "distanceField" => {
+                    d.field_type = a.get("fieldType").cloned();
+                    d.distance_range = attr(a, "distanceRange")?;
+                }
+                "page" => {
+                    let id: usize = attr(a, "id")?;
+                    let file = a.get("file").cloned().unwrap_or_default();
+                    pages.push((id, file));
+                }
+                "char" => d.chars.push(Char {
+                    id: attr(a, "id")?,
+                    x: attr(a, "x")?,
+                    y: attr(a, "y")?,
+                    width: attr(a, "width")?,
+                    height: attr(a, "height")?,
+                    x_offset: attr(a, "xoffset")?,
+                    y_offset: attr(a, "yoffset")?,
+                    x_advance: attr(a, "xadvance")?,
+                    page: attr(a, "page")?,
+                    channel: attr(a, "chnl")?,
+                }),
+                "kerning" => d.kernings.push((
+                    attr(a, "first")?,
+                    attr(a, "second")?,
+                    attr(a, "amount")?,
+                )),
+                _ => {}
+            }
+        }
+        pages.sort_by_key(|&(id, _)| id);
+        d.pages = pages.into_iter().map(|(_, file)| file).collect();
+        Ok(d)
+    }
+}
+
+/// Parses the binary format, version 3.
+fn parse_binary(data: &[u8]) -> Result<Descriptor, BmFontError> {
+    let truncated = || BmFontError::Parse("Truncated binary descriptor".into());
+    if data.get(3) != Some(&3) {
+        return Err(BmFontError::Parse(
+            "Only version 3 of the binary format is supported".into(),
+        ));
+    }
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let i16_at = |b: &[u8], i: usize| i16::from_le_bytes([b[i], b[i + 1]]);
+    let u32_at = |b: &[u8], i: usize| {
+        u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+    };
+
+    let mut d = Descriptor::default();
+    let mut rest = &data[4..];
+    while !rest.is_empty() {
+        let kind = rest[0];
+        let size = u32_at(rest.get(..5).ok_or_else(truncated)?, 1) as usize;
+        let block = rest.get(5..5 + size).ok_or_else(truncated)?;
+        rest = &rest[5 + size..];
+
+        match kind {
+            1 if size >= 14 => {
+                d.size = i16_at(block, 0) as i32;
+                let name = &block[14..];
+                let end =
+                    name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                d.face = String::from_utf8_lossy(&name[..end]).into_owned();
+            }
+            2 if size >= 15 => {
+                d.line_height = u16_at(block, 0) as i32;
+                d.base = u16_at(block, 2) as i32;
+                d.width = u16_at(block, 4) as u32;
+                d.height = u16_at(block, 6) as u32;
+            }
+            3 => {
+                d.pages = block
+                    .split(|&b| b == 0)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| String::from_utf8_lossy(name).into_owned())
+                    .collect();
+            }
+            4 => {
+                d.chars = block
+                    .chunks_exact(20)
+                    .map(|c| Char {
+                        id: u32_at(c, 0),
+                        x: u16_at(c, 4) as i32,
+                        y: u16_at(c, 6) as i32,
+                        width: u16_at(c, 8) as i32,
+                        height: u16_at(c, 10) as i32,
+                        x_offset: i16_at(c, 12) as i32,
+                        y_offset: i16_at(c, 14) as i32,
+                        x_advance: i16_at(c, 16) as i32,
+                        page: c[18] as u32,
+                        channel: c[19],
+                    })
+                    .collect();
+            }
+            5 => {
+                d.kernings = block
+                    .chunks_exact(10)
+                    .map(|k| (u32_at(k, 0), u32_at(k, 4), i16_at(k, 8) as i32))
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+    Ok(d)
+}
+
+impl Descriptor {
+    fn into_artery(
+        self,
+        images: Vec<Image>,
+        image_type: ImageType,
+        distance_range: f32,
+        em_size: Option<f32>,
+    ) -> Result<ArteryFont, BmFontError> {
+        // A negative size means the font was rendered to match the height
+        // of the characters, so it is the em size. A positive size is the
+        // height of the cell, which BMFont doesn't relate to the em. Unless
+        // the caller knows it, the em is estimated as the part of the cell
+        // above the baseline.
+        let scale = if self.size < 0 {
+            self.size.unsigned_abs() as f32
+        } else if let Some(em_size) = em_size {
+            em_size
+        } else {
+            self.size as f32 * self.base as f32 / self.line_height.max(1) as f32
+        }
+        .max(1.0);
+
+        let empty = Rect {
+            left: 0.0,
+            bottom: 0.0,
+            right: 0.0,
+            top: 0.0,
+        };
+        let mut glyphs = Vec::with_capacity(self.chars.len());
+        for c in &self.chars {
+            let page_height = match images.get(c.page as usize) {
+                Some(image) => image.height as f32,
+                None => {
+                    return Err(BmFontError::Parse(format!(
+                        "Char {} refers to the missing page {}",
+                        c.id, c.page
+                    )))
+                }
+            };
+
+            let left = c.x_offset as f32 / scale;
+            let top = (self.base - c.y_offset) as f32 / scale;
+            let (plane_bounds, image_bounds) = if c.width > 0 && c.height > 0 {
+                let plane_bounds = Rect {
+                    left,
+                    bottom: top - c.height as f32 / scale,
+                    right: left + c.width as f32 / scale,
+                    top,
+                };
+                let image_bounds = Rect {
+                    left: c.x as f32,
+                    bottom: page_height - (c.y + c.height) as f32,
+                    right: (c.x + c.width) as f32,
+                    top: page_height - c.y as f32,
+                };
+                (plane_bounds, image_bounds)
+            } else {
+                (empty, empty)
+            };
+
+            glyphs.push(Glyph {
+                codepoint: c.id,
+                image: c.page,
+                plane_bounds,
+                image_bounds,
+                advance: Advance {
+                    horizontal: c.x_advance as f32 / scale,
+                    vertical: 0.0,
+                },
+            });
+        }
+
+        let ascender = self.base as f32 / scale;
+        let descender = (self.base - self.line_height) as f32 / scale;
+        let variant = FontVariant {
+            flags: 0,
+            weight: 0,
+            codepoint_type: CodepointType::Unicode,
+            image_type,
+            fallback_variant: 0,
+            fallback_glyph: 0,
+            metrics: FontMetric {
+                font_size: scale,
+                distance_range,
+                em_size: 1.0,
+                ascender,
+                descender,
+                line_height: self.line_height as f32 / scale,
+                // BMFont has no underline metrics, place it halfway into
+                // the descender.
+                underline_y: descender * 0.5,
+                underline_thickness: 0.05,
+            },
+            name: self.face,
+            metadata: String::new(),
+            glyphs,
+            kern_pairs: self
+                .kernings
+                .iter()
+                .map(|&(first, second, amount)| KernPair {
+                    codepoint1: first,
+                    codepoint2: second,
+                    advance: Advance {
+                        horizontal: amount as f32 / scale,
+                        vertical: 0.0,
+                    },
+                })
+                .collect(),
+        };
+
+        Ok(ArteryFont {
+            metadata_format: MetadataFormat::None,
+            variants: vec![variant],
+            images,
+            appendices: Vec::new(),
+        })
+    }
+}
+
+/// Converts a page to RGBA. For single channel fields the channel selected
+/// by `channel` is copied to red, green and blue, so the MSDF shaders, which
+/// take the median of the three, see the plain distance.
+fn expand_to_rgba(image: &mut Image, image_type: ImageType, channel: u8) {
+    let channels = image.channels as usize;
+    if image.pixel_format != PixelFormat::Unsigned8 {
+        return;
+    }
+    let single = matches!(image_type, ImageType::Sdf | ImageType::Psdf);
+    // Index of the field inside an RGBA pixel.
+    let source = match channel {
+        8 => 3,
+        1 => 2,
+        2 => 1,
+        _ => 0,
+    };
+
+    image.data = image
+        .data
+        .chunks_exact(channels)
+        .flat_map(|p| {
+            let rgba = match p {
+                [v] => [*v, *v, *v, 255],
+                [v, a] => [*v, *v, *v, *a],
+                [r, g, b] => [*r, *g, *b, 255],
+                [r, g, b, a] => [*r, *g, *b, *a],
+                _ => unreachable!(),
+            };
+            if single {
+                let v = if channels == 1 { rgba[0] } else { rgba[source] };
+                [v, v, v, 255]
+            } else {
+                rgba
+            }
+        })
+        .collect();
+    image.channels = 4;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A font whose bounds are whole pixels at 32 pixels per em, so they
+    /// survive the rounding to BMFont's integers.
+    fn font() -> ArteryFont {
+        let rect = |left, bottom, right, top| Rect {
+            left,
+            bottom,
+            right,
+            top,
+        };
+        let glyph = |codepoint, image, plane_bounds, image_bounds| Glyph {
+            codepoint,
+            image,
+            plane_bounds,
+            image_bounds,
+            advance: Advance {
+                horizontal: 0.5,
+                vertical: 0.0,
+            },
+        };
+        let image = |width, height| Image {
+            flags: 0,
+            width,
+            height,
+            channels: 4,
+            pixel_format: PixelFormat::Unsigned8,
+            image_type: ImageType::Msdf,
+            child_images: 0,
+            texture_flags: 0,
+            metadata: String::new(),
+            data: (0..width * height * 4).map(|i| i as u8).collect(),
+        };
+        let empty = rect(0.0, 0.0, 0.0, 0.0);
+        let variant = FontVariant {
+            flags: 0,
+            weight: 0,
+            codepoint_type: CodepointType::Unicode,
+            image_type: ImageType::Msdf,
+            fallback_variant: 0,
+            fallback_glyph: 0,
+            metrics: FontMetric {
+                font_size: 32.0,
+                distance_range: 4.0,
+                em_size: 1.0,
+                ascender: 0.75,
+                descender: -0.5,
+                line_height: 1.25,
+                underline_y: -0.25,
+                underline_thickness: 0.05,
+            },
+            name: "Test".to_owned(),
+            metadata: String::new(),
+            glyphs: vec![
+                glyph(
+                    65,
+                    0,
+                    rect(-0.0625, 0.375, 0.15625, 0.625),
+                    rect(1.0, 2.0, 8.0, 10.0),
+                ),
+                glyph(
+                    66,
+                    1,
+                    rect(0.0, -0.125, 0.25, 0.5),
+                    rect(0.0, 0.0, 8.0, 20.0),
+                ),
+                glyph(32, 0, empty, empty),
+            ],
+            kern_pairs: vec![KernPair {
+                codepoint1: 65,
+                codepoint2: 66,
+                advance: Advance {
+                    horizontal: -0.0625,
+                    vertical: 0.0,
+                },
+            }],
+        };
+        ArteryFont {
+            metadata_format: MetadataFormat::None,
+            variants: vec![variant],
+            images: vec![image(16, 12), image(8, 20)],
+            appendices: Vec::new(),
+        }
+    }
+
+    /// Reads `descriptor` with the pages of `font` as `page0.png` and
+    /// `page1.png`.
+    fn read_with_pages(
+        descriptor: &[u8],
+        font: &ArteryFont,
+        em_size: Option<f32>,
+    ) -> Result<ArteryFont, BmFontError> {
+        let pages: Vec<Vec<u8>> = font
+            .images
+            .iter()
+            .map(|image| {
+                let mut png = Vec::new();
+                arfont::write_png(image, &mut png).unwrap();
+                png
+            })
+            .collect();
+        read(
+            descriptor,
+            DistanceField::default(),
+            em_size,
+            |file| match file {
+                "page0.png" => Ok(Cursor::new(pages[0].clone())),
+                "page1.png" => Ok(Cursor::new(pages[1].clone())),
+                _ => Err(io::ErrorKind::NotFound.into()),
+            },
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let font = font();
+        for format in [BmFontFormat::Text, BmFontFormat::Xml] {
+            let mut descriptor = Vec::new();
+            write(&font, format, &["page0.png", "page1.png"], &mut descriptor)
+                .unwrap();
+            let read = read_with_pages(&descriptor, &font, None).unwrap();
+
+            let (a, b) = (&font.variants[0], &read.variants[0]);
+            assert_eq!(b.name, a.name);
+            assert_eq!(b.image_type, a.image_type);
+            // artery-font types don't implement PartialEq
+            assert_eq!(format!("{:?}", b.metrics), format!("{:?}", a.metrics));
+            assert_eq!(format!("{:?}", b.glyphs), format!("{:?}", a.glyphs));
+            assert_eq!(
+                format!("{:?}", b.kern_pairs),
+                format!("{:?}", a.kern_pairs)
+            );
+            for (a, b) in font.images.iter().zip(&read.images) {
+                assert_eq!(a.data, b.data);
+            }
+        }
+    }
+
+    #[test]
+    fn binary_descriptors_are_read_but_not_written() {
+        let font = font();
+        let err = write(&font, BmFontFormat::Binary, &[], Vec::new());
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::Unsupported);
+
+        let block = |kind: u8, data: &[u8]| {
+            let mut block = vec![kind];
+            block.extend((data.len() as u32).to_le_bytes());
+            block.extend(data);
+            block
+        };
+        let mut info = (-32i16).to_le_bytes().to_vec();
+        info.extend([0; 12]);
+        info.extend(b"Binary\0");
+        let mut common = Vec::new();
+        for value in [40u16, 24, 16, 12, 1] {
+            common.extend(value.to_le_bytes());
+        }
+        common.extend([0; 5]);
+        let mut char = 65u32.to_le_bytes().to_vec();
+        for value in [1i16, 2, 7, 8, -2, 4, 16] {
+            char.extend(value.to_le_bytes());
+        }
+        char.extend([0, 15]);
+        let mut kerning = 65u32.to_le_bytes().to_vec();
+        kerning.extend(66u32.to_le_bytes());
+        kerning.extend((-2i16).to_le_bytes());
+
+        let descriptor = [
+            b"BMF\x03".to_vec(),
+            block(1, &info),
+            block(2, &common),
+            block(3, b"page0.png\0"),
+            block(4, &char),
+            block(5, &kerning),
+        ]
+        .concat();
+        let read = read_with_pages(&descriptor, &font, None).unwrap();
+
+        let variant = &read.variants[0];
+        assert_eq!(variant.name, "Binary");
+        // Without a distanceField line, the default field is used
+        assert_eq!(variant.image_type, ImageType::Sdf);
+        assert_eq!(variant.metrics.font_size, 32.0);
+        let a = &font.variants[0].glyphs[0];
+        let b = &variant.glyphs[0];
+        assert_eq!(format!("{:?}", b), format!("{:?}", a));
+        assert_eq!(variant.kern_pairs[0].advance.horizontal, -0.0625);
+    }
+
+    #[test]
+    fn positive_sizes_use_the_given_em_size() {
+        let font = font();
+        let descriptor = "info face=\"Cell\" size=40\n\
+            common lineHeight=40 base=30 scaleW=16 scaleH=12 pages=1\n\
+            page id=0 file=\"page0.png\"\n\
+            char id=65 x=0 y=0 width=4 height=4 xoffset=0 yoffset=0 \
+            xadvance=20 page=0 chnl=15\n";
+
+        let estimated =
+            read_with_pages(descriptor.as_bytes(), &font, None).unwrap();
+        // The part of the cell above the baseline
+        assert_eq!(estimated.variants[0].metrics.font_size, 30.0);
+
+        let given =
+            read_with_pages(descriptor.as_bytes(), &font, Some(32.0)).unwrap();
+        let variant = &given.variants[0];
+        assert_eq!(variant.metrics.font_size, 32.0);
+        assert_eq!(variant.glyphs[0].advance.horizontal, 0.625);
+    }
+
+    #[test]
+    fn the_most_negative_size_doesnt_overflow() {
+        let descriptor = "info size=-2147483648\n\
+            common lineHeight=40 base=30 pages=0\n";
+        let font = read_with_pages(descriptor.as_bytes(), &font(), None);
+        assert_eq!(font.unwrap().variants[0].metrics.font_size, 2147483648.0);
+    }
+
+    #[test]
+    fn chars_need_their_page() {
+        let descriptor = "common lineHeight=40 base=30 pages=1\n\
+            char id=65 x=0 y=0 width=4 height=4 page=3\n";
+        assert!(matches!(
+            read_with_pages(descriptor.as_bytes(), &font(), None),
+            Err(BmFontError::Parse(_))
+        ));
+    }
+}
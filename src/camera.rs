@@ -1,9 +1,10 @@
 use nalgebra::{Matrix4, Point3, Vector3};
-use winit::event::{
-    DeviceEvent, KeyboardInput, MouseScrollDelta, VirtualKeyCode,
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{DeviceEvent, KeyboardInput, MouseScrollDelta, VirtualKeyCode},
 };
 
-use crate::Graphics;
+use crate::{util::Globals, Graphics};
 
 #[rustfmt::skip]
 const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
@@ -21,6 +22,7 @@ pub struct Camera {
     pub fov: f32,
     near: f32,
     far: f32,
+    size: PhysicalSize<u32>,
     pub controller: CameraController,
 }
 
@@ -36,6 +38,10 @@ impl Camera {
             fov: 60.,
             near: 0.01,
             far: 100.0,
+            size: PhysicalSize::new(
+                graphics.config.width,
+                graphics.config.height,
+            ),
             controller,
         }
     }
@@ -56,9 +62,31 @@ impl Camera {
         OPENGL_TO_WGPU_MATRIX * projection * view
     }
 
+    /// Contents of the matrix buffer, with the camera axes billboards face
+    /// the camera with.
+    pub fn globals(&mut self) -> Globals {
+        let forward = self.target.coords.normalize();
+        let right = forward.cross(&self.up).normalize();
+        let up = right.cross(&forward);
+        Globals {
+            matrix: self.update_global_matrix().into(),
+            eye: self.eye.to_homogeneous().into(),
+            right: right.push(0.0).into(),
+            up: up.push(0.0).into(),
+            viewport: [
+                self.size.width as f32,
+                self.size.height as f32,
+                0.0,
+                0.0,
+            ],
+        }
+    }
+
     pub fn resize(&mut self, graphics: &Graphics) {
         self.aspect =
             graphics.config.width as f32 / graphics.config.height as f32;
+        self.size =
+            PhysicalSize::new(graphics.config.width, graphics.config.height);
     }
 
     pub fn update(&mut self) {
@@ -73,10 +101,10 @@ impl Camera {
         );
         let target =
             Vector3::new(self.target.x, 0.0, self.target.z).normalize();
-        self.eye += &target
+        self.eye += target
             * self.controller.speed
             * (self.controller.forward - self.controller.backward);
-        self.eye += &target.cross(&self.up)
+        self.eye += target.cross(&self.up)
             * self.controller.speed
             * (self.controller.right - self.controller.left);
         self.eye += Vector3::new(0.0, 1.0, 0.0)
@@ -89,9 +117,91 @@ impl Camera {
     }
 }
 
+/// Orthographic projection for text in screen space, like UI text, which
+/// doesn't move with the 3D camera. Units are logical pixels with the
+/// origin at the top left corner of the window. y points up like in the
+/// 3D scene, so text below the top edge has negative y.
+pub struct ScreenProjection {
+    size: PhysicalSize<u32>,
+    scale_factor: f64,
+}
+
+impl ScreenProjection {
+    pub fn new(size: PhysicalSize<u32>, scale_factor: f64) -> Self {
+        Self { size, scale_factor }
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.size = size;
+    }
+
+    /// Sets the ratio of physical to logical pixels, when the window
+    /// moves to a display with another DPI.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// Physical pixels per unit, to snap text to with
+    /// [`crate::text::Text::set_pixel_snap`].
+    pub fn pixels_per_unit(&self) -> f32 {
+        self.scale_factor as f32
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let size = self.size.to_logical::<f32>(self.scale_factor);
+        let projection = Matrix4::new_orthographic(
+            0.0,
+            size.width,
+            -size.height,
+            0.0,
+            -1.0,
+            1.0,
+        );
+        OPENGL_TO_WGPU_MATRIX * projection
+    }
+
+    /// Contents of the matrix buffer. The screen is seen from the front,
+    /// so billboards don't turn.
+    pub fn globals(&self) -> Globals {
+        Globals {
+            matrix: self.matrix().into(),
+            eye: [0.0, 0.0, 1.0, 1.0],
+            right: [1.0, 0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0, 0.0],
+            viewport: [
+                self.size.width as f32,
+                self.size.height as f32,
+                0.0,
+                0.0,
+            ],
+        }
+    }
+}
+
+/// Projects `point` with the global `matrix` to physical pixels of a window
+/// of `size`. Returns `None` for points behind the camera.
+pub fn world_to_window(
+    matrix: &Matrix4<f32>,
+    point: Point3<f32>,
+    size: PhysicalSize<u32>,
+) -> Option<PhysicalPosition<f32>> {
+    let clip = matrix * point.to_homogeneous();
+    if clip.w <= 0.0 {
+        return None;
+    }
+    let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+    Some(PhysicalPosition::new(
+        (x + 1.0) * 0.5 * size.width as f32,
+        (1.0 - y) * 0.5 * size.height as f32,
+    ))
+}
+
 pub struct CameraController {
     speed: f32,
-    sensitivity: f64,
     forward: f32,
     backward: f32,
     left: f32,
@@ -103,11 +213,16 @@ pub struct CameraController {
     fov_delta: f32,
 }
 
+impl Default for CameraController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CameraController {
     pub fn new() -> Self {
         CameraController {
             speed: 0.08,
-            sensitivity: 0.1,
             forward: 0.0,
             backward: 0.0,
             left: 0.0,
@@ -122,22 +237,6 @@ impl CameraController {
 
     pub fn process_input(&mut self, event: &winit::event::DeviceEvent) {
         match event {
-            /*DeviceEvent::MouseMotion { delta } => {
-                self.yaw += (delta.0 * self.sensitivity) as f32;
-                self.pitch -= (delta.1 * self.sensitivity) as f32;
-
-                if self.pitch > 89.0 {
-                    self.pitch = 89.0;
-                } else if self.pitch < -89.0 {
-                    self.pitch = -89.0;
-                }
-
-                if self.yaw > 360.0 {
-                    self.yaw = 0.0;
-                } else if self.yaw < 0.0 {
-                    self.yaw = 360.0;
-                }
-            }*/
             DeviceEvent::MouseWheel { delta } => {
                 self.fov_delta = match delta {
                     MouseScrollDelta::LineDelta(_, scroll) => *scroll,
@@ -153,12 +252,12 @@ impl CameraController {
                 virtual_keycode,
                 ..
             }) => {
-                let value: f32;
-                if *state == winit::event::ElementState::Pressed {
-                    value = 1.
-                } else {
-                    value = 0.;
-                }
+                let value: f32 =
+                    if *state == winit::event::ElementState::Pressed {
+                        1.
+                    } else {
+                        0.
+                    };
                 match virtual_keycode.unwrap() {
                     VirtualKeyCode::Space => {
                         self.up = value;
@@ -0,0 +1,38 @@
+//! Blinking of text carets.
+
+use std::time::{Duration, Instant};
+
+/// Whether a caret is shown. It blinks while nothing happens and stays on
+/// while the text is edited or the caret moved.
+pub struct Blink {
+    /// How long the caret stays on, and then off.
+    interval: Duration,
+    since: Instant,
+}
+
+impl Default for Blink {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(530))
+    }
+}
+
+impl Blink {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            since: Instant::now(),
+        }
+    }
+
+    /// Shows the caret and starts blinking over, call on edits and caret
+    /// moves.
+    pub fn reset(&mut self) {
+        self.since = Instant::now();
+    }
+
+    pub fn visible(&self) -> bool {
+        let elapsed = self.since.elapsed().as_secs_f64();
+        let phase = (elapsed / self.interval.as_secs_f64()) as u64;
+        phase.is_multiple_of(2)
+    }
+}
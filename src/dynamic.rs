@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+
+use artery_font::Rect;
+use ttf_parser::{Face, FaceParsingError, GlyphId};
+
+use crate::{
+    msdf::GlyphBitmap,
+    packer::{Bin, Heuristic, MaxRectsBin},
+    shaping::LineMetrics,
+    text::{Glyph, GlyphKey},
+    upload::{self, Region},
+    Graphics,
+};
+
+pub struct DynamicAtlasSettings {
+    /// Width and height of a single atlas page in pixels.
+    pub page_size: u32,
+    /// Number of pages, i.e. layers of the atlas texture array.
+    pub pages: u32,
+    /// Size of the rasterized glyphs in pixels per em.
+    pub px_per_em: f32,
+    /// Distance range in pixels, passed on to the shaders with the atlas.
+    pub distance_range: f32,
+}
+
+impl Default for DynamicAtlasSettings {
+    fn default() -> Self {
+        Self {
+            page_size: 1024,
+            pages: 2,
+            px_per_em: 32.0,
+            distance_range: 6.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DynamicAtlasError {
+    Font(FaceParsingError),
+    /// No room is left, and every glyph was used in the current frame.
+    Full,
+    /// The glyph with this index doesn't fit on a page even when rasterized
+    /// smaller, the distance range alone takes up the page.
+    GlyphTooBig(u16),
+}
+
+impl std::fmt::Display for DynamicAtlasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DynamicAtlasError::Font(err) => write!(f, "Font Error: {}", err),
+            DynamicAtlasError::Full => {
+                write!(f, "The dynamic atlas has no evictable glyphs left")
+            }
+            DynamicAtlasError::GlyphTooBig(id) => {
+                write!(f, "Glyph {} doesn't fit on a dynamic atlas page", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DynamicAtlasError {}
+
+/// Place of a glyph in the atlas.
+struct Entry {
+    page: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    last_used: u64,
+}
+
+/// Glyphs in the atlas, packed by their size and evicted by when they were
+/// last used.
+struct LruGlyphs {
+    page_size: u32,
+    bins: Vec<MaxRectsBin>,
+    /// Number of glyphs on each page.
+    counts: Vec<usize>,
+    entries: HashMap<GlyphKey, Entry>,
+    frame: u64,
+}
+
+impl LruGlyphs {
+    fn new(page_size: u32, pages: u32) -> Self {
+        Self {
+            page_size,
+            bins: (0..pages).map(|_| empty_bin(page_size)).collect(),
+            counts: vec![0; pages as usize],
+            entries: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    /// Marks the glyph of `key` as used, returns `false` if the glyph isn't
+    /// in the atlas.
+    fn touch(&mut self, key: GlyphKey) -> bool {
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                entry.last_used = self.frame;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Finds free space for a `width` x `height` glyph of `key`, evicting
+    /// the least recently used glyphs from `glyphs` until it fits and adding
+    /// their keys to `evicted`. Returns the page and position of the glyph.
+    fn allocate(
+        &mut self,
+        key: GlyphKey,
+        width: u32,
+        height: u32,
+        glyphs: &mut HashMap<GlyphKey, Glyph>,
+        evicted: &mut Vec<GlyphKey>,
+    ) -> Result<(usize, u32, u32), DynamicAtlasError> {
+        loop {
+            let free = self.bins.iter_mut().enumerate().find_map(|(i, bin)| {
+                bin.insert(width + PADDING, height + PADDING)
+                    .map(|(x, y)| (i, x + PADDING, y + PADDING))
+            });
+            if let Some((page, x, y)) = free {
+                self.counts[page] += 1;
+                self.entries.insert(
+                    key,
+                    Entry {
+                        page,
+                        x,
+                        y,
+                        width,
+                        height,
+                        last_used: self.frame,
+                    },
+                );
+                return Ok((page, x, y));
+            }
+            evicted.push(self.evict(glyphs)?);
+        }
+    }
+
+    /// Removes the least recently used glyph not used in the current frame,
+    /// returning its key.
+    fn evict(
+        &mut self,
+        glyphs: &mut HashMap<GlyphKey, Glyph>,
+    ) -> Result<GlyphKey, DynamicAtlasError> {
+        let frame = self.frame;
+        let key = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.last_used < frame)
+            .min_by_key(|(_, e)| e.last_used)
+            .map(|(&key, _)| key)
+            .ok_or(DynamicAtlasError::Full)?;
+        let entry = self.entries.remove(&key).unwrap();
+        glyphs.remove(&key);
+
+        self.counts[entry.page] -= 1;
+        // Emptied pages start over, which is cheaper than merging the last
+        // glyph's space back.
+        if self.counts[entry.page] == 0 {
+            self.bins[entry.page] = empty_bin(self.page_size);
+        } else {
+            self.bins[entry.page].free(
+                entry.x - PADDING,
+                entry.y - PADDING,
+                entry.width + PADDING,
+                entry.height + PADDING,
+            );
+        }
+        Ok(key)
+    }
+}
+
+/// Gap in pixels between glyphs and around the page border.
+const PADDING: u32 = 1;
+
+/// Bin of a page, shifted by the padding so glyphs keep it to the left and
+/// bottom border as well, like [`crate::packer::pack`] does.
+fn empty_bin(page_size: u32) -> MaxRectsBin {
+    MaxRectsBin::new(
+        page_size - PADDING,
+        page_size - PADDING,
+        Heuristic::BestShortSideFit,
+    )
+}
+
+/// Atlas which rasterizes glyphs on demand instead of loading them from a
+/// pre-baked font atlas.
+///
+/// Glyphs are packed by their size with [`MaxRectsBin`]. When no page has
+/// room left, the glyphs which were least recently used get evicted. Glyphs
+/// used since the last [`DynamicAtlas::next_frame`] are never evicted.
+///
+/// Evicted glyphs are removed from the glyph table, but vertex buffers
+/// created before still point at their old place in the atlas, which may
+/// hold another glyph by now. The prepare functions return the keys of the
+/// evicted glyphs, buffers of text using any of them have to be recreated
+/// once its glyphs are prepared again.
+pub struct DynamicAtlas {
+    font_data: Vec<u8>,
+    metrics: LineMetrics,
+    settings: DynamicAtlasSettings,
+    lru: LruGlyphs,
+}
+
+impl DynamicAtlas {
+    pub fn new(
+        font_data: Vec<u8>,
+        settings: DynamicAtlasSettings,
+    ) -> Result<Self, DynamicAtlasError> {
+        let face =
+            Face::from_slice(&font_data, 0).map_err(DynamicAtlasError::Font)?;
+        let metrics = LineMetrics::from_face(&face);
+        let lru = LruGlyphs::new(settings.page_size, settings.pages);
+
+        Ok(Self {
+            font_data,
+            metrics,
+            settings,
+            lru,
+        })
+    }
+
+    pub fn texture_size(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.settings.page_size,
+            height: self.settings.page_size,
+            depth_or_array_layers: self.settings.pages,
+        }
+    }
+
+    pub fn metrics(&self) -> LineMetrics {
+        self.metrics
+    }
+
+    /// Marks the start of a new frame. Glyphs not used since can be evicted.
+    pub fn next_frame(&mut self) {
+        self.lru.frame += 1;
+    }
+
+    /// Makes sure every character of `text` has a glyph in `glyphs`,
+    /// rasterizing and uploading the missing ones to `texture`. Returns the
+    /// keys of the glyphs evicted to make room.
+    pub fn prepare(
+        &mut self,
+        gfx: &Graphics,
+        texture: &wgpu::Texture,
+        glyphs: &mut HashMap<GlyphKey, Glyph>,
+        text: &str,
+    ) -> Result<Vec<GlyphKey>, DynamicAtlasError> {
+        self.prepare_keys(
+            gfx,
+            texture,
+            glyphs,
+            text.chars().map(GlyphKey::char),
+        )
+    }
+
+    /// Like [`DynamicAtlas::prepare`] for glyph indices, as produced by
+    /// [`crate::shaping::Shaper`].
+    pub fn prepare_glyphs(
+        &mut self,
+        gfx: &Graphics,
+        texture: &wgpu::Texture,
+        glyphs: &mut HashMap<GlyphKey, Glyph>,
+        glyph_ids: impl IntoIterator<Item = u16>,
+    ) -> Result<Vec<GlyphKey>, DynamicAtlasError> {
+        let keys = glyph_ids.into_iter().map(GlyphKey::Index);
+        self.prepare_keys(gfx, texture, glyphs, keys)
+    }
+
+    /// Like [`DynamicAtlas::prepare`] for glyphs looked up by either key.
+    pub fn prepare_keys(
+        &mut self,
+        gfx: &Graphics,
+        texture: &wgpu::Texture,
+        glyphs: &mut HashMap<GlyphKey, Glyph>,
+        keys: impl IntoIterator<Item = GlyphKey>,
+    ) -> Result<Vec<GlyphKey>, DynamicAtlasError> {
+        let mut evicted = Vec::new();
+        // Glyphs already in the atlas are only marked as used, the font is
+        // parsed when some are missing.
+        let missing: Vec<_> = keys
+            .into_iter()
+            .filter(|&key| !self.lru.touch(key) && !glyphs.contains_key(&key))
+            .collect();
+        if missing.is_empty() {
+            return Ok(evicted);
+        }
+        let face = Face::from_slice(&self.font_data, 0)
+            .map_err(DynamicAtlasError::Font)?;
+        let units_per_em = face.units_per_em() as f32;
+
+        for key in missing {
+            // Keys listed more than once are rasterized the first time
+            if glyphs.contains_key(&key) {
+                continue;
+            }
+
+            let id = match key {
+                GlyphKey::Codepoint(c) => char::from_u32(c)
+                    .and_then(|c| face.glyph_index(c))
+                    .unwrap_or(GlyphId(0)),
+                GlyphKey::Index(id) => GlyphId(id),
+            };
+            let advance_x =
+                face.glyph_hor_advance(id).unwrap_or(0) as f32 / units_per_em;
+            let advance_y =
+                face.glyph_ver_advance(id).unwrap_or(0) as f32 / units_per_em;
+            let glyph = match self.rasterize(&face, id)? {
+                Some(bitmap) => {
+                    let (page, x, y) = self.lru.allocate(
+                        key,
+                        bitmap.width,
+                        bitmap.height,
+                        glyphs,
+                        &mut evicted,
+                    )?;
+                    let atlas_bounds =
+                        self.upload(gfx, texture, &bitmap, page as u32, x, y);
+                    Glyph {
+                        advance_x,
+                        advance_y,
+                        plane_bounds: bitmap.plane_bounds,
+                        atlas_bounds,
+                        page: page as u32,
+                    }
+                }
+                // Glyphs without an outline, like the space, take no space
+                // in the atlas.
+                None => {
+                    let empty = Rect {
+                        left: 0.0,
+                        bottom: 0.0,
+                        right: 0.0,
+                        top: 0.0,
+                    };
+                    Glyph {
+                        advance_x,
+                        advance_y,
+                        plane_bounds: empty,
+                        atlas_bounds: empty,
+                        page: 0,
+                    }
+                }
+            };
+            glyphs.insert(key, glyph);
+        }
+        Ok(evicted)
+    }
+
+    /// Rasterizes glyph `id`, with fewer pixels per em than the settings if
+    /// it wouldn't fit on a page otherwise. The plane bounds stay the same
+    /// in ems, so big glyphs come out blurrier instead of cut off.
+    fn rasterize(
+        &self,
+        face: &Face,
+        id: GlyphId,
+    ) -> Result<Option<GlyphBitmap>, DynamicAtlasError> {
+        let px_per_em = self.settings.px_per_em;
+        let range = self.settings.distance_range;
+        let max = self.settings.page_size - 2 * PADDING;
+        let fits =
+            |bitmap: &GlyphBitmap| bitmap.width <= max && bitmap.height <= max;
+
+        let bitmap = match GlyphBitmap::rasterize(face, id, px_per_em, range) {
+            Some(bitmap) => bitmap,
+            None => return Ok(None),
+        };
+        if fits(&bitmap) {
+            return Ok(Some(bitmap));
+        }
+
+        // The distance range keeps its size in pixels, only the outline
+        // within it shrinks.
+        let border = 2.0 * range;
+        if max as f32 <= border {
+            return Err(DynamicAtlasError::GlyphTooBig(id.0));
+        }
+        let shrink = |size: u32| (max as f32 - border) / (size as f32 - border);
+        let scale = shrink(bitmap.width).min(shrink(bitmap.height));
+        match GlyphBitmap::rasterize(face, id, px_per_em * scale, range) {
+            Some(bitmap) if fits(&bitmap) => Ok(Some(bitmap)),
+            _ => Err(DynamicAtlasError::GlyphTooBig(id.0)),
+        }
+    }
+
+    /// Writes `bitmap` to `x`, `y` of `page`, returning the atlas bounds of
+    /// the glyph.
+    fn upload(
+        &self,
+        gfx: &Graphics,
+        texture: &wgpu::Texture,
+        bitmap: &GlyphBitmap,
+        page: u32,
+        x: u32,
+        y: u32,
+    ) -> Rect {
+        upload::write_region(
+            gfx,
+            texture,
+            wgpu::TextureFormat::Rgba8Unorm,
+            Region {
+                x,
+                y,
+                layer: page,
+                mip_level: 0,
+                width: bitmap.width,
+                height: bitmap.height,
+            },
+            &bitmap.pixels,
+        );
+
+        let page_size = self.settings.page_size as f32;
+        Rect {
+            left: x as f32,
+            bottom: y as f32,
+            right: (x + bitmap.width) as f32,
+            top: (y + bitmap.height) as f32,
+        }
+        .scaled(1.0 / page_size, 1.0 / page_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atlas(page_size: u32, px_per_em: f32) -> DynamicAtlas {
+        let settings = DynamicAtlasSettings {
+            page_size,
+            pages: 1,
+            px_per_em,
+            distance_range: 6.0,
+        };
+        let font_data = include_bytes!("../fonts/monserat.ttf").to_vec();
+        DynamicAtlas::new(font_data, settings).unwrap()
+    }
+
+    #[test]
+    fn oversized_glyphs_are_rasterized_smaller() {
+        let atlas = atlas(64, 128.0);
+        let face = Face::from_slice(&atlas.font_data, 0).unwrap();
+        let id = face.glyph_index('W').unwrap();
+        let full = GlyphBitmap::rasterize(&face, id, 128.0, 6.0).unwrap();
+        assert!(full.width > 62);
+
+        let bitmap = atlas.rasterize(&face, id).unwrap().unwrap();
+        assert!(bitmap.width <= 62 && bitmap.height <= 62);
+        // Still covers the whole glyph
+        let (a, b) = (full.plane_bounds, bitmap.plane_bounds);
+        assert!(b.left <= a.left + 0.01 && b.right >= a.right - 0.01);
+        assert!(b.bottom <= a.bottom + 0.01 && b.top >= a.top - 0.01);
+    }
+
+    #[test]
+    fn glyphs_too_big_for_the_distance_range_fail() {
+        let atlas = atlas(12, 128.0);
+        let face = Face::from_slice(&atlas.font_data, 0).unwrap();
+        let id = face.glyph_index('W').unwrap();
+        assert!(matches!(
+            atlas.rasterize(&face, id),
+            Err(DynamicAtlasError::GlyphTooBig(_))
+        ));
+    }
+
+    #[test]
+    fn glyphs_without_outline_take_no_space() {
+        let atlas = atlas(64, 32.0);
+        let face = Face::from_slice(&atlas.font_data, 0).unwrap();
+        let id = face.glyph_index(' ').unwrap();
+        assert!(atlas.rasterize(&face, id).unwrap().is_none());
+    }
+}
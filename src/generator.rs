@@ -0,0 +1,307 @@
+//! Generates MSDF atlases from TrueType/OpenType fonts.
+//!
+//! The result is an [`ArteryFont`], the same representation the `.arfont`
+//! loader produces, so a generated atlas can be handed straight to
+//! `Requisites::init` or written out by one of the exporters.
+
+use std::collections::HashMap;
+
+use artery_font::{
+    Advance, ArteryFont, CodepointType, FontMetric, FontVariant, Glyph, Image,
+    ImageType, KernPair, MetadataFormat, PixelFormat, Rect,
+};
+use ttf_parser::{kern::Format, Face, FaceParsingError, GlyphId};
+
+use crate::{
+    mipmap,
+    msdf::GlyphBitmap,
+    packer::{self, PackerSettings},
+};
+
+pub struct GeneratorSettings {
+    /// Size of the rasterized glyphs in pixels per em.
+    pub px_per_em: f32,
+    /// Distance range in pixels, stored in the atlas for the shaders.
+    pub distance_range: f32,
+    /// Mip levels the atlas is meant to be sampled with. Raises the packer
+    /// padding so glyphs don't bleed into each other in the smaller levels.
+    pub mip_levels: u32,
+    pub packer: PackerSettings,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        Self {
+            px_per_em: 64.0,
+            distance_range: 6.0,
+            mip_levels: 4,
+            packer: PackerSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GeneratorError {
+    Font(FaceParsingError),
+    /// Glyphs for these codepoints, or glyph indices for indexed atlases,
+    /// didn't fit into the allowed pages.
+    Unplaced(Vec<u32>),
+}
+
+impl std::fmt::Display for GeneratorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeneratorError::Font(err) => write!(f, "Font Error: {}", err),
+            GeneratorError::Unplaced(chars) => write!(
+                f,
+                "{} glyphs didn't fit into the atlas: {:?}",
+                chars.len(),
+                chars
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GeneratorError {}
+
+/// Rasterizes every character of `charset` and packs the glyphs into atlas
+/// pages. Characters missing from the font are skipped.
+pub fn generate(
+    font_data: &[u8],
+    charset: impl IntoIterator<Item = char>,
+    settings: &GeneratorSettings,
+) -> Result<ArteryFont, GeneratorError> {
+    let face = Face::from_slice(font_data, 0).map_err(GeneratorError::Font)?;
+    let chars = charset
+        .into_iter()
+        .filter_map(|c| face.glyph_index(c).map(|id| (c as u32, id)))
+        .collect();
+    generate_glyphs(&face, chars, CodepointType::Unicode, settings)
+}
+
+/// Rasterizes glyphs by their index in the font instead of by character,
+/// which also covers glyphs without a codepoint of their own, like
+/// ligatures and the contextual forms produced by [`crate::shaping`]. The
+/// codepoints of the resulting glyphs are glyph indices.
+pub fn generate_indexed(
+    font_data: &[u8],
+    glyph_ids: impl IntoIterator<Item = u16>,
+    settings: &GeneratorSettings,
+) -> Result<ArteryFont, GeneratorError> {
+    let face = Face::from_slice(font_data, 0).map_err(GeneratorError::Font)?;
+    let glyphs = glyph_ids
+        .into_iter()
+        .filter(|&id| id < face.number_of_glyphs())
+        .map(|id| (id as u32, GlyphId(id)))
+        .collect();
+    generate_glyphs(&face, glyphs, CodepointType::Indexed, settings)
+}
+
+/// `chars` pairs the codepoint each glyph is stored under with its glyph.
+fn generate_glyphs(
+    face: &Face,
+    mut chars: Vec<(u32, GlyphId)>,
+    codepoint_type: CodepointType,
+    settings: &GeneratorSettings,
+) -> Result<ArteryFont, GeneratorError> {
+    let units_per_em = face.units_per_em() as f32;
+    chars.sort_unstable_by_key(|&(c, _)| c);
+    chars.dedup_by_key(|&mut (c, _)| c);
+
+    let bitmaps: Vec<Option<GlyphBitmap>> = chars
+        .iter()
+        .map(|&(_, id)| {
+            GlyphBitmap::rasterize(
+                face,
+                id,
+                settings.px_per_em,
+                settings.distance_range,
+            )
+        })
+        .collect();
+
+    // Glyphs without an outline get a zero sized rectangle, which always
+    // fits, so the indices of `sizes` match `chars`.
+    let sizes: Vec<(u32, u32)> = bitmaps
+        .iter()
+        .map(|b| b.as_ref().map_or((0, 0), |b| (b.width, b.height)))
+        .collect();
+    let packer = PackerSettings {
+        padding: settings
+            .packer
+            .padding
+            .max(mipmap::mip_padding(settings.mip_levels)),
+        ..settings.packer
+    };
+    let packed = packer::pack(&sizes, &packer);
+    if !packed.unplaced.is_empty() {
+        let unplaced = packed.unplaced.iter().map(|&i| chars[i].0).collect();
+        return Err(GeneratorError::Unplaced(unplaced));
+    }
+
+    let mut images: Vec<Image> = packed
+        .pages
+        .iter()
+        .map(|page| Image {
+            flags: 0,
+            width: page.width,
+            height: page.height,
+            channels: 4,
+            pixel_format: PixelFormat::Unsigned8,
+            image_type: ImageType::Msdf,
+            child_images: 0,
+            texture_flags: 0,
+            metadata: String::new(),
+            data: vec![0; 4 * (page.width * page.height) as usize],
+        })
+        .collect();
+
+    let empty = Rect {
+        left: 0.0,
+        bottom: 0.0,
+        right: 0.0,
+        top: 0.0,
+    };
+    let mut glyphs = Vec::with_capacity(chars.len());
+    for (placement, bitmap) in packed.placements.iter().zip(&bitmaps) {
+        let (c, id) = chars[placement.id];
+        let advance = Advance {
+            horizontal: face.glyph_hor_advance(id).unwrap_or(0) as f32
+                / units_per_em,
+            vertical: face.glyph_ver_advance(id).unwrap_or(0) as f32
+                / units_per_em,
+        };
+        let (plane_bounds, image_bounds) = match bitmap {
+            Some(bitmap) => {
+                // Both the bitmap and the image are stored bottom row first,
+                // so placements map directly to image bounds.
+                let image = &mut images[placement.page];
+                let row = 4 * bitmap.width as usize;
+                for (y, src) in bitmap.pixels.chunks_exact(row).enumerate() {
+                    let start = 4
+                        * ((placement.y as usize + y) * image.width as usize
+                            + placement.x as usize);
+                    image.data[start..start + row].copy_from_slice(src);
+                }
+                let image_bounds = Rect {
+                    left: placement.x as f32,
+                    bottom: placement.y as f32,
+                    right: (placement.x + placement.width) as f32,
+                    top: (placement.y + placement.height) as f32,
+                };
+                (bitmap.plane_bounds, image_bounds)
+            }
+            None => (empty, empty),
+        };
+        glyphs.push(Glyph {
+            codepoint: c,
+            image: placement.page as u32,
+            plane_bounds,
+            image_bounds,
+            advance,
+        });
+    }
+
+    let variant = FontVariant {
+        flags: 0,
+        weight: face.weight().to_number() as u32,
+        codepoint_type,
+        image_type: ImageType::Msdf,
+        fallback_variant: 0,
+        fallback_glyph: 0,
+        metrics: metrics(face, settings),
+        name: face_name(face).unwrap_or_default(),
+        metadata: String::new(),
+        glyphs,
+        kern_pairs: kern_pairs(face, &chars),
+    };
+
+    Ok(ArteryFont {
+        metadata_format: MetadataFormat::None,
+        variants: vec![variant],
+        images,
+        appendices: Vec::new(),
+    })
+}
+
+fn metrics(face: &Face, settings: &GeneratorSettings) -> FontMetric {
+    let units_per_em = face.units_per_em() as f32;
+    let ascender = face.ascender() as f32 / units_per_em;
+    let descender = face.descender() as f32 / units_per_em;
+    let line_gap = face.line_gap() as f32 / units_per_em;
+    let underline = face.underline_metrics();
+
+    FontMetric {
+        font_size: settings.px_per_em,
+        distance_range: settings.distance_range,
+        em_size: 1.0,
+        ascender,
+        descender,
+        line_height: ascender - descender + line_gap,
+        underline_y: underline
+            .map_or(0.0, |u| u.position as f32 / units_per_em),
+        underline_thickness: underline
+            .map_or(0.0, |u| u.thickness as f32 / units_per_em),
+    }
+}
+
+fn face_name(face: &Face) -> Option<String> {
+    face.names()
+        .into_iter()
+        .filter(|n| n.name_id == ttf_parser::name_id::FULL_NAME)
+        .find_map(|n| n.to_string())
+}
+
+/// Kerning between the characters of `chars`, taken from the pairs listed
+/// in the `kern` table. Class based `kern` subtables and the pair
+/// adjustments in `GPOS`, where most modern fonts keep their kerning, are
+/// not extracted.
+fn kern_pairs(face: &Face, chars: &[(u32, GlyphId)]) -> Vec<KernPair> {
+    let kern = match face.tables().kern {
+        Some(kern) => kern,
+        None => return Vec::new(),
+    };
+    let units_per_em = face.units_per_em() as f32;
+
+    // A glyph can be stored under several codepoints.
+    let mut codepoints: HashMap<GlyphId, Vec<u32>> = HashMap::new();
+    for &(c, id) in chars {
+        codepoints.entry(id).or_default().push(c);
+    }
+
+    // The first subtable listing a pair wins.
+    let mut values = HashMap::new();
+    for subtable in kern.subtables {
+        if !subtable.horizontal || subtable.variable {
+            continue;
+        }
+        if let Format::Format0(subtable) = subtable.format {
+            for pair in subtable.pairs {
+                values
+                    .entry((pair.left(), pair.right()))
+                    .or_insert(pair.value);
+            }
+        }
+    }
+
+    let mut pairs: Vec<KernPair> = values
+        .into_iter()
+        .filter(|&(_, value)| value != 0)
+        .flat_map(|((left, right), value)| {
+            let lefts = codepoints.get(&left).map_or(&[][..], |c| c);
+            let rights = codepoints.get(&right).map_or(&[][..], |c| c);
+            lefts.iter().flat_map(move |&c1| {
+                rights.iter().map(move |&c2| KernPair {
+                    codepoint1: c1,
+                    codepoint2: c2,
+                    advance: Advance {
+                        horizontal: value as f32 / units_per_em,
+                        vertical: 0.0,
+                    },
+                })
+            })
+        })
+        .collect();
+    pairs.sort_unstable_by_key(|p| (p.codepoint1, p.codepoint2));
+    pairs
+}
@@ -0,0 +1,920 @@
+//! Editable single or multi line text.
+//!
+//! [`TextInput`] keeps a [`Text`] and its [`Layout`] up to date with the
+//! winit events of a window: typed characters, IME commits and keys for
+//! moving the caret, selecting, deleting, the clipboard and undo. Carets
+//! move through the text in logical order. Positions are byte offsets
+//! which always sit on grapheme cluster boundaries.
+//!
+//! While an IME composes text, the preedit string is shown in place of the
+//! selection, underlined, and keys are left to the IME.
+
+use std::{collections::HashMap, ops::Range};
+
+use artery_font::Rect;
+use unicode_segmentation::UnicodeSegmentation;
+use winit::event::{
+    ElementState, Ime, KeyboardInput, ModifiersState, VirtualKeyCode,
+    WindowEvent,
+};
+
+use crate::{
+    caret::Blink,
+    layout::{Affinity, GlyphSource, Layout, Line},
+    text::{Glyph, GlyphKey, Text},
+    Graphics,
+};
+
+/// Where cut and copied text goes. winit has no clipboard access, this
+/// hooks up the system clipboard.
+pub trait Clipboard {
+    fn read(&mut self) -> Option<String>;
+    fn write(&mut self, text: &str);
+}
+
+/// Clipboard within the application.
+#[derive(Debug, Default)]
+pub struct LocalClipboard {
+    content: Option<String>,
+}
+
+impl Clipboard for LocalClipboard {
+    fn read(&mut self) -> Option<String> {
+        self.content.clone()
+    }
+
+    fn write(&mut self, text: &str) {
+        self.content = Some(text.to_owned());
+    }
+}
+
+/// Selection as `(anchor, caret)`: the end which stays and the one which
+/// moves when extending it.
+type Selection = (usize, usize);
+
+/// Replacement of `deleted` at `start` by `inserted`.
+#[derive(Debug)]
+struct Edit {
+    start: usize,
+    deleted: String,
+    inserted: String,
+    before: Selection,
+    after: Selection,
+    /// Typed text, which consecutive typing is merged into.
+    typing: bool,
+}
+
+impl Edit {
+    /// Merges `next` into this edit if it continues typing right after it,
+    /// words are undone one at a time.
+    fn merge(&mut self, next: &Edit) -> bool {
+        let merges = self.typing
+            && next.typing
+            && next.deleted.is_empty()
+            && next.start == self.start + self.inserted.len()
+            && !next.inserted.starts_with(char::is_whitespace);
+        if merges {
+            self.inserted.push_str(&next.inserted);
+            self.after = next.after;
+        }
+        merges
+    }
+}
+
+#[derive(Debug, Default)]
+struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History {
+    fn push(&mut self, edit: Edit) {
+        self.redo.clear();
+        if let Some(last) = self.undo.last_mut() {
+            if last.merge(&edit) {
+                return;
+            }
+        }
+        self.undo.push(edit);
+    }
+}
+
+/// Caret movements, in logical order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    PrevGrapheme,
+    NextGrapheme,
+    PrevWord,
+    NextWord,
+    PrevLine,
+    NextLine,
+    LineStart,
+    LineEnd,
+    TextStart,
+    TextEnd,
+}
+
+/// Text being composed by an IME, from [`Ime::Preedit`].
+#[derive(Debug)]
+struct Preedit {
+    text: String,
+    /// Byte range of the cursor within `text`, the caret is hidden when
+    /// `None`. A non-empty range marks the clause being converted.
+    cursor: Option<(usize, usize)>,
+}
+
+/// Buffers of a [`TextInput`], see [`TextInput::create_buffers`].
+pub struct InputBuffers {
+    pub text: (wgpu::Buffer, u32),
+    pub selection: (wgpu::Buffer, u32),
+    pub caret: (wgpu::Buffer, u32),
+    pub underline: (wgpu::Buffer, u32),
+}
+
+impl InputBuffers {
+    /// Draws the selection behind the text, the caret and the underline of
+    /// the preedit in front of it. The bind group of the atlas has to be
+    /// set.
+    pub fn draw<'a>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        text_pipeline: &'a wgpu::RenderPipeline,
+        solid_pipeline: &'a wgpu::RenderPipeline,
+        caret: bool,
+    ) {
+        let layers = [
+            (&self.selection, solid_pipeline, true),
+            (&self.text, text_pipeline, true),
+            (&self.underline, solid_pipeline, true),
+            (&self.caret, solid_pipeline, caret),
+        ];
+        for ((buffer, count), pipeline, visible) in layers {
+            if visible && *count > 0 {
+                rpass.set_pipeline(pipeline);
+                rpass.set_vertex_buffer(0, buffer.slice(..));
+                rpass.draw(0..4, 0..*count);
+            }
+        }
+    }
+}
+
+pub struct TextInput {
+    text: Text,
+    layout: Layout,
+    anchor: usize,
+    caret: usize,
+    affinity: Affinity,
+    /// Position along the line which moving between lines returns to.
+    goal: Option<f32>,
+    /// Line breaks can be typed.
+    multiline: bool,
+    modifiers: ModifiersState,
+    history: History,
+    clipboard: Box<dyn Clipboard>,
+    blink: Blink,
+    preedit: Option<Preedit>,
+    /// Width of the caret in ems.
+    pub caret_width: f32,
+    pub caret_color: [f32; 4],
+    pub selection_color: [f32; 4],
+    /// Thickness of the preedit underline in ems, the clause being
+    /// converted is underlined twice as thick.
+    pub underline_width: f32,
+    pub underline_color: [f32; 4],
+}
+
+impl TextInput {
+    /// Input holding `text`, with the caret at its end.
+    pub fn new(text: Text, multiline: bool, source: GlyphSource) -> Self {
+        let end = text.text().len();
+        let layout = text.layout(source);
+        Self {
+            text,
+            layout,
+            anchor: end,
+            caret: end,
+            affinity: Affinity::Upstream,
+            goal: None,
+            multiline,
+            modifiers: ModifiersState::empty(),
+            history: History::default(),
+            clipboard: Box::<LocalClipboard>::default(),
+            blink: Blink::default(),
+            preedit: None,
+            caret_width: 0.06,
+            caret_color: [1.0, 1.0, 1.0, 1.0],
+            selection_color: [0.2, 0.4, 0.8, 0.6],
+            underline_width: 0.04,
+            underline_color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    pub fn set_clipboard(&mut self, clipboard: Box<dyn Clipboard>) {
+        self.clipboard = clipboard;
+    }
+
+    pub fn text(&self) -> &str {
+        self.text.text()
+    }
+
+    /// Layout of the text as shown, including the preedit.
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn is_composing(&self) -> bool {
+        self.preedit.is_some()
+    }
+
+    /// Rectangle for [`winit::window::Window::set_ime_position`], the caret
+    /// at the start of the preedit or where it would start. In the space
+    /// of the quads, the window position is its bottom left corner.
+    pub fn ime_rect(&self) -> Rect {
+        let start = self.selection().start;
+        self.text
+            .caret_rect(&self.layout, start, Affinity::Downstream)
+    }
+
+    /// Selected byte range, empty when nothing is selected.
+    pub fn selection(&self) -> Range<usize> {
+        self.anchor.min(self.caret)..self.anchor.max(self.caret)
+    }
+
+    pub fn caret(&self) -> (usize, Affinity) {
+        (self.caret, self.affinity)
+    }
+
+    /// Whether to draw the caret in this frame.
+    pub fn caret_visible(&self) -> bool {
+        self.blink.visible()
+    }
+
+    /// Handles `event`, laying the text out with `source` again when it
+    /// changes. Returns `true` if the text, selection or caret changed and
+    /// the buffers need to be created again.
+    pub fn handle_event(
+        &mut self,
+        event: &WindowEvent,
+        source: GlyphSource,
+    ) -> bool {
+        let changed = match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            }
+            // The IME handles keys while composing.
+            WindowEvent::ReceivedCharacter(_)
+            | WindowEvent::KeyboardInput { .. }
+                if self.is_composing() =>
+            {
+                false
+            }
+            WindowEvent::ReceivedCharacter(c) => self.type_char(*c, source),
+            WindowEvent::Ime(Ime::Preedit(text, cursor)) => {
+                self.compose(text, *cursor, source)
+            }
+            WindowEvent::Ime(Ime::Commit(text)) => {
+                self.compose("", None, source);
+                let text = self.insertable(text);
+                self.replace_selection(&text, true, source);
+                true
+            }
+            WindowEvent::Ime(Ime::Disabled) => self.compose("", None, source),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.press_key(*key, source),
+            _ => false,
+        };
+        if changed {
+            self.blink.reset();
+        }
+        changed
+    }
+
+    /// Creates the buffers of the text, selection, caret and preedit with
+    /// the glyphs the text was laid out with.
+    pub fn create_buffers(
+        &self,
+        gfx: &Graphics,
+        glyphs: &HashMap<GlyphKey, Glyph>,
+    ) -> InputBuffers {
+        let layout = &self.layout;
+        let text = self.text.create_layout_buffer(gfx, glyphs, layout);
+        let (selection, caret, underlines) = match &self.preedit {
+            None => (self.selection(), Some(self.caret), Vec::new()),
+            Some(preedit) => {
+                // The preedit is laid out in place of the selection.
+                let start = self.selection().start;
+                let end = start + preedit.text.len();
+                let mut underlines = vec![(start..end, self.underline_width)];
+                let caret = preedit.cursor.map(|(from, to)| {
+                    if from < to {
+                        let clause = start + from..start + to;
+                        underlines.push((clause, 2.0 * self.underline_width));
+                    }
+                    start + to
+                });
+                (start..start, caret, underlines)
+            }
+        };
+
+        let caret = match caret {
+            Some(caret) => self.text.create_caret_buffer(
+                gfx,
+                layout,
+                caret,
+                self.affinity,
+                self.caret_width,
+                self.caret_color,
+            ),
+            None => create_empty_buffer(gfx),
+        };
+        InputBuffers {
+            text,
+            selection: self.text.create_selection_buffer(
+                gfx,
+                layout,
+                selection,
+                self.selection_color,
+            ),
+            caret,
+            underline: self.text.create_underline_buffer(
+                gfx,
+                layout,
+                &underlines,
+                self.underline_color,
+            ),
+        }
+    }
+
+    /// Shows `text` as the preedit, or stops composing if it is empty.
+    fn compose(
+        &mut self,
+        text: &str,
+        cursor: Option<(usize, usize)>,
+        source: GlyphSource,
+    ) -> bool {
+        if text.is_empty() && self.preedit.is_none() {
+            return false;
+        }
+        self.preedit = (!text.is_empty()).then(|| Preedit {
+            text: text.to_owned(),
+            cursor,
+        });
+        self.relayout(source);
+        true
+    }
+
+    fn type_char(&mut self, c: char, source: GlyphSource) -> bool {
+        // Shortcuts arrive as control characters, AltGr as Ctrl + Alt.
+        let shortcut = self.modifiers.ctrl() && !self.modifiers.alt();
+        let text = match c {
+            '\r' | '\n' if self.multiline => "\n".to_owned(),
+            '\t' => "\t".to_owned(),
+            c if c.is_control() || shortcut => return false,
+            c => c.to_string(),
+        };
+        self.replace_selection(&text, true, source);
+        true
+    }
+
+    /// Filters pasted or committed text like [`TextInput::type_char`] does
+    /// typed characters. Line breaks become spaces in a single line input.
+    fn insertable(&self, text: &str) -> String {
+        text.replace("\r\n", "\n")
+            .chars()
+            .filter_map(|c| match c {
+                '\r' | '\n' if self.multiline => Some('\n'),
+                '\r' | '\n' => Some(' '),
+                '\t' => Some('\t'),
+                c if c.is_control() => None,
+                c => Some(c),
+            })
+            .collect()
+    }
+
+    fn press_key(&mut self, key: VirtualKeyCode, source: GlyphSource) -> bool {
+        let ctrl = self.modifiers.ctrl();
+        let extend = self.modifiers.shift();
+        let vertical = self.layout.vertical;
+        let motion = match key {
+            VirtualKeyCode::Left if vertical => Some(Motion::NextLine),
+            VirtualKeyCode::Right if vertical => Some(Motion::PrevLine),
+            VirtualKeyCode::Up if vertical => Some(Motion::PrevGrapheme),
+            VirtualKeyCode::Down if vertical => Some(Motion::NextGrapheme),
+            VirtualKeyCode::Left if ctrl => Some(Motion::PrevWord),
+            VirtualKeyCode::Right if ctrl => Some(Motion::NextWord),
+            VirtualKeyCode::Left => Some(Motion::PrevGrapheme),
+            VirtualKeyCode::Right => Some(Motion::NextGrapheme),
+            VirtualKeyCode::Up => Some(Motion::PrevLine),
+            VirtualKeyCode::Down => Some(Motion::NextLine),
+            VirtualKeyCode::Home if ctrl => Some(Motion::TextStart),
+            VirtualKeyCode::End if ctrl => Some(Motion::TextEnd),
+            VirtualKeyCode::Home => Some(Motion::LineStart),
+            VirtualKeyCode::End => Some(Motion::LineEnd),
+            _ => None,
+        };
+        if let Some(motion) = motion {
+            self.move_caret(motion, extend);
+            return true;
+        }
+
+        match key {
+            VirtualKeyCode::Back | VirtualKeyCode::Delete => {
+                let motion = match (key, ctrl) {
+                    (VirtualKeyCode::Back, false) => Motion::PrevGrapheme,
+                    (VirtualKeyCode::Back, true) => Motion::PrevWord,
+                    (_, false) => Motion::NextGrapheme,
+                    (_, true) => Motion::NextWord,
+                };
+                self.delete(motion, source);
+            }
+            VirtualKeyCode::A if ctrl => {
+                self.anchor = 0;
+                self.caret = self.text().len();
+            }
+            VirtualKeyCode::C if ctrl => {
+                self.copy();
+                return false;
+            }
+            VirtualKeyCode::X if ctrl => {
+                self.copy();
+                self.replace_selection("", false, source);
+            }
+            VirtualKeyCode::V if ctrl => match self.clipboard.read() {
+                Some(text) => {
+                    let text = self.insertable(&text);
+                    self.replace_selection(&text, false, source)
+                }
+                None => return false,
+            },
+            VirtualKeyCode::Z if ctrl && self.modifiers.shift() => {
+                self.redo(source)
+            }
+            VirtualKeyCode::Z if ctrl => self.undo(source),
+            VirtualKeyCode::Y if ctrl => self.redo(source),
+            _ => return false,
+        }
+        true
+    }
+
+    fn copy(&mut self) {
+        let selection = self.selection();
+        if !selection.is_empty() {
+            self.clipboard.write(&self.text.text()[selection]);
+        }
+    }
+
+    fn move_caret(&mut self, motion: Motion, extend: bool) {
+        let selection = self.selection();
+        let caret = if !extend && !selection.is_empty() {
+            // Collapse the selection towards the movement.
+            match motion {
+                Motion::PrevGrapheme => selection.start,
+                Motion::NextGrapheme => selection.end,
+                _ => self.target(motion),
+            }
+        } else {
+            self.target(motion)
+        };
+
+        match motion {
+            // The hit test picked the affinity.
+            Motion::PrevLine | Motion::NextLine => (),
+            Motion::PrevGrapheme | Motion::PrevWord | Motion::LineStart => {
+                self.affinity = Affinity::Downstream;
+                self.goal = None;
+            }
+            _ => {
+                self.affinity = Affinity::Upstream;
+                self.goal = None;
+            }
+        }
+        self.caret = caret;
+        if !extend {
+            self.anchor = caret;
+        }
+    }
+
+    /// Where `motion` takes the caret.
+    fn target(&mut self, motion: Motion) -> usize {
+        let text = self.text.text();
+        let caret = self.caret;
+        match motion {
+            Motion::PrevGrapheme => text[..caret]
+                .grapheme_indices(true)
+                .next_back()
+                .map_or(0, |(i, _)| i),
+            Motion::NextGrapheme => text[caret..]
+                .graphemes(true)
+                .next()
+                .map_or(caret, |g| caret + g.len()),
+            Motion::PrevWord => text[..caret]
+                .split_word_bound_indices()
+                .rev()
+                .find(|(_, word)| !word.trim().is_empty())
+                .map_or(0, |(i, _)| i),
+            Motion::NextWord => text[caret..]
+                .split_word_bound_indices()
+                .find(|(_, word)| !word.trim().is_empty())
+                .map_or(text.len(), |(i, word)| caret + i + word.len()),
+            Motion::PrevLine => self.line_target(-1),
+            Motion::NextLine => self.line_target(1),
+            Motion::LineStart => self.current_line().range.start,
+            Motion::LineEnd => self.current_line().range.end,
+            Motion::TextStart => 0,
+            Motion::TextEnd => text.len(),
+        }
+    }
+
+    fn current_line(&self) -> &Line {
+        let lines = &self.layout.lines;
+        lines
+            .iter()
+            .find(|l| self.caret <= l.range.end)
+            .unwrap_or_else(|| lines.last().unwrap())
+    }
+
+    /// Caret in the line `delta` lines away, as close to the goal as
+    /// possible. Moving past the first or last line goes to the start or
+    /// end of the text.
+    fn line_target(&mut self, delta: isize) -> usize {
+        let layout = &self.layout;
+        let (x, y) = layout.caret_position(self.caret, self.affinity);
+        let inline = if layout.vertical { -y } else { x };
+        let goal = *self.goal.get_or_insert(inline);
+
+        let current = layout
+            .lines
+            .iter()
+            .position(|l| self.caret <= l.range.end)
+            .unwrap_or(layout.lines.len() - 1);
+        let target = match current.checked_add_signed(delta) {
+            Some(target) if target < layout.lines.len() => target,
+            _ if delta < 0 => return 0,
+            _ => return self.text.text().len(),
+        };
+        let baseline = layout.lines[target].baseline;
+        let (offset, affinity) = if layout.vertical {
+            layout.hit_test(baseline, -goal)
+        } else {
+            layout.hit_test(goal, baseline)
+        };
+        self.affinity = affinity;
+        offset
+    }
+
+    /// Deletes the selection, or from the caret to where `motion` takes it.
+    fn delete(&mut self, motion: Motion, source: GlyphSource) {
+        if self.selection().is_empty() {
+            let target = self.target(motion);
+            self.anchor = target;
+        }
+        self.replace_selection("", false, source);
+    }
+
+    fn replace_selection(
+        &mut self,
+        inserted: &str,
+        typing: bool,
+        source: GlyphSource,
+    ) {
+        let range = self.selection();
+        if range.is_empty() && inserted.is_empty() {
+            return;
+        }
+        let deleted = self.text.text()[range.clone()].to_owned();
+        let before = (self.anchor, self.caret);
+        self.text.replace_range(range.clone(), inserted);
+
+        let caret = range.start + inserted.len();
+        self.history.push(Edit {
+            start: range.start,
+            deleted,
+            inserted: inserted.to_owned(),
+            before,
+            after: (caret, caret),
+            typing,
+        });
+        self.select((caret, caret), source);
+    }
+
+    fn undo(&mut self, source: GlyphSource) {
+        if let Some(edit) = self.history.undo.pop() {
+            let range = edit.start..edit.start + edit.inserted.len();
+            self.text.replace_range(range, &edit.deleted);
+            self.select(edit.before, source);
+            self.history.redo.push(edit);
+        }
+    }
+
+    fn redo(&mut self, source: GlyphSource) {
+        if let Some(edit) = self.history.redo.pop() {
+            let range = edit.start..edit.start + edit.deleted.len();
+            self.text.replace_range(range, &edit.inserted);
+            self.select(edit.after, source);
+            self.history.undo.push(edit);
+        }
+    }
+
+    /// Sets the selection after the text changed and lays it out again.
+    fn select(&mut self, (anchor, caret): Selection, source: GlyphSource) {
+        self.anchor = anchor;
+        self.caret = caret;
+        self.affinity = Affinity::Upstream;
+        self.goal = None;
+        self.relayout(source);
+    }
+
+    /// Lays out the text, with the preedit in place of the selection.
+    fn relayout(&mut self, source: GlyphSource) {
+        self.layout = match &self.preedit {
+            Some(preedit) => {
+                let mut shown = self.text.text().to_owned();
+                shown.replace_range(self.selection(), &preedit.text);
+                Layout::new(&shown, source, self.text.settings())
+            }
+            None => self.text.layout(source),
+        };
+    }
+}
+
+fn create_empty_buffer(gfx: &Graphics) -> (wgpu::Buffer, u32) {
+    let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Empty Buffer"),
+        size: 0,
+        usage: wgpu::BufferUsages::VERTEX,
+        mapped_at_creation: false,
+    });
+    (buffer, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shaping::LineMetrics;
+
+    const METRICS: LineMetrics = LineMetrics {
+        ascender: 0.8,
+        descender: -0.2,
+        line_height: 1.2,
+        underline_y: -0.1,
+        underline_thickness: 0.05,
+        strikeout_y: 0.3,
+        strikeout_thickness: 0.05,
+    };
+
+    /// Latin letters, the space and a combining acute accent, half an em
+    /// wide.
+    fn glyphs() -> HashMap<GlyphKey, Glyph> {
+        let bounds = Rect {
+            left: 0.05,
+            bottom: 0.0,
+            right: 0.45,
+            top: 0.7,
+        };
+        "abcdefghijklmnopqrstuvwxyz \u{301}"
+            .chars()
+            .map(|c| {
+                let glyph = Glyph {
+                    advance_x: 0.5,
+                    advance_y: 0.0,
+                    plane_bounds: bounds,
+                    atlas_bounds: bounds,
+                    page: 0,
+                };
+                (GlyphKey::char(c), glyph)
+            })
+            .collect()
+    }
+
+    fn source(glyphs: &HashMap<GlyphKey, Glyph>) -> GlyphSource<'_> {
+        GlyphSource::Atlas {
+            glyphs,
+            metrics: METRICS,
+        }
+    }
+
+    fn input(text: &str, multiline: bool, source: GlyphSource) -> TextInput {
+        TextInput::new(Text::new(text, (0.0, 0.0, 0.0)), multiline, source)
+    }
+
+    fn type_str(input: &mut TextInput, text: &str, source: GlyphSource) {
+        for c in text.chars() {
+            let event = WindowEvent::ReceivedCharacter(c);
+            input.handle_event(&event, source);
+        }
+    }
+
+    fn set_modifiers(
+        input: &mut TextInput,
+        modifiers: ModifiersState,
+        source: GlyphSource,
+    ) {
+        let event = WindowEvent::ModifiersChanged(modifiers);
+        input.handle_event(&event, source);
+    }
+
+    #[test]
+    fn typing_inserts_at_the_caret() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("", false, source);
+        type_str(&mut input, "ab", source);
+        assert!(input.press_key(VirtualKeyCode::Left, source));
+        type_str(&mut input, "x", source);
+        assert_eq!(input.text(), "axb");
+        assert_eq!(input.selection(), 2..2);
+        assert_eq!(input.caret(), (2, Affinity::Upstream));
+        assert_eq!(input.layout().glyphs.len(), 3);
+    }
+
+    #[test]
+    fn backspace_and_delete_remove_grapheme_clusters() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("ae\u{301}bc", false, source);
+        input.press_key(VirtualKeyCode::Back, source);
+        assert_eq!(input.text(), "ae\u{301}b");
+        input.press_key(VirtualKeyCode::Left, source);
+        input.press_key(VirtualKeyCode::Back, source);
+        assert_eq!(input.text(), "ab");
+        assert_eq!(input.selection(), 1..1);
+        input.press_key(VirtualKeyCode::Delete, source);
+        assert_eq!(input.text(), "a");
+        input.press_key(VirtualKeyCode::Delete, source);
+        assert_eq!(input.text(), "a");
+    }
+
+    #[test]
+    fn ctrl_backspace_and_delete_remove_words() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("hello big world", false, source);
+        set_modifiers(&mut input, ModifiersState::CTRL, source);
+        input.press_key(VirtualKeyCode::Back, source);
+        assert_eq!(input.text(), "hello big ");
+        input.press_key(VirtualKeyCode::Home, source);
+        input.press_key(VirtualKeyCode::Delete, source);
+        assert_eq!(input.text(), " big ");
+    }
+
+    #[test]
+    fn typing_over_a_selection_replaces_it() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("hello", false, source);
+        set_modifiers(&mut input, ModifiersState::SHIFT, source);
+        input.press_key(VirtualKeyCode::Left, source);
+        input.press_key(VirtualKeyCode::Left, source);
+        assert_eq!(input.selection(), 3..5);
+        set_modifiers(&mut input, ModifiersState::empty(), source);
+        type_str(&mut input, "p", source);
+        assert_eq!(input.text(), "help");
+        assert_eq!(input.selection(), 4..4);
+    }
+
+    #[test]
+    fn undo_and_redo_typing_one_word_at_a_time() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("", false, source);
+        type_str(&mut input, "ab cd", source);
+        input.undo(source);
+        assert_eq!(input.text(), "ab");
+        assert_eq!(input.selection(), 2..2);
+        input.undo(source);
+        assert_eq!(input.text(), "");
+        input.undo(source);
+        assert_eq!(input.text(), "");
+        input.redo(source);
+        assert_eq!(input.text(), "ab");
+        input.redo(source);
+        assert_eq!(input.text(), "ab cd");
+        assert_eq!(input.selection(), 5..5);
+    }
+
+    #[test]
+    fn undo_restores_the_selection() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("hello", false, source);
+        set_modifiers(&mut input, ModifiersState::CTRL, source);
+        input.press_key(VirtualKeyCode::A, source);
+        set_modifiers(&mut input, ModifiersState::empty(), source);
+        type_str(&mut input, "x", source);
+        assert_eq!(input.text(), "x");
+        input.undo(source);
+        assert_eq!(input.text(), "hello");
+        assert_eq!(input.selection(), 0..5);
+        assert_eq!(input.layout().glyphs.len(), 5);
+    }
+
+    #[test]
+    fn editing_after_undo_drops_the_redo() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("", false, source);
+        type_str(&mut input, "ab", source);
+        input.undo(source);
+        type_str(&mut input, "c", source);
+        input.redo(source);
+        assert_eq!(input.text(), "c");
+    }
+
+    #[test]
+    fn deletions_are_undone_separately() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("", false, source);
+        type_str(&mut input, "abc", source);
+        input.press_key(VirtualKeyCode::Back, source);
+        input.press_key(VirtualKeyCode::Back, source);
+        assert_eq!(input.text(), "a");
+        input.undo(source);
+        assert_eq!(input.text(), "ab");
+        input.undo(source);
+        assert_eq!(input.text(), "abc");
+        input.undo(source);
+        assert_eq!(input.text(), "");
+    }
+
+    #[test]
+    fn cut_and_paste_through_the_clipboard() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("hello", false, source);
+        set_modifiers(&mut input, ModifiersState::CTRL, source);
+        input.press_key(VirtualKeyCode::A, source);
+        assert!(input.press_key(VirtualKeyCode::X, source));
+        assert_eq!(input.text(), "");
+        assert!(input.press_key(VirtualKeyCode::V, source));
+        assert!(input.press_key(VirtualKeyCode::V, source));
+        assert_eq!(input.text(), "hellohello");
+        input.press_key(VirtualKeyCode::Z, source);
+        assert_eq!(input.text(), "hello");
+        input.press_key(VirtualKeyCode::Y, source);
+        assert_eq!(input.text(), "hellohello");
+    }
+
+    #[test]
+    fn line_breaks_only_go_into_multiline_inputs() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut single = input("", false, source);
+        assert!(!single.type_char('\r', source));
+        assert!(!single.type_char('\u{8}', source));
+        assert_eq!(single.text(), "");
+        assert_eq!(single.insertable("a\r\nb\rc\u{7}\td"), "a b c\td");
+
+        let mut multi = input("", true, source);
+        assert!(multi.type_char('\r', source));
+        assert_eq!(multi.text(), "\n");
+        assert_eq!(multi.insertable("a\r\nb\rc"), "a\nb\nc");
+    }
+
+    #[test]
+    fn shortcuts_are_not_typed() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("", false, source);
+        set_modifiers(&mut input, ModifiersState::CTRL, source);
+        assert!(!input.type_char('a', source));
+        // AltGr arrives as Ctrl + Alt.
+        set_modifiers(
+            &mut input,
+            ModifiersState::CTRL | ModifiersState::ALT,
+            source,
+        );
+        assert!(input.type_char('a', source));
+        assert_eq!(input.text(), "a");
+    }
+
+    #[test]
+    fn ime_commits_replace_the_selection() {
+        let glyphs = glyphs();
+        let source = source(&glyphs);
+        let mut input = input("ab", false, source);
+        let preedit =
+            WindowEvent::Ime(Ime::Preedit("xy".to_owned(), Some((2, 2))));
+        assert!(input.handle_event(&preedit, source));
+        assert!(input.is_composing());
+        assert_eq!(input.text(), "ab");
+        assert_eq!(input.layout().glyphs.len(), 4);
+        let typed = WindowEvent::ReceivedCharacter('z');
+        assert!(!input.handle_event(&typed, source));
+
+        let commit = WindowEvent::Ime(Ime::Commit("xy".to_owned()));
+        assert!(input.handle_event(&commit, source));
+        assert!(!input.is_composing());
+        assert_eq!(input.text(), "abxy");
+        input.undo(source);
+        assert_eq!(input.text(), "ab");
+    }
+}
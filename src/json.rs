@@ -0,0 +1,609 @@
+//! JSON atlas layout in the format written by msdf-atlas-gen's `-json`
+//! option. The atlas image is stored separately, see [`crate::arfont::write_png`].
+
+use std::io::{self, Read, Write};
+
+use artery_font::{
+    Advance, ArteryFont, CodepointType, FontMetric, FontVariant, Glyph, Image,
+    ImageType, KernPair, MetadataFormat, Rect,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::arfont;
+
+/// Whether atlas and plane bounds grow upwards or downwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum YOrigin {
+    Bottom,
+    Top,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonFont {
+    pub atlas: JsonAtlas,
+    pub metrics: JsonMetrics,
+    pub glyphs: Vec<JsonGlyph>,
+    #[serde(default)]
+    pub kerning: Vec<JsonKerning>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonAtlas {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub distance_range: f32,
+    pub size: f32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default = "default_y_origin")]
+    pub y_origin: YOrigin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonMetrics {
+    pub em_size: f32,
+    pub line_height: f32,
+    pub ascender: f32,
+    pub descender: f32,
+    pub underline_y: f32,
+    pub underline_thickness: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonGlyph {
+    /// Set in layouts keyed by codepoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unicode: Option<u32>,
+    /// Glyph index in the font, set in layouts keyed by glyph index.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    pub advance: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plane_bounds: Option<JsonBounds>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atlas_bounds: Option<JsonBounds>,
+    /// Atlas image holding the glyph. Not part of the msdf-atlas-gen
+    /// format, which only supports a single image.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub page: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct JsonBounds {
+    pub left: f32,
+    pub bottom: f32,
+    pub right: f32,
+    pub top: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonKerning {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unicode1: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unicode2: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index1: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index2: Option<u32>,
+    pub advance: f32,
+}
+
+fn default_y_origin() -> YOrigin {
+    YOrigin::Bottom
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// Splits `codepoint` into the `unicode` and `index` fields of the JSON.
+fn json_key(
+    codepoint_type: CodepointType,
+    codepoint: u32,
+) -> (Option<u32>, Option<u32>) {
+    match codepoint_type {
+        CodepointType::Indexed => (None, Some(codepoint)),
+        _ => (Some(codepoint), None),
+    }
+}
+
+impl JsonFont {
+    /// Describes the first variant of `font`.
+    pub fn from_artery(
+        font: &ArteryFont,
+        y_origin: YOrigin,
+    ) -> io::Result<Self> {
+        let variant = arfont::first_variant(font)?;
+        let (width, height) = font
+            .images
+            .iter()
+            .fold((0, 0), |(w, h), i| (i.width.max(w), i.height.max(h)));
+        let m = &variant.metrics;
+        // With the origin at the top, y coordinates in ems point downwards.
+        let flip = match y_origin {
+            YOrigin::Bottom => 1.0,
+            YOrigin::Top => -1.0,
+        };
+
+        Ok(Self {
+            atlas: JsonAtlas {
+                kind: image_type_name(variant.image_type).to_owned(),
+                distance_range: m.distance_range,
+                size: m.font_size,
+                width,
+                height,
+                y_origin,
+            },
+            metrics: JsonMetrics {
+                em_size: m.em_size,
+                line_height: m.line_height,
+                ascender: m.ascender * flip,
+                descender: m.descender * flip,
+                underline_y: m.underline_y * flip,
+                underline_thickness: m.underline_thickness,
+            },
+            glyphs: glyphs(font, variant, y_origin),
+            kerning: variant
+                .kern_pairs
+                .iter()
+                .map(|k| {
+                    let (unicode1, index1) =
+                        json_key(variant.codepoint_type, k.codepoint1);
+                    let (unicode2, index2) =
+                        json_key(variant.codepoint_type, k.codepoint2);
+                    JsonKerning {
+                        unicode1,
+                        unicode2,
+                        index1,
+                        index2,
+                        advance: k.advance.horizontal,
+                    }
+                })
+                .collect(),
+        })
+    }
+}
+
+fn glyphs(
+    font: &ArteryFont,
+    variant: &FontVariant,
+    y_origin: YOrigin,
+) -> Vec<JsonGlyph> {
+    variant
+        .glyphs
+        .iter()
+        .map(|g| {
+            let (plane_bounds, atlas_bounds) = if g.is_drawable() {
+                let (p, a) = (g.plane_bounds, g.image_bounds);
+                let height = font.images[g.image as usize].height as f32;
+                Some(match y_origin {
+                    YOrigin::Bottom => (
+                        JsonBounds {
+                            left: p.left,
+                            bottom: p.bottom,
+                            right: p.right,
+                            top: p.top,
+                        },
+                        JsonBounds {
+                            left: a.left,
+                            bottom: a.bottom,
+                            right: a.right,
+                            top: a.top,
+                        },
+                    ),
+                    YOrigin::Top => (
+                        JsonBounds {
+                            left: p.left,
+                            bottom: -p.bottom,
+                            right: p.right,
+                            top: -p.top,
+                        },
+                        JsonBounds {
+                            left: a.left,
+                            bottom: height - a.bottom,
+                            right: a.right,
+                            top: height - a.top,
+                        },
+                    ),
+                })
+            } else {
+                None
+            }
+            .unzip();
+
+            let (unicode, index) =
+                json_key(variant.codepoint_type, g.codepoint);
+            JsonGlyph {
+                unicode,
+                index,
+                advance: g.advance.horizontal,
+                plane_bounds,
+                atlas_bounds,
+                page: g.image,
+            }
+        })
+        .collect()
+}
+
+fn image_type_from_name(name: &str) -> ImageType {
+    match name {
+        "sdf" => ImageType::Sdf,
+        "psdf" => ImageType::Psdf,
+        "msdf" => ImageType::Msdf,
+        "mtsdf" => ImageType::Mtsdf,
+        _ => ImageType::LinearMask,
+    }
+}
+
+fn image_type_name(image_type: ImageType) -> &'static str {
+    match image_type {
+        ImageType::Sdf => "sdf",
+        ImageType::Psdf => "psdf",
+        ImageType::Msdf => "msdf",
+        ImageType::Mtsdf => "mtsdf",
+        _ => "hardmask",
+    }
+}
+
+/// Writes the layout of `font` as msdf-atlas-gen style JSON.
+pub fn write<W: Write>(
+    font: &ArteryFont,
+    y_origin: YOrigin,
+    writer: W,
+) -> io::Result<()> {
+    serde_json::to_writer_pretty(
+        writer,
+        &JsonFont::from_artery(font, y_origin)?,
+    )
+    .map_err(io::Error::from)
+}
+
+#[derive(Debug)]
+pub enum JsonError {
+    Json(serde_json::Error),
+    Png(png::DecodingError),
+    /// A glyph refers to a page without an image.
+    MissingPage(u32),
+    /// A glyph has neither a codepoint nor a glyph index.
+    MissingCodepoint,
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::Json(err) => write!(f, "JSON Error: {}", err),
+            JsonError::Png(err) => write!(f, "PNG Error: {}", err),
+            JsonError::MissingPage(page) => {
+                write!(f, "No image was given for atlas page {}", page)
+            }
+            JsonError::MissingCodepoint => {
+                write!(f, "A glyph has neither a codepoint nor an index")
+            }
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// Loads an msdf-atlas-gen JSON layout together with its atlas images,
+/// one PNG per page. The result can be passed to `Requisites::init` like a
+/// font loaded from an `.arfont` file.
+pub fn read<R: Read, P: Read>(
+    json: R,
+    pages: impl IntoIterator<Item = P>,
+) -> Result<ArteryFont, JsonError> {
+    let layout: JsonFont =
+        serde_json::from_reader(json).map_err(JsonError::Json)?;
+    let image_type = image_type_from_name(&layout.atlas.kind);
+
+    let images = pages
+        .into_iter()
+        .map(|page| arfont::read_png(page, image_type))
+        .collect::<Result<Vec<Image>, _>>()
+        .map_err(JsonError::Png)?;
+
+    layout.into_artery(images)
+}
+
+impl JsonFont {
+    fn into_artery(self, images: Vec<Image>) -> Result<ArteryFont, JsonError> {
+        let atlas = &self.atlas;
+        // Undo the downward y axis of layouts with the origin at the top.
+        let flip = match atlas.y_origin {
+            YOrigin::Bottom => 1.0,
+            YOrigin::Top => -1.0,
+        };
+
+        let empty = Rect {
+            left: 0.0,
+            bottom: 0.0,
+            right: 0.0,
+            top: 0.0,
+        };
+        // Layouts are keyed by codepoint unless some glyphs only have an
+        // index, like the ones msdf-atlas-gen writes for glyph index input.
+        let codepoint_type = if self.glyphs.iter().all(|g| g.unicode.is_some())
+        {
+            CodepointType::Unicode
+        } else {
+            CodepointType::Indexed
+        };
+        let key =
+            |unicode: Option<u32>, index: Option<u32>| match codepoint_type {
+                CodepointType::Indexed => index,
+                _ => unicode,
+            };
+
+        let mut glyphs = Vec::with_capacity(self.glyphs.len());
+        for g in &self.glyphs {
+            let image = images
+                .get(g.page as usize)
+                .ok_or(JsonError::MissingPage(g.page))?;
+            let height = image.height as f32;
+            let plane_bounds = g.plane_bounds.map_or(empty, |p| Rect {
+                left: p.left,
+                bottom: p.bottom * flip,
+                right: p.right,
+                top: p.top * flip,
+            });
+            let image_bounds =
+                g.atlas_bounds.map_or(empty, |a| match atlas.y_origin {
+                    YOrigin::Bottom => Rect {
+                        left: a.left,
+                        bottom: a.bottom,
+                        right: a.right,
+                        top: a.top,
+                    },
+                    YOrigin::Top => Rect {
+                        left: a.left,
+                        bottom: height - a.bottom,
+                        right: a.right,
+                        top: height - a.top,
+                    },
+                });
+
+            glyphs.push(Glyph {
+                codepoint: key(g.unicode, g.index)
+                    .ok_or(JsonError::MissingCodepoint)?,
+                image: g.page,
+                plane_bounds,
+                image_bounds,
+                advance: Advance {
+                    horizontal: g.advance,
+                    vertical: 0.0,
+                },
+            });
+        }
+
+        let m = &self.metrics;
+        let variant = FontVariant {
+            flags: 0,
+            weight: 0,
+            codepoint_type,
+            image_type: image_type_from_name(&atlas.kind),
+            fallback_variant: 0,
+            fallback_glyph: 0,
+            metrics: FontMetric {
+                font_size: atlas.size,
+                distance_range: atlas.distance_range,
+                em_size: m.em_size,
+                ascender: m.ascender * flip,
+                descender: m.descender * flip,
+                line_height: m.line_height,
+                underline_y: m.underline_y * flip,
+                underline_thickness: m.underline_thickness,
+            },
+            name: String::new(),
+            metadata: String::new(),
+            glyphs,
+            kern_pairs: self
+                .kerning
+                .iter()
+                .filter_map(|k| {
+                    Some(KernPair {
+                        codepoint1: key(k.unicode1, k.index1)?,
+                        codepoint2: key(k.unicode2, k.index2)?,
+                        advance: Advance {
+                            horizontal: k.advance,
+                            vertical: 0.0,
+                        },
+                    })
+                })
+                .collect(),
+        };
+
+        Ok(ArteryFont {
+            metadata_format: MetadataFormat::None,
+            variants: vec![variant],
+            images,
+            appendices: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font(codepoint_type: CodepointType) -> ArteryFont {
+        let rect = |left, bottom, right, top| Rect {
+            left,
+            bottom,
+            right,
+            top,
+        };
+        let glyph = |codepoint, image, plane_bounds, image_bounds| Glyph {
+            codepoint,
+            image,
+            plane_bounds,
+            image_bounds,
+            advance: Advance {
+                horizontal: 0.5,
+                vertical: 0.0,
+            },
+        };
+        let image = |width, height| Image {
+            flags: 0,
+            width,
+            height,
+            channels: 4,
+            pixel_format: artery_font::PixelFormat::Unsigned8,
+            image_type: ImageType::Msdf,
+            child_images: 0,
+            texture_flags: 0,
+            metadata: String::new(),
+            data: (0..width * height * 4).map(|i| i as u8).collect(),
+        };
+        let variant = FontVariant {
+            flags: 0,
+            weight: 0,
+            codepoint_type,
+            image_type: ImageType::Msdf,
+            fallback_variant: 0,
+            fallback_glyph: 0,
+            metrics: FontMetric {
+                font_size: 32.0,
+                distance_range: 4.0,
+                em_size: 1.0,
+                ascender: 0.9,
+                descender: -0.25,
+                line_height: 1.2,
+                underline_y: -0.1,
+                underline_thickness: 0.05,
+            },
+            name: String::new(),
+            metadata: String::new(),
+            glyphs: vec![
+                glyph(65, 0, rect(-0.1, -0.1, 0.6, 0.8), rect(1., 2., 8., 9.)),
+                glyph(66, 1, rect(0.0, -0.2, 0.5, 0.7), rect(0., 0., 5., 9.)),
+                glyph(32, 0, rect(0., 0., 0., 0.), rect(0., 0., 0., 0.)),
+            ],
+            kern_pairs: vec![KernPair {
+                codepoint1: 65,
+                codepoint2: 66,
+                advance: Advance {
+                    horizontal: -0.05,
+                    vertical: 0.0,
+                },
+            }],
+        };
+        ArteryFont {
+            metadata_format: MetadataFormat::None,
+            variants: vec![variant],
+            images: vec![image(16, 12), image(8, 10)],
+            appendices: Vec::new(),
+        }
+    }
+
+    fn pngs(font: &ArteryFont) -> Vec<Vec<u8>> {
+        font.images
+            .iter()
+            .map(|image| {
+                let mut png = Vec::new();
+                arfont::write_png(image, &mut png).unwrap();
+                png
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for codepoint_type in [CodepointType::Unicode, CodepointType::Indexed] {
+            for y_origin in [YOrigin::Bottom, YOrigin::Top] {
+                let font = font(codepoint_type);
+                let mut json = Vec::new();
+                write(&font, y_origin, &mut json).unwrap();
+                let pages = pngs(&font);
+                let read =
+                    read(&json[..], pages.iter().map(|p| &p[..])).unwrap();
+
+                let (a, b) = (&font.variants[0], &read.variants[0]);
+                assert_eq!(b.codepoint_type, codepoint_type);
+                assert_eq!(b.image_type, a.image_type);
+                // artery-font types don't implement PartialEq
+                assert_eq!(
+                    format!("{:?}", b.metrics),
+                    format!("{:?}", a.metrics)
+                );
+                assert_eq!(
+                    format!("{:?}", b.glyphs),
+                    format!("{:?}", a.glyphs)
+                );
+                assert_eq!(
+                    format!("{:?}", b.kern_pairs),
+                    format!("{:?}", a.kern_pairs)
+                );
+                for (a, b) in font.images.iter().zip(&read.images) {
+                    assert_eq!(a.data, b.data);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_layouts_write_indices() {
+        let layout =
+            JsonFont::from_artery(&font(CodepointType::Indexed), YOrigin::Top)
+                .unwrap();
+        assert!(layout.glyphs.iter().all(|g| g.unicode.is_none()));
+        assert_eq!(layout.glyphs[0].index, Some(65));
+        assert_eq!(layout.kerning[0].index2, Some(66));
+    }
+
+    #[test]
+    fn reads_msdf_atlas_gen_layouts() {
+        let json = r#"{
+            "atlas": {"type": "mtsdf", "distanceRange": 2, "size": 40,
+                "width": 16, "height": 12, "yOrigin": "top"},
+            "metrics": {"emSize": 1, "lineHeight": 1.25, "ascender": -0.95,
+                "descender": 0.25, "underlineY": 0.1,
+                "underlineThickness": 0.05},
+            "glyphs": [
+                {"unicode": 32, "advance": 0.25},
+                {"unicode": 65, "advance": 0.5,
+                    "planeBounds": {"left": 0, "bottom": 0.1,
+                        "right": 0.5, "top": -0.7},
+                    "atlasBounds": {"left": 1, "bottom": 10,
+                        "right": 7, "top": 2}}
+            ]
+        }"#;
+        let font = font(CodepointType::Unicode);
+        let pages = pngs(&font);
+        let read = read(json.as_bytes(), pages.iter().map(|p| &p[..])).unwrap();
+
+        let variant = &read.variants[0];
+        assert_eq!(variant.image_type, ImageType::Mtsdf);
+        assert_eq!(variant.metrics.ascender, 0.95);
+        assert_eq!(variant.metrics.descender, -0.25);
+        assert!(variant.kern_pairs.is_empty());
+        let a = variant.glyphs[1];
+        assert_eq!((a.plane_bounds.bottom, a.plane_bounds.top), (-0.1, 0.7));
+        assert_eq!((a.image_bounds.bottom, a.image_bounds.top), (2.0, 10.0));
+        assert!(!variant.glyphs[0].is_drawable());
+    }
+
+    #[test]
+    fn glyphs_need_a_page_and_a_codepoint() {
+        let font = font(CodepointType::Unicode);
+        let mut json = Vec::new();
+        write(&font, YOrigin::Bottom, &mut json).unwrap();
+        let pages = pngs(&font);
+        assert!(matches!(
+            read(&json[..], pages.iter().take(1).map(|p| &p[..])),
+            Err(JsonError::MissingPage(1))
+        ));
+
+        let mut layout = JsonFont::from_artery(&font, YOrigin::Bottom).unwrap();
+        layout.glyphs[0].unicode = None;
+        assert!(matches!(
+            layout.into_artery(font.images.clone()),
+            Err(JsonError::MissingCodepoint)
+        ));
+    }
+}
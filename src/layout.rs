@@ -0,0 +1,1775 @@
+//! Line layout of text.
+//!
+//! Every paragraph becomes a line. The bidi algorithm (UAX #9) splits lines
+//! into runs of a single direction and orders them visually, each run is
+//! then turned into glyphs on its own, see [`GlyphSource`]. Positions are in
+//! ems relative to the text origin, y pointing up, with the baseline of the
+//! first line at 0.
+//!
+//! In vertical text, see [`WritingMode`], paragraphs become columns instead.
+//! The first column is centered on the origin and starts at y = 0, further
+//! columns follow to the left. CJK characters stand upright and advance by
+//! their vertical advance, everything else is turned sideways.
+//!
+//! Tabs reach to the next tab stop, whitespace is either kept as it is or
+//! collapsed, see [`WhiteSpace`]. No-break spaces are never collapsed and
+//! fall back to the space glyph if the atlas has none.
+//!
+//! Lines wider than a maximum width are cut short with an ellipsis, at the
+//! end, start or middle, and lines beyond a maximum count are dropped, see
+//! [`Layout::truncated`].
+//!
+//! Glyphs remember the byte offset of the characters they were made from,
+//! which maps carets and hit tests between the logical order of the text
+//! and the visual order of the glyphs.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use artery_font::Rect;
+use unicode_bidi::{BidiInfo, Level, ParagraphInfo};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    shaping::{LineMetrics, Shaper},
+    text::{Glyph, GlyphKey},
+};
+
+/// Where the glyphs of a run come from.
+#[derive(Clone, Copy)]
+pub enum GlyphSource<'a> {
+    /// Runs are shaped with the OpenType tables of the font, glyphs are
+    /// looked up by [`GlyphKey::Index`].
+    Shaper(&'a Shaper),
+    /// Characters map directly to atlas glyphs by [`GlyphKey::Codepoint`],
+    /// one grapheme cluster at a time. For atlases without the font file.
+    Atlas {
+        glyphs: &'a HashMap<GlyphKey, Glyph>,
+        metrics: LineMetrics,
+    },
+}
+
+impl GlyphSource<'_> {
+    fn metrics(&self) -> LineMetrics {
+        match self {
+            GlyphSource::Shaper(shaper) => shaper.metrics(),
+            GlyphSource::Atlas { metrics, .. } => *metrics,
+        }
+    }
+
+    /// Key and advance of the space glyph, which tabs are drawn with.
+    fn space(&self) -> (GlyphKey, f32) {
+        let glyph = match self {
+            GlyphSource::Shaper(shaper) => {
+                shaper.shape(" ").first().map(|s| (s.key(), s.x_advance))
+            }
+            GlyphSource::Atlas { glyphs, .. } => {
+                let key = GlyphKey::char(' ');
+                glyphs.get(&key).map(|g| (key, g.advance_x))
+            }
+        };
+        glyph.unwrap_or((GlyphKey::char(' '), DEFAULT_SPACE_ADVANCE))
+    }
+
+    /// Glyphs of `range` in visual order.
+    fn run_glyphs(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        rtl: bool,
+    ) -> Vec<RunGlyph> {
+        match self {
+            GlyphSource::Shaper(shaper) => shaper
+                .shape_run(text, range, rtl)
+                .into_iter()
+                .map(|s| RunGlyph {
+                    key: s.key(),
+                    cluster: s.cluster,
+                    advance: s.x_advance,
+                    offset: [s.x_offset, s.y_offset],
+                })
+                .collect(),
+            GlyphSource::Atlas { glyphs, .. } => {
+                let mut clusters: Vec<_> = text[range.clone()]
+                    .grapheme_indices(true)
+                    .map(|(start, grapheme)| {
+                        grapheme_glyphs(glyphs, range.start + start, grapheme)
+                    })
+                    .collect();
+                if rtl {
+                    clusters.reverse();
+                }
+                clusters.into_iter().flatten().collect()
+            }
+        }
+    }
+
+    /// Glyphs of `range` standing upright in a column, top to bottom. Pens
+    /// are on the center line of the column, offsets move the horizontal
+    /// origin of the glyphs below and left of them.
+    fn upright_glyphs(&self, text: &str, range: Range<usize>) -> Vec<RunGlyph> {
+        match self {
+            GlyphSource::Shaper(shaper) => shaper
+                .shape_vertical_run(text, range)
+                .into_iter()
+                .map(|s| RunGlyph {
+                    key: s.key(),
+                    cluster: s.cluster,
+                    advance: -s.y_advance,
+                    offset: [s.x_offset, s.y_offset],
+                })
+                .collect(),
+            GlyphSource::Atlas { glyphs, metrics } => text[range.clone()]
+                .grapheme_indices(true)
+                .flat_map(|(start, grapheme)| {
+                    let mut cluster =
+                        grapheme_glyphs(glyphs, range.start + start, grapheme);
+                    stand_upright(&mut cluster, glyphs, metrics);
+                    cluster
+                })
+                .collect(),
+        }
+    }
+
+    /// Glyphs of an ellipsis at byte `cluster`: the font's ellipsis
+    /// character, or three full stops if it has none.
+    fn ellipsis(&self, cluster: usize) -> Vec<RunGlyph> {
+        let glyph = |(key, advance)| RunGlyph {
+            key,
+            cluster,
+            advance,
+            offset: [0.0, 0.0],
+        };
+        match self {
+            GlyphSource::Shaper(shaper) => {
+                let mut shaped = shaper.shape("\u{2026}");
+                if shaped.iter().any(|s| s.glyph_id == 0) {
+                    shaped = shaper.shape("...");
+                }
+                shaped
+                    .iter()
+                    .map(|s| glyph((s.key(), s.x_advance)))
+                    .collect()
+            }
+            GlyphSource::Atlas { glyphs, .. } => {
+                let ellipsis = GlyphKey::char('\u{2026}');
+                let stop = GlyphKey::char('.');
+                let keys = if glyphs.contains_key(&ellipsis) {
+                    vec![ellipsis]
+                } else {
+                    vec![stop; 3]
+                };
+                keys.into_iter()
+                    .filter_map(|key| glyphs.get(&key).map(|g| (key, g)))
+                    .map(|(key, g)| glyph((key, g.advance_x)))
+                    .collect()
+            }
+        }
+    }
+
+    /// Left and right edge of the outline of the glyph of `key`.
+    fn glyph_extent(&self, key: GlyphKey) -> Option<(f32, f32)> {
+        match (self, key) {
+            (GlyphSource::Shaper(shaper), GlyphKey::Index(id)) => {
+                shaper.glyph_extent(id)
+            }
+            (GlyphSource::Shaper(_), GlyphKey::Codepoint(_)) => None,
+            (GlyphSource::Atlas { glyphs, .. }, key) => glyphs
+                .get(&key)
+                .map(|g| g.plane_bounds)
+                .filter(|b| !b.is_empty())
+                .map(|b| (b.left, b.right)),
+        }
+    }
+}
+
+/// Advance of a space in fonts without a space glyph.
+const DEFAULT_SPACE_ADVANCE: f32 = 0.25;
+
+struct RunGlyph {
+    key: GlyphKey,
+    cluster: usize,
+    advance: f32,
+    offset: [f32; 2],
+}
+
+/// Places the characters of a grapheme cluster starting at byte `cluster`.
+///
+/// Characters without an advance are combining marks. They are centered on
+/// the base glyph and, as fonts draw them for a lowercase base, moved up by
+/// as much as the glyphs below them rise above an "x", or down by as much
+/// as they reach below it. Plane bounds include the distance field padding,
+/// comparing them with those of the "x" cancels it out.
+fn grapheme_glyphs(
+    glyphs: &HashMap<GlyphKey, Glyph>,
+    cluster: usize,
+    grapheme: &str,
+) -> Vec<RunGlyph> {
+    let reference = glyphs.get(&GlyphKey::char('x')).map(|x| x.plane_bounds);
+    let mut result = Vec::new();
+    let mut pen = 0.0;
+    // Horizontal center of the base glyph and the vertical extent of
+    // everything stacked onto it so far.
+    let mut base: Option<(f32, f32, f32)> = None;
+
+    for c in grapheme.chars() {
+        let (key, glyph) = match atlas_glyph(glyphs, c) {
+            Some(glyph) => glyph,
+            None => continue,
+        };
+        let b = glyph.plane_bounds;
+        let mut offset = [0.0, 0.0];
+
+        match &mut base {
+            Some((center, top, bottom))
+                if glyph.advance_x == 0.0 && !b.is_empty() =>
+            {
+                offset[0] = *center - 0.5 * (b.left + b.right) - pen;
+                if let Some(x) = reference {
+                    if b.top + b.bottom < 0.0 {
+                        offset[1] = (*bottom - x.bottom).min(0.0);
+                        *bottom = b.bottom + offset[1];
+                    } else {
+                        offset[1] = (*top - x.top).max(0.0);
+                        *top = b.top + offset[1];
+                    }
+                }
+            }
+            None if !b.is_empty() => {
+                base = Some((pen + 0.5 * (b.left + b.right), b.top, b.bottom));
+            }
+            _ => (),
+        }
+
+        result.push(RunGlyph {
+            key,
+            cluster,
+            advance: glyph.advance_x,
+            offset,
+        });
+        pen += glyph.advance_x;
+    }
+    result
+}
+
+/// Glyph of `c` in the atlas. Tabs and no-break spaces fall back to the
+/// space glyph, atlases rarely have their own.
+fn atlas_glyph(
+    glyphs: &HashMap<GlyphKey, Glyph>,
+    c: char,
+) -> Option<(GlyphKey, &Glyph)> {
+    let key = GlyphKey::char(c);
+    match glyphs.get(&key) {
+        Some(glyph) => Some((key, glyph)),
+        None if c == '\t' || is_no_break_space(c) => {
+            let key = GlyphKey::char(' ');
+            glyphs.get(&key).map(|glyph| (key, glyph))
+        }
+        None => None,
+    }
+}
+
+/// Turns horizontally placed glyphs sideways for a column, see
+/// [`PositionedGlyph::sideways`]. They are centered on the column by the
+/// middle of ascender and descender.
+fn turn_sideways(
+    glyphs: Vec<RunGlyph>,
+    metrics: &LineMetrics,
+) -> Vec<RunGlyph> {
+    let center = 0.5 * (metrics.ascender + metrics.descender);
+    glyphs
+        .into_iter()
+        .map(|g| RunGlyph {
+            offset: [g.offset[1] - center, -g.offset[0]],
+            ..g
+        })
+        .collect()
+}
+
+/// Stands a cluster placed by [`grapheme_glyphs`] upright: centered on the
+/// pen and hanging from it by the ascender. The vertical advance of the
+/// cluster goes onto its last glyph, fonts without vertical metrics advance
+/// by the distance of ascender and descender.
+fn stand_upright(
+    cluster: &mut [RunGlyph],
+    glyphs: &HashMap<GlyphKey, Glyph>,
+    metrics: &LineMetrics,
+) {
+    let width: f32 = cluster.iter().map(|g| g.advance).sum();
+    let advance = cluster
+        .iter()
+        .filter_map(|g| glyphs.get(&g.key))
+        .map(|g| g.advance_y)
+        .find(|&a| a > 0.0)
+        .unwrap_or(metrics.ascender - metrics.descender);
+
+    let mut pen = -0.5 * width;
+    for g in cluster.iter_mut() {
+        g.offset[0] += pen;
+        g.offset[1] -= metrics.ascender;
+        pen += g.advance;
+        g.advance = 0.0;
+    }
+    if let Some(last) = cluster.last_mut() {
+        last.advance = advance;
+    }
+}
+
+/// Whether `c` stands upright in vertical text. Roughly the characters
+/// UAX #50 orients upright: CJK scripts with their punctuation and symbols,
+/// fullwidth forms and emoji.
+fn is_upright(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x11FF
+            | 0x2E80..=0x2FFF
+            | 0x3000..=0x30FF
+            | 0x3100..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7AF
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFFEF
+            | 0x1F000..=0x1FAFF
+            | 0x20000..=0x3FFFF
+    )
+}
+
+/// Splits `range` into runs of upright and sideways characters, the flag
+/// is set for sideways runs. Grapheme clusters take the orientation of
+/// their first character.
+fn orientation_runs(
+    text: &str,
+    range: Range<usize>,
+) -> Vec<(Range<usize>, bool)> {
+    let mut runs: Vec<(Range<usize>, bool)> = Vec::new();
+    for (start, grapheme) in text[range.clone()].grapheme_indices(true) {
+        let start = range.start + start;
+        let end = start + grapheme.len();
+        let sideways = !grapheme.chars().next().is_some_and(is_upright);
+        match runs.last_mut() {
+            Some((run, s)) if *s == sideways => run.end = end,
+            _ => runs.push((start..end, sideways)),
+        }
+    }
+    runs
+}
+
+/// Direction of the paragraphs of a text, which decides the order of the
+/// runs of a line and where lines start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BaseDirection {
+    /// Taken from the first strong character of every paragraph, left to
+    /// right if there is none.
+    #[default]
+    Auto,
+    LeftToRight,
+    RightToLeft,
+}
+
+/// Direction of lines and of the characters within them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WritingMode {
+    #[default]
+    Horizontal,
+    /// Top to bottom columns progressing from right to left, as in
+    /// Japanese. Runs are in logical order, bidi levels are ignored.
+    VerticalRl,
+}
+
+/// What happens to spaces and tabs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhiteSpace {
+    /// Every space is kept and tabs reach to the next tab stop.
+    #[default]
+    Preserve,
+    /// Sequences of spaces and tabs become a single space, and are removed
+    /// at the start and end of lines. Line breaks are kept.
+    Collapse,
+}
+
+/// Where tabs end, measured from the start of the line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TabStops {
+    /// A stop every so many space advances.
+    Spaces(u32),
+    /// Stops at these positions in ems, in increasing order. Tabs after the
+    /// last one advance like a space.
+    Positions(Vec<f32>),
+}
+
+impl Default for TabStops {
+    fn default() -> Self {
+        TabStops::Spaces(4)
+    }
+}
+
+impl TabStops {
+    /// The first stop after `pen`.
+    fn next_stop(&self, pen: f32, space: f32) -> f32 {
+        match self {
+            TabStops::Spaces(spaces) => {
+                let width = *spaces as f32 * space;
+                if width > 0.0 {
+                    ((pen / width).floor() + 1.0) * width
+                } else {
+                    pen
+                }
+            }
+            TabStops::Positions(stops) => stops
+                .iter()
+                .copied()
+                .find(|&stop| stop > pen)
+                .unwrap_or(pen + space),
+        }
+    }
+}
+
+/// Where the ellipsis of a line that is too wide goes, the characters
+/// around it are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Ellipsis {
+    Start,
+    Middle,
+    #[default]
+    End,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LayoutSettings {
+    pub base_direction: BaseDirection,
+    pub writing_mode: WritingMode,
+    pub white_space: WhiteSpace,
+    pub tab_stops: TabStops,
+    /// Extra space after every character in ems, negative to tighten.
+    pub letter_spacing: f32,
+    /// Extra space after every space in ems, on top of the letter spacing.
+    pub word_spacing: f32,
+    /// Width in ems of a cell every character is centered in, instead of
+    /// advancing by its own width. For tabular digits. Upright glyphs in
+    /// vertical text keep their advances.
+    pub monospace: Option<f32>,
+    /// Lines longer than this many ems are cut short, see [`Ellipsis`].
+    pub max_width: Option<f32>,
+    pub ellipsis: Ellipsis,
+    /// Lines after this many are dropped, the last one kept ends in an
+    /// ellipsis.
+    pub max_lines: Option<usize>,
+}
+
+/// Whitespace handling of a layout in progress.
+struct Spacing<'a> {
+    settings: &'a LayoutSettings,
+    /// Key and advance of the space glyph.
+    space: (GlyphKey, f32),
+    /// Byte offsets of the collapsed characters.
+    collapsed: HashSet<usize>,
+    /// Range of the current line drawn as an ellipsis.
+    ellipsis: Option<Range<usize>>,
+}
+
+impl<'a> Spacing<'a> {
+    fn new(settings: &'a LayoutSettings, source: GlyphSource) -> Self {
+        Self {
+            settings,
+            space: source.space(),
+            collapsed: HashSet::new(),
+            ellipsis: None,
+        }
+    }
+
+    fn is_ellipsis(&self, cluster: usize) -> bool {
+        self.ellipsis.as_ref().is_some_and(|e| e.start == cluster)
+    }
+
+    /// Marks the whitespace of the line `range` that
+    /// [`WhiteSpace::Collapse`] removes: all but the first character of a
+    /// sequence, and whole sequences at the start and end.
+    fn collapse_line(&mut self, text: &str, range: Range<usize>) {
+        if self.settings.white_space != WhiteSpace::Collapse {
+            return;
+        }
+        let line = &text[range.clone()];
+        let start = line.len() - line.trim_start_matches(is_collapsible).len();
+        let end = line.trim_end_matches(is_collapsible).len();
+        let mut previous = false;
+        for (i, c) in line.char_indices() {
+            let collapsible = is_collapsible(c);
+            if collapsible && (previous || i < start || i >= end) {
+                self.collapsed.insert(range.start + i);
+            }
+            previous = collapsible;
+        }
+    }
+
+    /// Applies the whitespace and spacing settings to the glyphs of a run
+    /// which starts at `pen`, a cluster at a time. Glyphs of collapsed
+    /// characters are dropped.
+    fn place_run(
+        &self,
+        text: &str,
+        source: GlyphSource,
+        glyphs: Vec<RunGlyph>,
+        mut pen: f32,
+        upright: bool,
+    ) -> Vec<RunGlyph> {
+        let mut placed = Vec::with_capacity(glyphs.len());
+        let mut glyphs = glyphs
+            .into_iter()
+            .filter(|g| {
+                !self.collapsed.contains(&g.cluster)
+                    || self.is_ellipsis(g.cluster)
+            })
+            .peekable();
+        let mut cluster = Vec::new();
+        while let Some(glyph) = glyphs.next() {
+            let start = glyph.cluster;
+            cluster.push(glyph);
+            if glyphs.peek().is_some_and(|next| next.cluster == start) {
+                continue;
+            }
+            self.place_cluster(text, source, &mut cluster, pen, upright);
+            pen += cluster.iter().map(|g| g.advance).sum::<f32>();
+            placed.append(&mut cluster);
+        }
+        placed
+    }
+
+    /// Tabs are drawn as a space reaching from `pen` to the next tab stop,
+    /// or as a single space when whitespace collapses. Other clusters get
+    /// the letter and word spacing and the monospace cell, which puts the
+    /// whole advance onto their last glyph.
+    fn place_cluster(
+        &self,
+        text: &str,
+        source: GlyphSource,
+        cluster: &mut Vec<RunGlyph>,
+        pen: f32,
+        upright: bool,
+    ) {
+        let settings = self.settings;
+        let c = if self.is_ellipsis(cluster[0].cluster) {
+            None
+        } else {
+            text[cluster[0].cluster..].chars().next()
+        };
+        if c == Some('\t') {
+            let (key, space) = self.space;
+            cluster.truncate(1);
+            cluster[0].key = key;
+            cluster[0].advance = space;
+            if settings.white_space == WhiteSpace::Preserve {
+                cluster[0].advance =
+                    settings.tab_stops.next_stop(pen, space) - pen;
+                return;
+            }
+        }
+
+        let width: f32 = cluster.iter().map(|g| g.advance).sum();
+        if width == 0.0 {
+            return;
+        }
+        let mut advance = width + settings.letter_spacing;
+        let mut shift = 0.0;
+        if c.is_some_and(|c| c == '\t' || is_word_separator(c)) {
+            advance += settings.word_spacing;
+        }
+        if let (Some(cell), false) = (settings.monospace, upright) {
+            let center = ink_center(source, cluster).unwrap_or(0.5 * width);
+            shift = 0.5 * cell - center;
+            advance += cell - width;
+        }
+        if advance == width && shift == 0.0 {
+            return;
+        }
+
+        // Pens within the cluster move to its start, the offsets keep the
+        // glyphs where they were.
+        let mut inner = 0.0;
+        for g in cluster.iter_mut() {
+            g.offset[0] += inner + shift;
+            inner += g.advance;
+            g.advance = 0.0;
+        }
+        if let Some(last) = cluster.last_mut() {
+            last.advance = advance;
+        }
+    }
+}
+
+/// Horizontal center of the outlines of a cluster, relative to its start.
+fn ink_center(source: GlyphSource, cluster: &[RunGlyph]) -> Option<f32> {
+    let mut pen = 0.0;
+    let mut extent: Option<(f32, f32)> = None;
+    for g in cluster {
+        if let Some((left, right)) = source.glyph_extent(g.key) {
+            let (left, right) =
+                (pen + g.offset[0] + left, pen + g.offset[0] + right);
+            extent = Some(match extent {
+                Some((l, r)) => (l.min(left), r.max(right)),
+                None => (left, right),
+            });
+        }
+        pen += g.advance;
+    }
+    extent.map(|(left, right)| 0.5 * (left + right))
+}
+
+/// Which character a caret belongs to where two positions share a byte
+/// offset, like at the boundary of two runs of different direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Affinity {
+    /// The character before it.
+    Upstream,
+    /// The character after it.
+    #[default]
+    Downstream,
+}
+
+/// Line drawn along text, see [`Layout::decoration_rects`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecorationLine {
+    Underline,
+    Strikethrough,
+    Overline,
+}
+
+/// A glyph placed on a line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionedGlyph {
+    pub key: GlyphKey,
+    /// Byte offset of the first character of the glyph's cluster.
+    pub cluster: usize,
+    /// Pen position on the baseline.
+    pub x: f32,
+    pub y: f32,
+    pub advance: f32,
+    /// Where the glyph is drawn relative to the pen position, like a mark
+    /// moved onto its base.
+    pub offset: [f32; 2],
+    /// The glyph is turned a quarter clockwise, its baseline running down
+    /// from the pen position plus offset. For Latin text in a column.
+    pub sideways: bool,
+}
+
+/// Characters of a single direction, placed next to each other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    /// Byte range in the source text.
+    pub range: Range<usize>,
+    pub rtl: bool,
+    /// Left edge, or distance of the top edge below the origin in vertical
+    /// text.
+    pub x: f32,
+    pub width: f32,
+    /// Range of [`Layout::glyphs`], in visual order.
+    pub glyphs: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// Byte range in the source text, without the line break.
+    pub range: Range<usize>,
+    /// Runs from left to right.
+    pub runs: Vec<Run>,
+    pub rtl: bool,
+    /// Left edge. Right-to-left lines end at the origin instead of starting
+    /// there. Columns of vertical text start at the origin, their `x` and
+    /// `width` are measured downwards.
+    pub x: f32,
+    pub width: f32,
+    /// Center line of the column in vertical text.
+    pub baseline: f32,
+}
+
+impl Line {
+    /// Where a caret sits on an empty line, or before its first character.
+    fn start_x(&self) -> f32 {
+        if self.rtl {
+            self.x + self.width
+        } else {
+            self.x
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layout {
+    /// Glyphs of all lines, in visual order within each line.
+    pub glyphs: Vec<PositionedGlyph>,
+    pub lines: Vec<Line>,
+    pub metrics: LineMetrics,
+    /// Lines are columns, see [`WritingMode::VerticalRl`].
+    pub vertical: bool,
+    /// Lines were cut short or dropped, see [`LayoutSettings::max_width`]
+    /// and [`LayoutSettings::max_lines`].
+    pub truncated: bool,
+}
+
+/// A paragraph being laid out as a line.
+struct LineText<'a> {
+    text: &'a str,
+    bidi: &'a BidiInfo<'a>,
+    /// `None` for the empty line after a trailing paragraph separator.
+    para: Option<&'a ParagraphInfo>,
+    /// Without the paragraph separator.
+    range: Range<usize>,
+    rtl: bool,
+    vertical: bool,
+}
+
+impl LineText<'_> {
+    /// Runs of `range`, a part of the line, in visual order. They are
+    /// flagged as right to left, or as sideways in vertical text.
+    fn runs(&self, range: Range<usize>) -> Vec<(Range<usize>, bool)> {
+        if range.is_empty() {
+            return Vec::new();
+        }
+        if self.vertical {
+            return orientation_runs(self.text, range);
+        }
+        // Only empty lines have no paragraph.
+        let para = self.para.unwrap();
+        let (levels, runs) = self.bidi.visual_runs(para, range);
+        runs.into_iter()
+            .map(|run| {
+                let rtl = levels[run.start].is_rtl();
+                (run, rtl)
+            })
+            .collect()
+    }
+
+    /// Runs of the line with `hidden` as a run of its own, for the
+    /// ellipsis.
+    fn runs_with_ellipsis(
+        &self,
+        hidden: Range<usize>,
+    ) -> Vec<(Range<usize>, bool)> {
+        let before = self.runs(self.range.start..hidden.start);
+        let after = self.runs(hidden.end..self.range.end);
+        let ellipsis = (hidden, self.rtl || self.vertical);
+        if self.rtl {
+            [after, vec![ellipsis], before].concat()
+        } else {
+            [before, vec![ellipsis], after].concat()
+        }
+    }
+}
+
+/// Range of a line dropped for an ellipsis, such that the remaining
+/// `clusters`, starting bytes and advances in logical order, take at most
+/// `budget`.
+fn hidden_range(
+    clusters: &[(usize, f32)],
+    line: Range<usize>,
+    position: Ellipsis,
+    budget: f32,
+) -> Range<usize> {
+    // Number of clusters fitting into `budget` and their width.
+    let fitting = |clusters: &mut dyn Iterator<Item = &(usize, f32)>,
+                   budget: f32| {
+        let mut width = 0.0;
+        let mut count = 0;
+        for &(_, advance) in clusters {
+            if width + advance > budget {
+                break;
+            }
+            width += advance;
+            count += 1;
+        }
+        (count, width)
+    };
+    let start_of = |i: usize| clusters.get(i).map_or(line.end, |c| c.0);
+
+    match position {
+        Ellipsis::End => {
+            let (kept, _) = fitting(&mut clusters.iter(), budget);
+            start_of(kept)..line.end
+        }
+        Ellipsis::Start => {
+            let (kept, _) = fitting(&mut clusters.iter().rev(), budget);
+            line.start..start_of(clusters.len() - kept)
+        }
+        Ellipsis::Middle => {
+            let (head, width) = fitting(&mut clusters.iter(), 0.5 * budget);
+            let (tail, _) =
+                fitting(&mut clusters[head..].iter().rev(), budget - width);
+            start_of(head)..start_of(clusters.len() - tail)
+        }
+    }
+}
+
+impl Layout {
+    pub fn new(
+        text: &str,
+        source: GlyphSource,
+        settings: &LayoutSettings,
+    ) -> Self {
+        let base_level = match settings.base_direction {
+            BaseDirection::Auto => None,
+            BaseDirection::LeftToRight => Some(Level::ltr()),
+            BaseDirection::RightToLeft => Some(Level::rtl()),
+        };
+        let bidi = BidiInfo::new(text, base_level);
+
+        let mut layout = Self {
+            glyphs: Vec::new(),
+            lines: Vec::new(),
+            metrics: source.metrics(),
+            vertical: settings.writing_mode == WritingMode::VerticalRl,
+            truncated: false,
+        };
+        let mut spacing = Spacing::new(settings, source);
+
+        // The bidi algorithm ends a paragraph at the "\r" of a "\r\n" and
+        // starts another one for the "\n", which is the same line break.
+        let mut lines: Vec<_> = bidi
+            .paragraphs
+            .iter()
+            .filter(|para| {
+                &text[para.range.clone()] != "\n"
+                    || !text[..para.range.start].ends_with('\r')
+            })
+            .map(|para| {
+                let end = para.range.start
+                    + text[para.range.clone()]
+                        .trim_end_matches(is_paragraph_separator)
+                        .len();
+                (para.range.start..end, Some(para))
+            })
+            .collect();
+        // A paragraph separator at the end starts one more, empty, line.
+        if text.is_empty() || text.ends_with(is_paragraph_separator) {
+            lines.push((text.len()..text.len(), None));
+        }
+        let max_lines = settings.max_lines.map_or(lines.len(), |m| m.max(1));
+        let clamped = lines.len() > max_lines;
+        lines.truncate(max_lines);
+
+        let count = lines.len();
+        let empty_rtl = base_level.is_some_and(|l| l.is_rtl());
+        for (i, (range, para)) in lines.into_iter().enumerate() {
+            spacing.collapse_line(text, range.clone());
+            let line = LineText {
+                text,
+                bidi: &bidi,
+                para,
+                range,
+                rtl: para.map_or(empty_rtl, |p| p.level.is_rtl()),
+                vertical: layout.vertical,
+            };
+            let clamped = clamped && i + 1 == count;
+            layout.push_truncated(&line, source, &mut spacing, clamped);
+        }
+
+        layout
+    }
+
+    /// Lays out `line`, cutting it short with an ellipsis when it is wider
+    /// than the maximum width or, if `clamped`, followed by dropped lines.
+    fn push_truncated(
+        &mut self,
+        line: &LineText,
+        source: GlyphSource,
+        spacing: &mut Spacing,
+        clamped: bool,
+    ) {
+        spacing.ellipsis = None;
+        let first_glyph = self.glyphs.len();
+        self.push(line, source, spacing, line.runs(line.range.clone()));
+
+        let max_width = spacing.settings.max_width.unwrap_or(f32::INFINITY);
+        if self.lines.last().unwrap().width <= max_width && !clamped {
+            return;
+        }
+        self.truncated = true;
+
+        // Advances of the clusters in logical order.
+        let mut clusters: Vec<(usize, f32)> = self.glyphs[first_glyph..]
+            .iter()
+            .map(|g| (g.cluster, g.advance))
+            .collect();
+        clusters.sort_by_key(|&(cluster, _)| cluster);
+        clusters.dedup_by(|next, first| {
+            let same = next.0 == first.0;
+            if same {
+                first.1 += next.1;
+            }
+            same
+        });
+
+        let position = if clamped {
+            Ellipsis::End
+        } else {
+            spacing.settings.ellipsis
+        };
+        let ellipsis: f32 = source.ellipsis(0).iter().map(|g| g.advance).sum();
+        let mut budget = max_width - ellipsis;
+        loop {
+            self.pop_line(first_glyph);
+            let hidden =
+                hidden_range(&clusters, line.range.clone(), position, budget);
+            spacing.ellipsis = Some(hidden.clone());
+            self.push(line, source, spacing, line.runs_with_ellipsis(hidden));
+
+            // Tabs may have grown, drop more until the line fits.
+            let width = self.lines.last().unwrap().width;
+            if width <= max_width || budget <= 0.0 {
+                break;
+            }
+            budget -= width - max_width;
+        }
+    }
+
+    fn push(
+        &mut self,
+        line: &LineText,
+        source: GlyphSource,
+        spacing: &Spacing,
+        runs: Vec<(Range<usize>, bool)>,
+    ) {
+        let range = line.range.clone();
+        if self.vertical {
+            self.push_column(line.text, source, spacing, range, runs);
+        } else {
+            self.push_line(line.text, source, spacing, range, runs, line.rtl);
+        }
+    }
+
+    /// Removes the last line, whose glyphs start at `first_glyph`.
+    fn pop_line(&mut self, first_glyph: usize) {
+        self.lines.pop();
+        self.glyphs.truncate(first_glyph);
+    }
+
+    fn push_line(
+        &mut self,
+        text: &str,
+        source: GlyphSource,
+        spacing: &Spacing,
+        range: Range<usize>,
+        runs: Vec<(Range<usize>, bool)>,
+        rtl: bool,
+    ) {
+        let baseline = -(self.lines.len() as f32) * self.metrics.line_height;
+        let first_glyph = self.glyphs.len();
+        let mut pen_x = 0.0;
+        let mut line_runs = Vec::with_capacity(runs.len());
+
+        for (run, run_rtl) in runs {
+            let run_x = pen_x;
+            let start = self.glyphs.len();
+            let glyphs = if spacing.ellipsis == Some(run.clone()) {
+                source.ellipsis(run.start)
+            } else {
+                source.run_glyphs(text, run.clone(), run_rtl)
+            };
+            for g in spacing.place_run(text, source, glyphs, pen_x, false) {
+                self.glyphs.push(PositionedGlyph {
+                    key: g.key,
+                    cluster: g.cluster,
+                    x: pen_x,
+                    y: baseline,
+                    advance: g.advance,
+                    offset: g.offset,
+                    sideways: false,
+                });
+                pen_x += g.advance;
+            }
+            line_runs.push(Run {
+                range: run,
+                rtl: run_rtl,
+                x: run_x,
+                width: pen_x - run_x,
+                glyphs: start..self.glyphs.len(),
+            });
+        }
+
+        let width = pen_x;
+        let x = if rtl { -width } else { 0.0 };
+        for glyph in &mut self.glyphs[first_glyph..] {
+            glyph.x += x;
+        }
+        for run in &mut line_runs {
+            run.x += x;
+        }
+
+        self.lines.push(Line {
+            range,
+            runs: line_runs,
+            rtl,
+            x,
+            width,
+            baseline,
+        });
+    }
+
+    /// Like [`Layout::push_line`] for a column of vertical text, `runs`
+    /// flagged as sideways are turned.
+    fn push_column(
+        &mut self,
+        text: &str,
+        source: GlyphSource,
+        spacing: &Spacing,
+        range: Range<usize>,
+        runs: Vec<(Range<usize>, bool)>,
+    ) {
+        let center = -(self.lines.len() as f32) * self.metrics.line_height;
+        let mut pen_y = 0.0;
+        let mut column_runs = Vec::with_capacity(runs.len());
+
+        for (run, sideways) in runs {
+            let run_y = pen_y;
+            let start = self.glyphs.len();
+            let glyphs = if spacing.ellipsis == Some(run.clone()) {
+                let glyphs = source.ellipsis(run.start);
+                let glyphs =
+                    spacing.place_run(text, source, glyphs, pen_y, false);
+                turn_sideways(glyphs, &self.metrics)
+            } else if sideways {
+                let glyphs = source.run_glyphs(text, run.clone(), false);
+                let glyphs =
+                    spacing.place_run(text, source, glyphs, pen_y, false);
+                turn_sideways(glyphs, &self.metrics)
+            } else {
+                let glyphs = source.upright_glyphs(text, run.clone());
+                spacing.place_run(text, source, glyphs, pen_y, true)
+            };
+            for g in glyphs {
+                self.glyphs.push(PositionedGlyph {
+                    key: g.key,
+                    cluster: g.cluster,
+                    x: center,
+                    y: -pen_y,
+                    advance: g.advance,
+                    offset: g.offset,
+                    sideways,
+                });
+                pen_y += g.advance;
+            }
+            column_runs.push(Run {
+                range: run,
+                rtl: false,
+                x: run_y,
+                width: pen_y - run_y,
+                glyphs: start..self.glyphs.len(),
+            });
+        }
+
+        self.lines.push(Line {
+            range,
+            runs: column_runs,
+            rtl: false,
+            x: 0.0,
+            width: pen_y,
+            baseline: center,
+        });
+    }
+
+    /// Pen position of `glyph` along its line.
+    fn inline_position(&self, glyph: &PositionedGlyph) -> f32 {
+        if self.vertical {
+            -glyph.y
+        } else {
+            glyph.x
+        }
+    }
+
+    /// Line holding the caret at byte `offset`.
+    fn line_at_offset(&self, offset: usize) -> &Line {
+        self.lines
+            .iter()
+            .find(|l| offset <= l.range.end)
+            .unwrap_or_else(|| self.lines.last().unwrap())
+    }
+
+    /// Position of a caret at byte `offset`, on the baseline of its line
+    /// or the center line of its column. At the boundary of two runs the
+    /// caret sticks to the run of the character after it, or with
+    /// [`Affinity::Upstream`] to the one before it.
+    pub fn caret_position(
+        &self,
+        offset: usize,
+        affinity: Affinity,
+    ) -> (f32, f32) {
+        let line = self.line_at_offset(offset);
+        let inline = self.caret_inline(line, offset, affinity);
+        if self.vertical {
+            (line.baseline, -inline)
+        } else {
+            (inline, line.baseline)
+        }
+    }
+
+    /// Caret at byte `offset`, from the descender to the ascender of its
+    /// line. The rectangle has no width, or no height in vertical text,
+    /// renderers widen it.
+    pub fn caret_rect(&self, offset: usize, affinity: Affinity) -> Rect {
+        let line = self.line_at_offset(offset);
+        let inline = self.caret_inline(line, offset, affinity);
+        self.line_rect(line, inline, inline)
+    }
+
+    /// Rectangles covering the glyphs of the characters in `range`, at most
+    /// one per run. Lines whose break is selected get a space wide
+    /// rectangle past their visual end.
+    pub fn selection_rects(&self, range: Range<usize>) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            if range.end < line.range.start || line.range.end < range.start {
+                continue;
+            }
+            for run in &line.runs {
+                if let Some((start, end)) = self.run_extent(run, &range) {
+                    rects.push(self.line_rect(line, start, end));
+                }
+            }
+
+            let selects_break =
+                range.contains(&line.range.end) && i + 1 < self.lines.len();
+            if selects_break {
+                let (start, end) = if line.rtl {
+                    (line.x - DEFAULT_SPACE_ADVANCE, line.x)
+                } else {
+                    let end = line.x + line.width;
+                    (end, end + DEFAULT_SPACE_ADVANCE)
+                };
+                rects.push(self.line_rect(line, start, end));
+            }
+        }
+        rects
+    }
+
+    /// Rectangles of `decoration` under, through or over the characters of
+    /// `range`, one per run. The lines of vertical text run along the left
+    /// edge, center and right edge of the column.
+    pub fn decoration_rects(
+        &self,
+        range: Range<usize>,
+        decoration: DecorationLine,
+    ) -> Vec<Rect> {
+        let m = self.metrics;
+        let (top, thickness) = match decoration {
+            DecorationLine::Underline => (m.underline_y, m.underline_thickness),
+            DecorationLine::Strikethrough => {
+                (m.strikeout_y, m.strikeout_thickness)
+            }
+            DecorationLine::Overline => (m.ascender, m.underline_thickness),
+        };
+
+        let mut rects = Vec::new();
+        for line in &self.lines {
+            if range.end <= line.range.start || line.range.end < range.start {
+                continue;
+            }
+            let extents =
+                line.runs.iter().filter_map(|r| self.run_extent(r, &range));
+            for (start, end) in extents {
+                let mut rect = self.line_rect(line, start, end);
+                if self.vertical {
+                    rect.left = match decoration {
+                        DecorationLine::Underline => rect.left,
+                        DecorationLine::Strikethrough => {
+                            line.baseline - 0.5 * thickness
+                        }
+                        DecorationLine::Overline => rect.right - thickness,
+                    };
+                    rect.right = rect.left + thickness;
+                } else {
+                    rect.top = line.baseline + top;
+                    rect.bottom = rect.top - thickness;
+                }
+                rects.push(rect);
+            }
+        }
+        rects
+    }
+
+    /// Span of the glyphs of `run` which belong to `range`, along the line.
+    fn run_extent(
+        &self,
+        run: &Run,
+        range: &Range<usize>,
+    ) -> Option<(f32, f32)> {
+        self.glyphs[run.glyphs.clone()]
+            .iter()
+            .filter(|g| range.contains(&g.cluster) && g.advance != 0.0)
+            .map(|g| {
+                let start = self.inline_position(g);
+                (start.min(start + g.advance), start.max(start + g.advance))
+            })
+            .reduce(|(a, b), (c, d)| (a.min(c), b.max(d)))
+    }
+
+    /// Rectangle of `line` between two positions along it.
+    fn line_rect(&self, line: &Line, start: f32, end: f32) -> Rect {
+        let LineMetrics {
+            ascender,
+            descender,
+            ..
+        } = self.metrics;
+        if self.vertical {
+            let half = 0.5 * (ascender - descender);
+            Rect {
+                left: line.baseline - half,
+                bottom: -end,
+                right: line.baseline + half,
+                top: -start,
+            }
+        } else {
+            Rect {
+                left: start,
+                bottom: line.baseline + descender,
+                right: end,
+                top: line.baseline + ascender,
+            }
+        }
+    }
+
+    fn caret_inline(
+        &self,
+        line: &Line,
+        offset: usize,
+        affinity: Affinity,
+    ) -> f32 {
+        let containing =
+            || line.runs.iter().find(|r| r.range.contains(&offset));
+        let ending = || line.runs.iter().find(|r| r.range.end == offset);
+        let run = match affinity {
+            Affinity::Downstream => containing().or_else(ending),
+            Affinity::Upstream => ending().or_else(containing),
+        };
+        match run {
+            Some(run) => self.caret_in_run(run, offset),
+            None => line.start_x(),
+        }
+    }
+
+    fn caret_in_run(&self, run: &Run, offset: usize) -> f32 {
+        let glyphs = &self.glyphs[run.glyphs.clone()];
+        if run.rtl {
+            // Clusters decrease from left to right, the caret sits on the
+            // right edge of the first glyph at or after `offset`.
+            glyphs
+                .iter()
+                .rev()
+                .find(|g| g.cluster >= offset)
+                .map_or(run.x, |g| self.inline_position(g) + g.advance)
+        } else {
+            glyphs
+                .iter()
+                .find(|g| g.cluster >= offset)
+                .map_or(run.x + run.width, |g| self.inline_position(g))
+        }
+    }
+
+    /// Caret position closest to `(x, y)`: its byte offset, and whether it
+    /// was found after the character before it or before the one after it.
+    pub fn hit_test(&self, x: f32, y: f32) -> (usize, Affinity) {
+        let line_height = self.metrics.line_height;
+        let (index, inline) = if self.vertical {
+            ((0.5 * line_height - x) / line_height, -y)
+        } else {
+            ((self.metrics.ascender - y) / line_height, x)
+        };
+        let index = index.floor().max(0.0) as usize;
+        let line = &self.lines[index.min(self.lines.len() - 1)];
+
+        let run = match line
+            .runs
+            .iter()
+            .find(|r| inline < r.x + r.width)
+            .or_else(|| line.runs.last())
+        {
+            Some(run) => run,
+            None => return (line.range.start, Affinity::Downstream),
+        };
+        let glyphs = &self.glyphs[run.glyphs.clone()];
+        let glyph = match glyphs
+            .iter()
+            .find(|g| inline < self.inline_position(g) + g.advance)
+            .or_else(|| glyphs.last())
+        {
+            Some(glyph) => glyph,
+            None => return (run.range.start, Affinity::Downstream),
+        };
+
+        // The half of the glyph closer to its logical start selects the
+        // caret before the glyph, the other half the one after it.
+        let left_half =
+            inline < self.inline_position(glyph) + 0.5 * glyph.advance;
+        if left_half != run.rtl {
+            (glyph.cluster, Affinity::Downstream)
+        } else {
+            let next = glyphs
+                .iter()
+                .map(|g| g.cluster)
+                .filter(|&c| c > glyph.cluster)
+                .min()
+                .unwrap_or(run.range.end);
+            (next, Affinity::Upstream)
+        }
+    }
+}
+
+/// Characters of the bidi class B, which end a paragraph.
+fn is_paragraph_separator(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\u{1c}'..='\u{1e}' | '\u{85}' | '\u{2029}')
+}
+
+/// Whitespace which [`WhiteSpace::Collapse`] collapses.
+fn is_collapsible(c: char) -> bool {
+    matches!(c, ' ' | '\t')
+}
+
+/// Characters which get the word spacing.
+fn is_word_separator(c: char) -> bool {
+    matches!(c, ' ' | '\u{a0}')
+}
+
+fn is_no_break_space(c: char) -> bool {
+    matches!(c, '\u{a0}' | '\u{2007}' | '\u{202f}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Atlas glyphs of `chars`, half an em wide.
+    fn atlas(chars: &str) -> HashMap<GlyphKey, Glyph> {
+        let bounds = Rect {
+            left: 0.05,
+            bottom: 0.0,
+            right: 0.45,
+            top: 0.7,
+        };
+        chars
+            .chars()
+            .map(|c| {
+                let glyph = Glyph {
+                    advance_x: 0.5,
+                    advance_y: 0.0,
+                    plane_bounds: bounds,
+                    atlas_bounds: bounds,
+                    page: 0,
+                };
+                (GlyphKey::char(c), glyph)
+            })
+            .collect()
+    }
+
+    const METRICS: LineMetrics = LineMetrics {
+        ascender: 0.8,
+        descender: -0.2,
+        line_height: 1.2,
+        underline_y: -0.1,
+        underline_thickness: 0.05,
+        strikeout_y: 0.3,
+        strikeout_thickness: 0.05,
+    };
+
+    /// Latin and Hebrew letters, the space, full stop and ellipsis, and a
+    /// combining acute accent without an advance.
+    fn glyphs() -> HashMap<GlyphKey, Glyph> {
+        let mut glyphs =
+            atlas("abcdefghijklmnopqrstuvwxyz .\u{2026}\u{5d0}\u{5d1}\u{5d2}");
+        let accent = Rect {
+            left: 0.15,
+            bottom: 0.75,
+            right: 0.35,
+            top: 0.95,
+        };
+        let accent = Glyph {
+            advance_x: 0.0,
+            advance_y: 0.0,
+            plane_bounds: accent,
+            atlas_bounds: accent,
+            page: 0,
+        };
+        glyphs.insert(GlyphKey::char('\u{301}'), accent);
+        glyphs
+    }
+
+    fn layout_with(
+        glyphs: &HashMap<GlyphKey, Glyph>,
+        text: &str,
+        settings: &LayoutSettings,
+    ) -> Layout {
+        let source = GlyphSource::Atlas {
+            glyphs,
+            metrics: METRICS,
+        };
+        Layout::new(text, source, settings)
+    }
+
+    fn layout(text: &str, settings: &LayoutSettings) -> Layout {
+        layout_with(&glyphs(), text, settings)
+    }
+
+    /// Clusters of the glyphs in visual order.
+    fn clusters(layout: &Layout) -> Vec<usize> {
+        layout.glyphs.iter().map(|g| g.cluster).collect()
+    }
+
+    fn line_ranges(layout: &Layout) -> Vec<Range<usize>> {
+        layout.lines.iter().map(|l| l.range.clone()).collect()
+    }
+
+    #[test]
+    fn crlf_is_a_single_line_break() {
+        let settings = LayoutSettings::default();
+        assert_eq!(line_ranges(&layout("a\r\nb", &settings)), [0..1, 3..4]);
+        assert_eq!(
+            line_ranges(&layout("a\r\n\r\nb", &settings)),
+            [0..1, 3..3, 5..6]
+        );
+        assert_eq!(line_ranges(&layout("a\r\n", &settings)), [0..1, 3..3]);
+        // Reversed, they are two line breaks
+        assert_eq!(
+            line_ranges(&layout("a\n\rb", &settings)),
+            [0..1, 2..2, 3..4]
+        );
+    }
+
+    #[test]
+    fn every_paragraph_separator_breaks_lines() {
+        let settings = LayoutSettings::default();
+        for separator in ["\n", "\r", "\u{1c}", "\u{1d}", "\u{1e}"]
+            .into_iter()
+            .chain(["\u{85}", "\u{2029}"])
+        {
+            let text = format!("ab{}c", separator);
+            let layout = layout(&text, &settings);
+            let end = 2 + separator.len();
+            assert_eq!(line_ranges(&layout), [0..2, end..end + 1]);
+
+            let trailing = format!("ab{}", separator);
+            let layout = self::layout(&trailing, &settings);
+            assert_eq!(line_ranges(&layout), [0..2, end..end]);
+        }
+        // Other control characters don't
+        assert_eq!(layout("a\u{b}b", &settings).lines.len(), 1);
+    }
+
+    #[test]
+    fn rtl_runs_are_reversed() {
+        // "ab אבג cd", the spaces take the direction of the paragraph
+        let text = "ab \u{5d0}\u{5d1}\u{5d2} cd";
+        let layout = layout(text, &LayoutSettings::default());
+        assert_eq!(clusters(&layout), [0, 1, 2, 7, 5, 3, 9, 10, 11]);
+        let runs: Vec<_> = layout.lines[0]
+            .runs
+            .iter()
+            .map(|r| (r.range.clone(), r.rtl, r.x, r.width))
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                (0..3, false, 0.0, 1.5),
+                (3..9, true, 1.5, 1.5),
+                (9..12, false, 3.0, 1.5)
+            ]
+        );
+        let xs: Vec<_> = layout.glyphs.iter().map(|g| g.x).collect();
+        assert_eq!(xs, [0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0]);
+    }
+
+    #[test]
+    fn rtl_lines_end_at_the_origin() {
+        let text = "\u{5d0}\u{5d1} a";
+        let layout = layout(text, &LayoutSettings::default());
+        let line = &layout.lines[0];
+        assert!(line.rtl);
+        assert_eq!((line.x, line.width), (-2.0, 2.0));
+        assert_eq!(clusters(&layout), [5, 4, 2, 0]);
+        assert_eq!(layout.glyphs[0].x, -2.0);
+    }
+
+    #[test]
+    fn direction_is_detected_per_paragraph() {
+        let text = "\u{5d0}b\nab\n\n";
+        let auto = layout(text, &LayoutSettings::default());
+        let rtl: Vec<_> = auto.lines.iter().map(|l| l.rtl).collect();
+        assert_eq!(rtl, [true, false, false, false]);
+
+        let settings = LayoutSettings {
+            base_direction: BaseDirection::RightToLeft,
+            ..LayoutSettings::default()
+        };
+        let forced = layout(text, &settings);
+        assert!(forced.lines.iter().all(|l| l.rtl));
+        // Letters keep their own direction within the line
+        assert_eq!(forced.lines[1].runs[0].range, 4..6);
+        assert!(!forced.lines[1].runs[0].rtl);
+        assert_eq!(forced.lines[1].x, -1.0);
+    }
+
+    #[test]
+    fn marks_stay_on_their_base() {
+        let text = "e\u{301}x";
+        let layout = layout(text, &LayoutSettings::default());
+        assert_eq!(clusters(&layout), [0, 0, 3]);
+        let mark = layout.glyphs[1];
+        assert_eq!(mark.advance, 0.0);
+        // Centered on the "e", which is drawn from 0 to 0.5
+        assert_eq!(mark.x + mark.offset[0], 0.0);
+        assert_eq!(layout.glyphs[2].x, 0.5);
+        assert_eq!(layout.lines[0].width, 1.0);
+    }
+
+    /// Pen positions of the glyphs in visual order.
+    fn xs(layout: &Layout) -> Vec<f32> {
+        layout.glyphs.iter().map(|g| g.x).collect()
+    }
+
+    #[test]
+    fn tabs_reach_the_next_stop() {
+        // Every four spaces of half an em
+        let layout = layout("a\tb\t\tc", &LayoutSettings::default());
+        assert_eq!(xs(&layout), [0.0, 0.5, 2.0, 2.5, 4.0, 6.0]);
+        // Drawn with the space glyph
+        assert_eq!(layout.glyphs[1].key, GlyphKey::char(' '));
+
+        let settings = LayoutSettings {
+            tab_stops: TabStops::Positions(vec![1.0, 1.25]),
+            ..LayoutSettings::default()
+        };
+        // After the last stop, tabs advance like a space
+        let layout = self::layout("a\tb\tc\td", &settings);
+        assert_eq!(xs(&layout), [0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0]);
+    }
+
+    #[test]
+    fn collapsed_whitespace() {
+        let settings = LayoutSettings {
+            white_space: WhiteSpace::Collapse,
+            ..LayoutSettings::default()
+        };
+        let layout = layout(" \t a  \t b \n  c", &settings);
+        let keys: Vec<_> = layout.glyphs.iter().map(|g| g.key).collect();
+        let [a, space, b, c] = ['a', ' ', 'b', 'c'].map(GlyphKey::char);
+        assert_eq!(keys, [a, space, b, c]);
+        assert_eq!(xs(&layout), [0.0, 0.5, 1.0, 0.0]);
+        assert_eq!(layout.lines[0].width, 1.5);
+        // Tabs collapse into a single space as well
+        let layout = self::layout("a\tb", &settings);
+        assert_eq!(xs(&layout), [0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn no_break_spaces_are_kept() {
+        let settings = LayoutSettings {
+            white_space: WhiteSpace::Collapse,
+            ..LayoutSettings::default()
+        };
+        let layout = layout("a\u{a0}\u{a0}b", &settings);
+        // Drawn with the space glyph, which the atlas has instead
+        assert_eq!(layout.glyphs[1].key, GlyphKey::char(' '));
+        assert_eq!(xs(&layout), [0.0, 0.5, 1.0, 1.5]);
+    }
+
+    #[test]
+    fn letter_and_word_spacing() {
+        let settings = LayoutSettings {
+            letter_spacing: 0.125,
+            word_spacing: 0.25,
+            ..LayoutSettings::default()
+        };
+        let layout = layout("ab c", &settings);
+        assert_eq!(xs(&layout), [0.0, 0.625, 1.25, 2.125]);
+        assert_eq!(layout.lines[0].width, 2.75);
+    }
+
+    #[test]
+    fn monospace_cells_center_glyphs() {
+        let settings = LayoutSettings {
+            monospace: Some(1.0),
+            ..LayoutSettings::default()
+        };
+        let layout = layout("ab", &settings);
+        assert_eq!(xs(&layout), [0.0, 1.0]);
+        // The ink from 0.05 to 0.45 is moved to the center of the cell
+        assert_eq!(layout.glyphs[0].offset, [0.25, 0.0]);
+        assert_eq!(layout.lines[0].width, 2.0);
+    }
+
+    fn keys(layout: &Layout) -> String {
+        layout
+            .glyphs
+            .iter()
+            .map(|g| match g.key {
+                GlyphKey::Codepoint(c) => char::from_u32(c).unwrap(),
+                GlyphKey::Index(_) => '?',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ellipsis_positions() {
+        let truncated = |ellipsis| {
+            let settings = LayoutSettings {
+                max_width: Some(2.0),
+                ellipsis,
+                ..LayoutSettings::default()
+            };
+            let layout = layout("abcdefgh", &settings);
+            assert!(layout.truncated);
+            assert_eq!(layout.lines[0].width, 2.0);
+            (keys(&layout), clusters(&layout))
+        };
+        let end = ("abc\u{2026}".to_owned(), vec![0, 1, 2, 3]);
+        assert_eq!(truncated(Ellipsis::End), end);
+        let start = ("\u{2026}fgh".to_owned(), vec![0, 5, 6, 7]);
+        assert_eq!(truncated(Ellipsis::Start), start);
+        let middle = ("a\u{2026}gh".to_owned(), vec![0, 1, 6, 7]);
+        assert_eq!(truncated(Ellipsis::Middle), middle);
+    }
+
+    #[test]
+    fn lines_that_fit_are_kept() {
+        let settings = LayoutSettings {
+            max_width: Some(2.0),
+            max_lines: Some(2),
+            ..LayoutSettings::default()
+        };
+        let layout = layout("abcd\nab", &settings);
+        assert!(!layout.truncated);
+        assert_eq!(keys(&layout), "abcdab");
+    }
+
+    #[test]
+    fn full_stops_replace_a_missing_ellipsis() {
+        let glyphs = atlas("abcdefgh.");
+        let settings = LayoutSettings {
+            max_width: Some(2.0),
+            ..LayoutSettings::default()
+        };
+        let layout = layout_with(&glyphs, "abcdefgh", &settings);
+        assert_eq!(keys(&layout), "a...");
+        assert_eq!(layout.lines[0].width, 2.0);
+    }
+
+    #[test]
+    fn clamped_lines_end_in_an_ellipsis() {
+        let settings = LayoutSettings {
+            max_lines: Some(2),
+            ..LayoutSettings::default()
+        };
+        let layout = layout("ab\ncd\nef", &settings);
+        assert!(layout.truncated);
+        assert_eq!(line_ranges(&layout), [0..2, 3..5]);
+        assert_eq!(keys(&layout), "abcd\u{2026}");
+
+        // Both limits at once, the clamped line is cut at the end
+        let settings = LayoutSettings {
+            max_width: Some(1.5),
+            ellipsis: Ellipsis::Start,
+            max_lines: Some(1),
+            ..LayoutSettings::default()
+        };
+        let layout = self::layout("abcd\nef", &settings);
+        assert_eq!(keys(&layout), "ab\u{2026}");
+
+        // At least one line is kept
+        let settings = LayoutSettings {
+            max_lines: Some(0),
+            ..LayoutSettings::default()
+        };
+        assert_eq!(self::layout("a\nb", &settings).lines.len(), 1);
+    }
+
+    /// Left, bottom, right and top of `rects`, rounded to avoid float noise.
+    fn edges(rects: &[Rect]) -> Vec<[f32; 4]> {
+        let round = |v: f32| (v * 1000.0).round() / 1000.0;
+        rects
+            .iter()
+            .map(|r| [r.left, r.bottom, r.right, r.top].map(round))
+            .collect()
+    }
+
+    #[test]
+    fn carets_between_characters() {
+        let layout = layout("abc\nde", &LayoutSettings::default());
+        let caret = |offset| layout.caret_position(offset, Affinity::default());
+        assert_eq!(caret(1), (0.5, 0.0));
+        assert_eq!(caret(3), (1.5, 0.0));
+        assert_eq!(caret(4), (0.0, -1.2));
+        assert_eq!(caret(6), (1.0, -1.2));
+        let rect = layout.caret_rect(1, Affinity::Downstream);
+        assert_eq!(edges(&[rect]), [[0.5, -0.2, 0.5, 0.8]]);
+    }
+
+    #[test]
+    fn carets_in_rtl_lines() {
+        let layout =
+            layout("\u{5d0}\u{5d1}\u{5d2}", &LayoutSettings::default());
+        let caret = |offset| layout.caret_position(offset, Affinity::default());
+        assert_eq!(caret(0), (0.0, 0.0));
+        assert_eq!(caret(2), (-0.5, 0.0));
+        assert_eq!(caret(6), (-1.5, 0.0));
+        // Empty right-to-left lines start at the origin too
+        let settings = LayoutSettings {
+            base_direction: BaseDirection::RightToLeft,
+            ..LayoutSettings::default()
+        };
+        let empty = self::layout("", &settings);
+        assert_eq!(empty.caret_position(0, Affinity::Downstream), (0.0, 0.0));
+    }
+
+    #[test]
+    fn affinity_picks_the_run_at_direction_changes() {
+        // "ab אבג", byte 3 is the end of "ab " and the start of "אבג"
+        let layout =
+            layout("ab \u{5d0}\u{5d1}\u{5d2}", &LayoutSettings::default());
+        assert_eq!(layout.caret_position(3, Affinity::Downstream), (3.0, 0.0));
+        assert_eq!(layout.caret_position(3, Affinity::Upstream), (1.5, 0.0));
+    }
+
+    #[test]
+    fn hit_tests_find_the_closest_caret() {
+        let layout = layout("abc\n\nde", &LayoutSettings::default());
+        assert_eq!(layout.hit_test(0.6, 0.3), (1, Affinity::Downstream));
+        assert_eq!(layout.hit_test(0.9, 0.3), (2, Affinity::Upstream));
+        assert_eq!(layout.hit_test(5.0, 0.3), (3, Affinity::Upstream));
+        assert_eq!(layout.hit_test(-1.0, 5.0), (0, Affinity::Downstream));
+        // The empty line, then the last one for anything below it
+        assert_eq!(layout.hit_test(0.3, -1.0), (4, Affinity::Downstream));
+        assert_eq!(layout.hit_test(0.1, -9.0), (5, Affinity::Downstream));
+
+        let rtl = self::layout("\u{5d0}\u{5d1}", &LayoutSettings::default());
+        // The right half of the first letter is before it
+        assert_eq!(rtl.hit_test(-0.2, 0.0), (0, Affinity::Downstream));
+        assert_eq!(rtl.hit_test(-0.4, 0.0), (2, Affinity::Upstream));
+        assert_eq!(rtl.hit_test(-5.0, 0.0), (4, Affinity::Upstream));
+    }
+
+    #[test]
+    fn selections_cover_glyphs_and_line_breaks() {
+        let layout = layout("abc\nde", &LayoutSettings::default());
+        let rects = layout.selection_rects(1..2);
+        assert_eq!(edges(&rects), [[0.5, -0.2, 1.0, 0.8]]);
+
+        let rects = layout.selection_rects(1..5);
+        assert_eq!(
+            edges(&rects),
+            [
+                [0.5, -0.2, 1.5, 0.8],
+                // The selected line break
+                [1.5, -0.2, 1.75, 0.8],
+                [0.0, -1.4, 0.5, -0.4],
+            ]
+        );
+
+        // The first Hebrew letter is at the right end of its run
+        let text = "ab \u{5d0}\u{5d1}\u{5d2}";
+        let mixed = self::layout(text, &LayoutSettings::default());
+        assert_eq!(
+            edges(&mixed.selection_rects(3..5)),
+            [[2.5, -0.2, 3.0, 0.8]]
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! MSDF text rendering with wgpu.
+//!
+//! The renderer, the atlas loaders and exporters and the layout live in this
+//! library, `main.rs` is a demo on top of it. Applications use far more of
+//! the API than the demo does, which is why it is public instead of
+//! reported as dead code.
+
+pub mod arfont;
+pub mod bmfont;
+pub mod camera;
+pub mod caret;
+pub mod dynamic;
+pub mod generator;
+pub mod input;
+pub mod json;
+pub mod layout;
+pub mod mipmap;
+pub mod msdf;
+pub mod packer;
+pub mod shaping;
+pub mod text;
+pub mod upload;
+pub mod util;
+
+use winit::dpi::PhysicalSize;
+
+pub struct Graphics {
+    pub device: wgpu::Device,
+    pub surface: wgpu::Surface,
+    pub adapter: wgpu::Adapter,
+    pub queue: wgpu::Queue,
+    pub config: wgpu::SurfaceConfiguration,
+}
+
+impl Graphics {
+    pub async fn new(
+        window: &winit::window::Window,
+    ) -> Result<Self, wgpu::RequestDeviceError> {
+        let backends = wgpu::util::backend_bits_from_env()
+            .unwrap_or_else(wgpu::Backends::all);
+        let instance = wgpu::Instance::new(backends);
+        let (size, surface) = unsafe {
+            let size = window.inner_size();
+            let surface = instance.create_surface(&window);
+            (size, surface)
+        };
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: Some(&surface),
+                ..Default::default()
+            })
+            .await
+            .expect("No adapters found!");
+
+        let adapter_info = adapter.get_info();
+        println!(
+            "Adapter info: Name: {}, backend: {:?}, device: {:?}",
+            adapter_info.name, adapter_info.backend, adapter_info.device_type
+        );
+
+        let required_features =
+            wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+        let adapter_features = adapter.features();
+        let required_limits = wgpu::Limits::default();
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Device"),
+                    features: adapter_features & required_features,
+                    limits: required_limits,
+                },
+                None,
+            )
+            .await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface.get_supported_formats(&adapter)[0],
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        };
+        surface.configure(&device, &config);
+
+        Ok(Self {
+            device,
+            surface,
+            adapter,
+            queue,
+            config,
+        })
+    }
+
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.config.width = new_size.width.max(1);
+        self.config.height = new_size.height.max(1);
+        self.surface.configure(&self.device, &self.config);
+    }
+}
@@ -1,15 +1,22 @@
-mod camera;
-mod text;
-mod util;
-
 use std::time::{Duration, Instant};
 
-use camera::Camera;
+use sdfer_proto::{
+    camera, caret, dynamic, input, layout, shaping, text, util, Graphics,
+};
+
+use camera::{world_to_window, Camera, ScreenProjection};
+use caret::Blink;
+use dynamic::DynamicAtlasSettings;
+use input::TextInput;
+use layout::{Affinity, GlyphSource, Layout};
+use nalgebra::{Matrix4, Point3, Vector3};
 use pollster::block_on;
-use text::Text;
+use shaping::Shaper;
+use text::{
+    Billboard, BillboardFacing, Decoration, FauxStyle, GlyphKey, Text,
+};
 use util::Requisites;
 use winit::{
-    dpi::PhysicalSize,
     event::{ElementState, KeyboardInput, VirtualKeyCode},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
@@ -37,7 +44,7 @@ fn main() {
     )
     .unwrap();
     let mut camera = Camera::new(&gfx);
-    let reqs = Requisites::init(&gfx, &arfont);
+    let reqs = Requisites::init(&gfx, &arfont).unwrap();
 
     let pipeline1 = util::pipeline1(&gfx, &reqs);
     let pipeline2 = util::pipeline2(&gfx, &reqs);
@@ -45,45 +52,217 @@ fn main() {
     let pipeline4 = util::pipeline4(&gfx, &reqs);
 
     let text1 = Text::new("TEST Aabcdefghijklmnoprstuvz", (0.0, 1.5, 0.0));
-    let (vertex_buffer1, vertices1) = text1.create_buffer(&gfx, &reqs.glyphs);
+    let (vertex_buffer1, vertices1) = text1.create_buffer(&gfx, &reqs);
     let text2 = Text::new("TEST Aabcdefghijklmnoprstuvz", (0.0, 0.0, 0.0));
-    let (vertex_buffer2, vertices2) = text2.create_buffer(&gfx, &reqs.glyphs);
+    let (vertex_buffer2, vertices2) = text2.create_buffer(&gfx, &reqs);
     let text3 = Text::new("TEST Aabcdefghijklmnoprstuvz", (0.0, -1.5, 0.0));
-    let (vertex_buffer3, vertices3) = text3.create_buffer(&gfx, &reqs.glyphs);
+    let (vertex_buffer3, vertices3) = text3.create_buffer(&gfx, &reqs);
     let text4 = Text::new("TEST Aabcdefghijklmnoprstuvz", (0.0, -3.0, 0.0));
-    let (vertex_buffer4, vertices4) = text4.create_buffer(&gfx, &reqs.glyphs);
+    let (vertex_buffer4, vertices4) = text4.create_buffer(&gfx, &reqs);
+
+    // Glyphs rasterized at runtime instead of loaded from the .arfont
+    let mut dynamic_reqs = Requisites::init_dynamic(
+        &gfx,
+        include_bytes!("../fonts/monserat.ttf").to_vec(),
+        DynamicAtlasSettings::default(),
+    )
+    .unwrap();
+    let pipeline5 = util::pipeline4(&gfx, &dynamic_reqs);
+    let dynamic_text = "Dynamic atlas";
+    dynamic_reqs.prepare(&gfx, dynamic_text).unwrap();
+    let text5 = Text::new(dynamic_text, (0.0, -4.5, 0.0));
+    let (mut vertex_buffer5, mut vertices5) =
+        text5.create_buffer(&gfx, &dynamic_reqs);
+
+    // Shaped text, "ffi" becomes a single ligature glyph
+    let shaper =
+        Shaper::new(include_bytes!("../fonts/monserat.ttf").to_vec()).unwrap();
+    let mut text6 = Text::new("Shaped office text", (0.0, -6.0, 0.0));
+    // Underlined, struck through and overlined spans
+    text6.decorations_mut().extend([
+        Decoration {
+            range: 0..6,
+            underline: Some([1.0, 0.4, 0.4, 1.0]),
+            strikethrough: None,
+            overline: None,
+            skip_descenders: true,
+        },
+        Decoration {
+            range: 14..18,
+            underline: None,
+            strikethrough: Some([1.0, 1.0, 1.0, 1.0]),
+            overline: Some([0.4, 1.0, 0.4, 1.0]),
+            skip_descenders: false,
+        },
+    ]);
+    // Synthesized bold and italic spans
+    text6.styles_mut().extend([
+        FauxStyle {
+            range: 7..13,
+            bold: 0.1,
+            italic: 0.0,
+        },
+        FauxStyle {
+            range: 14..18,
+            bold: 0.0,
+            italic: 0.2,
+        },
+    ]);
+    let layout6 = text6.layout(GlyphSource::Shaper(&shaper));
+    dynamic_reqs.prepare_layout(&gfx, &layout6).unwrap();
+    let (mut vertex_buffer6, mut vertices6) =
+        text6.create_layout_buffer(&gfx, &dynamic_reqs.glyphs, &layout6);
+    let (mut decoration_buffer6, mut decorations6) =
+        text6.create_decoration_buffer(&gfx, &dynamic_reqs.glyphs, &layout6);
+
+    // Selection behind "office" and a blinking caret after it
+    let solid_pipeline = util::solid_pipeline(&gfx, &dynamic_reqs);
+    let (selection_buffer6, selections6) = text6.create_selection_buffer(
+        &gfx,
+        &layout6,
+        7..13,
+        [0.2, 0.4, 0.8, 0.6],
+    );
+    let (caret_buffer6, carets6) = text6.create_caret_buffer(
+        &gfx,
+        &layout6,
+        13,
+        Affinity::Upstream,
+        0.06,
+        [1.0, 1.0, 1.0, 1.0],
+    );
+    let blink = Blink::default();
+
+    // Editable text, typed into with the keyboard
+    let mut input = TextInput::new(
+        Text::new("Type here", (0.0, -7.5, 0.0)),
+        true,
+        GlyphSource::Shaper(&shaper),
+    );
+    dynamic_reqs.prepare_layout(&gfx, input.layout()).unwrap();
+    let mut input_buffers = input.create_buffers(&gfx, &dynamic_reqs.glyphs);
+    window.set_ime_allowed(true);
+    let mut ime_position = None;
+
+    // Pixel aligned text in screen space, which the camera doesn't move
+    let mut screen =
+        ScreenProjection::new(window.inner_size(), window.scale_factor());
+    let screen_binding = dynamic_reqs.create_matrix_binding(&gfx);
+    gfx.queue.write_buffer(
+        &screen_binding.matrix_buffer,
+        0,
+        bytemuck::bytes_of(&screen.globals()),
+    );
+    let mut text7 = Text::new("Screen space text", (16.0, -32.0, 0.0));
+    text7.set_size(24.0);
+    text7.set_pixel_snap(Some(screen.pixels_per_unit()));
+    let layout7 = text7.layout(GlyphSource::Shaper(&shaper));
+    dynamic_reqs.prepare_layout(&gfx, &layout7).unwrap();
+    let (mut vertex_buffer7, mut vertices7) =
+        text7.create_layout_buffer(&gfx, &dynamic_reqs.glyphs, &layout7);
+
+    // Labels turning to the camera, one upright and one staying 20 pixels
+    // tall at any distance
+    let mut text8 = Text::new("Upright label", (-4.0, 3.0, -3.0));
+    text8.set_billboard(Some(Billboard {
+        facing: BillboardFacing::Cylindrical,
+        fixed_size: false,
+    }));
+    let mut text9 = Text::new("Fixed size label", (4.0, 3.0, -3.0));
+    text9.set_size(20.0);
+    text9.set_billboard(Some(Billboard {
+        facing: BillboardFacing::Spherical,
+        fixed_size: true,
+    }));
+    let label_layouts: Vec<_> = [&text8, &text9]
+        .iter()
+        .map(|text| text.layout(GlyphSource::Shaper(&shaper)))
+        .collect();
+    let mut labels: Vec<_> = [&text8, &text9]
+        .iter()
+        .zip(&label_layouts)
+        .map(|(text, layout)| {
+            dynamic_reqs.prepare_layout(&gfx, layout).unwrap();
+            text.create_layout_buffer(&gfx, &dynamic_reqs.glyphs, layout)
+        })
+        .collect();
+
+    // Sign on a wall to the left, turned to face right and stretched wide
+    let mut text10 = Text::new("Wall sign", (0.0, 0.0, 0.0));
+    text10.set_transform(1).unwrap();
+    let wall = Matrix4::new_translation(&Vector3::new(-8.0, 1.0, 2.0))
+        * Matrix4::from_euler_angles(0.0, std::f32::consts::FRAC_PI_2, 0.0)
+        * Matrix4::new_nonuniform_scaling(&Vector3::new(1.5, 1.0, 1.0));
+    dynamic_reqs.write_transform(&gfx, 1, &wall).unwrap();
+    let layout10 = text10.layout(GlyphSource::Shaper(&shaper));
+    dynamic_reqs.prepare_layout(&gfx, &layout10).unwrap();
+    let (mut vertex_buffer10, mut vertices10) =
+        text10.create_layout_buffer(&gfx, &dynamic_reqs.glyphs, &layout10);
 
     /////////////////////////////// LOOP ///////////////////////////////////////
     let target_framerate = Duration::from_secs_f64(1.0 / FPS_CAP);
     let mut time = Instant::now();
+    // Glyphs evicted from the dynamic atlas since the buffers were built
+    let mut evicted: Vec<GlyphKey> = Vec::new();
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
 
         match event {
-            winit::event::Event::WindowEvent { event, .. } => match event {
-                winit::event::WindowEvent::Resized(inner_size)
-                | winit::event::WindowEvent::ScaleFactorChanged {
-                    new_inner_size: &mut inner_size,
+            winit::event::Event::WindowEvent { event, .. } => {
+                if input.handle_event(&event, GlyphSource::Shaper(&shaper)) {
+                    // Glyphs which don't fit are left out
+                    match dynamic_reqs.prepare_layout(&gfx, input.layout()) {
+                        Ok(keys) => evicted.extend(keys),
+                        Err(err) => eprintln!("{}", err),
+                    }
+                    input_buffers =
+                        input.create_buffers(&gfx, &dynamic_reqs.glyphs);
+                }
+                // Snapped to the pixels of the new display
+                if let winit::event::WindowEvent::ScaleFactorChanged {
+                    scale_factor,
                     ..
-                } => {
-                    gfx.resize(inner_size);
-                    camera.resize(&gfx);
+                } = event
+                {
+                    screen.set_scale_factor(scale_factor);
+                    text7.set_pixel_snap(Some(screen.pixels_per_unit()));
+                    (vertex_buffer7, vertices7) = text7.create_layout_buffer(
+                        &gfx,
+                        &dynamic_reqs.glyphs,
+                        &layout7,
+                    );
                 }
-                winit::event::WindowEvent::CloseRequested => {
-                    *control_flow = ControlFlow::Exit
+                match event {
+                    winit::event::WindowEvent::Resized(inner_size)
+                    | winit::event::WindowEvent::ScaleFactorChanged {
+                        new_inner_size: &mut inner_size,
+                        ..
+                    } => {
+                        gfx.resize(inner_size);
+                        camera.resize(&gfx);
+                        screen.resize(inner_size);
+                        gfx.queue.write_buffer(
+                            &screen_binding.matrix_buffer,
+                            0,
+                            bytemuck::bytes_of(&screen.globals()),
+                        );
+                    }
+                    winit::event::WindowEvent::CloseRequested => {
+                        *control_flow = ControlFlow::Exit
+                    }
+                    winit::event::WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
+                    _ => (),
                 }
-                winit::event::WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
-                            ..
-                        },
-                    ..
-                } => *control_flow = ControlFlow::Exit,
-                _ => (),
-            },
+            }
             winit::event::Event::DeviceEvent { event, .. } => {
                 camera.input(&event)
             }
@@ -102,12 +281,87 @@ fn main() {
                 // UPDATE
                 camera.update();
 
-                let mat: &[[f32; 4]; 4] = &camera.update_global_matrix().into();
+                // Glyphs of the static text are prepared every frame, so
+                // typing can only evict glyphs which went out of use.
+                dynamic_reqs.next_frame();
+                match dynamic_reqs.prepare(&gfx, dynamic_text) {
+                    Ok(keys) => evicted.extend(keys),
+                    Err(err) => eprintln!("{}", err),
+                }
+                let drawn = [&layout6, &layout7, &layout10]
+                    .into_iter()
+                    .chain(&label_layouts)
+                    .chain([input.layout()]);
+                for layout in drawn {
+                    match dynamic_reqs.prepare_layout(&gfx, layout) {
+                        Ok(keys) => evicted.extend(keys),
+                        Err(err) => eprintln!("{}", err),
+                    }
+                }
+
+                // Buffers pointing at the old place of an evicted glyph are
+                // built again from the glyphs prepared above
+                if !evicted.is_empty() {
+                    let glyphs = &dynamic_reqs.glyphs;
+                    if dynamic_text
+                        .chars()
+                        .any(|c| evicted.contains(&GlyphKey::char(c)))
+                    {
+                        (vertex_buffer5, vertices5) =
+                            text5.create_buffer(&gfx, &dynamic_reqs);
+                    }
+                    if uses_any(&layout6, &evicted) {
+                        (vertex_buffer6, vertices6) =
+                            text6.create_layout_buffer(&gfx, glyphs, &layout6);
+                        (decoration_buffer6, decorations6) = text6
+                            .create_decoration_buffer(&gfx, glyphs, &layout6);
+                    }
+                    if uses_any(&layout7, &evicted) {
+                        (vertex_buffer7, vertices7) =
+                            text7.create_layout_buffer(&gfx, glyphs, &layout7);
+                    }
+                    let label_texts = [&text8, &text9];
+                    for (i, layout) in label_layouts.iter().enumerate() {
+                        if uses_any(layout, &evicted) {
+                            labels[i] = label_texts[i]
+                                .create_layout_buffer(&gfx, glyphs, layout);
+                        }
+                    }
+                    if uses_any(&layout10, &evicted) {
+                        (vertex_buffer10, vertices10) = text10
+                            .create_layout_buffer(&gfx, glyphs, &layout10);
+                    }
+                    if uses_any(input.layout(), &evicted) {
+                        input_buffers = input.create_buffers(&gfx, glyphs);
+                    }
+                    evicted.clear();
+                }
+
+                let globals = camera.globals();
                 gfx.queue.write_buffer(
                     &reqs.matrix_buffer,
                     0,
-                    bytemuck::cast_slice(mat),
+                    bytemuck::bytes_of(&globals),
+                );
+                gfx.queue.write_buffer(
+                    &dynamic_reqs.matrix_buffer,
+                    0,
+                    bytemuck::bytes_of(&globals),
+                );
+
+                // Keep the IME candidate window below the input
+                let rect = input.ime_rect();
+                let position = world_to_window(
+                    &globals.matrix.into(),
+                    Point3::new(rect.left, rect.bottom, 0.0),
+                    window.inner_size(),
                 );
+                if position != ime_position {
+                    if let Some(position) = position {
+                        window.set_ime_position(position);
+                    }
+                    ime_position = position;
+                }
 
                 // RENDER
                 let frame = gfx.surface.get_current_texture().unwrap();
@@ -178,6 +432,56 @@ fn main() {
 
                     rpass.draw(0..4, 0..vertices4);
 
+                    // Dynamic atlas
+                    rpass.set_pipeline(&pipeline5);
+
+                    rpass.set_vertex_buffer(0, vertex_buffer5.slice(..));
+                    rpass.set_bind_group(0, &dynamic_reqs.bind_group, &[]);
+
+                    rpass.draw(0..4, 0..vertices5);
+
+                    // Selection behind, caret in front of the text
+                    rpass.set_pipeline(&solid_pipeline);
+                    rpass.set_vertex_buffer(0, selection_buffer6.slice(..));
+                    rpass.draw(0..4, 0..selections6);
+
+                    rpass.set_pipeline(&pipeline5);
+                    rpass.set_vertex_buffer(0, vertex_buffer6.slice(..));
+                    rpass.draw(0..4, 0..vertices6);
+
+                    rpass.set_pipeline(&solid_pipeline);
+                    rpass.set_vertex_buffer(0, decoration_buffer6.slice(..));
+                    rpass.draw(0..4, 0..decorations6);
+
+                    if blink.visible() {
+                        rpass.set_vertex_buffer(0, caret_buffer6.slice(..));
+                        rpass.draw(0..4, 0..carets6);
+                    }
+
+                    input_buffers.draw(
+                        &mut rpass,
+                        &pipeline5,
+                        &solid_pipeline,
+                        input.caret_visible(),
+                    );
+
+                    // Billboards
+                    rpass.set_pipeline(&pipeline5);
+                    for (buffer, vertices) in &labels {
+                        rpass.set_vertex_buffer(0, buffer.slice(..));
+                        rpass.draw(0..4, 0..*vertices);
+                    }
+
+                    // Transformed
+                    rpass.set_vertex_buffer(0, vertex_buffer10.slice(..));
+                    rpass.draw(0..4, 0..vertices10);
+
+                    // Screen space
+                    rpass.set_pipeline(&pipeline5);
+                    rpass.set_bind_group(0, &screen_binding.bind_group, &[]);
+                    rpass.set_vertex_buffer(0, vertex_buffer7.slice(..));
+                    rpass.draw(0..4, 0..vertices7);
+
                     // Lines
                     //rpass.set_pipeline(&line_pipeline);
 
@@ -195,79 +499,8 @@ fn main() {
         }
     });
 }
-pub struct Graphics {
-    pub device: wgpu::Device,
-    pub surface: wgpu::Surface,
-    pub adapter: wgpu::Adapter,
-    pub queue: wgpu::Queue,
-    pub config: wgpu::SurfaceConfiguration,
-}
 
-impl Graphics {
-    async fn new(
-        window: &winit::window::Window,
-    ) -> Result<Self, wgpu::RequestDeviceError> {
-        let backends = wgpu::util::backend_bits_from_env()
-            .unwrap_or_else(wgpu::Backends::all);
-        let instance = wgpu::Instance::new(backends);
-        let (size, surface) = unsafe {
-            let size = window.inner_size();
-            let surface = instance.create_surface(&window);
-            (size, surface)
-        };
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                ..Default::default()
-            })
-            .await
-            .expect("No adapters found!");
-
-        let adapter_info = adapter.get_info();
-        println!(
-            "Adapter info: Name: {}, backend: {:?}, device: {:?}",
-            adapter_info.name, adapter_info.backend, adapter_info.device_type
-        );
-
-        let required_features = wgpu::Features::empty();
-        let adapter_features = adapter.features();
-        let required_limits = wgpu::Limits::default();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device"),
-                    features: adapter_features & required_features,
-                    limits: required_limits,
-                },
-                None,
-            )
-            .await?;
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode: wgpu::CompositeAlphaMode::Auto,
-        };
-        surface.configure(&device, &config);
-
-        Ok(Self {
-            device,
-            surface,
-            adapter,
-            queue,
-            config,
-        })
-    }
-
-    fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        self.config.width = new_size.width.max(1);
-        self.config.height = new_size.height.max(1);
-        self.surface.configure(&self.device, &self.config);
-    }
+/// Whether `layout` uses any of the glyphs of `keys`.
+fn uses_any(layout: &Layout, keys: &[GlyphKey]) -> bool {
+    layout.glyphs.iter().any(|glyph| keys.contains(&glyph.key))
 }
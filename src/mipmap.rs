@@ -0,0 +1,255 @@
+//! Mip chains for distance field atlases.
+//!
+//! Distances are linear, so averaging them gives the distance at the center
+//! of the averaged texels, as long as all of them belong to the same glyph.
+//! Each glyph is therefore downsampled only from its own texels: where a
+//! glyph rectangle covers half a texel of the next level, the texels of the
+//! neighbouring glyph or the background aren't mixed in.
+//!
+//! Glyph rectangles shrink by half per level and stay apart only if they
+//! were at least `1 << levels` pixels apart in the full resolution atlas,
+//! see [`mip_padding`].
+
+use artery_font::{Image, PixelFormat, Rect};
+
+/// Gap between glyphs needed to keep them apart in `levels` mip levels.
+pub fn mip_padding(levels: u32) -> u32 {
+    1 << levels
+}
+
+/// Number of levels below the full resolution one for a `width` x `height`
+/// atlas, limited to `max_levels`.
+pub fn level_count(width: u32, height: u32, max_levels: u32) -> u32 {
+    let largest = width.max(height).max(1);
+    (31 - largest.leading_zeros()).min(max_levels)
+}
+
+/// Pixel rectangle `[left, right) x [bottom, top)`.
+#[derive(Clone, Copy)]
+struct PixelRect {
+    left: u32,
+    bottom: u32,
+    right: u32,
+    top: u32,
+}
+
+impl PixelRect {
+    fn from_bounds(bounds: &Rect, width: u32, height: u32) -> Self {
+        Self {
+            left: (bounds.left.floor().max(0.0) as u32).min(width),
+            bottom: (bounds.bottom.floor().max(0.0) as u32).min(height),
+            right: (bounds.right.ceil().max(0.0) as u32).min(width),
+            top: (bounds.top.ceil().max(0.0) as u32).min(height),
+        }
+    }
+
+    fn halved(self) -> Self {
+        Self {
+            left: self.left / 2,
+            bottom: self.bottom / 2,
+            right: self.right.div_ceil(2),
+            top: self.top.div_ceil(2),
+        }
+    }
+
+    fn contains(&self, x: u32, y: u32) -> bool {
+        (self.left..self.right).contains(&x)
+            && (self.bottom..self.top).contains(&y)
+    }
+}
+
+/// Generates `levels` downsampled copies of `image`. `glyph_bounds` are the
+/// image bounds of the glyphs on this image, in pixels.
+pub fn mip_chain(
+    image: &Image,
+    glyph_bounds: &[Rect],
+    levels: u32,
+) -> Vec<Image> {
+    let channels = image.channels as usize;
+    let mut width = image.width;
+    let mut height = image.height;
+    let mut values = to_floats(image);
+    let mut rects: Vec<PixelRect> = glyph_bounds
+        .iter()
+        .filter(|b| !b.is_empty())
+        .map(|b| PixelRect::from_bounds(b, width, height))
+        .collect();
+
+    let mut chain = Vec::with_capacity(levels as usize);
+    for _ in 0..levels {
+        let next_width = (width / 2).max(1);
+        let next_height = (height / 2).max(1);
+        let texel = |x: u32, y: u32, c: usize| {
+            values[(y * width + x) as usize * channels + c]
+        };
+
+        // Background: plain box filter over the whole image.
+        let mut next =
+            vec![0.0; (next_width * next_height) as usize * channels];
+        for y in 0..next_height {
+            for x in 0..next_width {
+                average_block(
+                    &mut next,
+                    x,
+                    y,
+                    next_width,
+                    channels,
+                    |sx, sy| sx < width && sy < height,
+                    texel,
+                );
+            }
+        }
+
+        // Glyphs: only from texels inside the glyph rectangle.
+        for rect in &mut rects {
+            let next_rect = rect.halved();
+            for y in next_rect.bottom..next_rect.top.min(next_height) {
+                for x in next_rect.left..next_rect.right.min(next_width) {
+                    average_block(
+                        &mut next,
+                        x,
+                        y,
+                        next_width,
+                        channels,
+                        |sx, sy| rect.contains(sx, sy),
+                        texel,
+                    );
+                }
+            }
+            *rect = next_rect;
+        }
+
+        values = next;
+        width = next_width;
+        height = next_height;
+        chain.push(Image {
+            width,
+            height,
+            data: from_floats(&values, image.pixel_format),
+            metadata: String::new(),
+            ..image.clone()
+        });
+    }
+    chain
+}
+
+/// Writes the average of the texels of the 2x2 block under `(x, y)` which
+/// pass `include`. Leaves the texel alone if none does.
+fn average_block(
+    next: &mut [f32],
+    x: u32,
+    y: u32,
+    next_width: u32,
+    channels: usize,
+    include: impl Fn(u32, u32) -> bool,
+    texel: impl Fn(u32, u32, usize) -> f32,
+) {
+    let mut sum = [0.0f32; 4];
+    let mut count = 0;
+    for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        let (sx, sy) = (2 * x + sx, 2 * y + sy);
+        if include(sx, sy) {
+            for (c, s) in sum.iter_mut().enumerate().take(channels) {
+                *s += texel(sx, sy, c);
+            }
+            count += 1;
+        }
+    }
+    if count > 0 {
+        let start = (y * next_width + x) as usize * channels;
+        for c in 0..channels {
+            next[start + c] = sum[c] / count as f32;
+        }
+    }
+}
+
+fn to_floats(image: &Image) -> Vec<f32> {
+    match image.pixel_format {
+        PixelFormat::Float32 => image
+            .data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        _ => image.data.iter().map(|&v| v as f32).collect(),
+    }
+}
+
+fn from_floats(values: &[f32], pixel_format: PixelFormat) -> Vec<u8> {
+    match pixel_format {
+        PixelFormat::Float32 => {
+            values.iter().flat_map(|v| v.to_le_bytes()).collect()
+        }
+        _ => values.iter().map(|v| v.round() as u8).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use artery_font::ImageType;
+
+    fn image(width: u32, height: u32, data: Vec<u8>) -> Image {
+        Image {
+            flags: 0,
+            width,
+            height,
+            channels: 1,
+            pixel_format: PixelFormat::Unsigned8,
+            image_type: ImageType::Sdf,
+            child_images: 0,
+            texture_flags: 0,
+            metadata: String::new(),
+            data,
+        }
+    }
+
+    #[test]
+    fn levels_stop_at_one_pixel() {
+        assert_eq!(level_count(256, 64, 10), 8);
+        assert_eq!(level_count(300, 5, 3), 3);
+        assert_eq!(level_count(1, 1, 4), 0);
+        assert_eq!(level_count(0, 0, 4), 0);
+        assert_eq!(mip_padding(3), 8);
+    }
+
+    #[test]
+    fn background_is_box_filtered() {
+        let data = (0..16).map(|i| i * 10).collect();
+        let chain = mip_chain(&image(4, 4, data), &[], 2);
+        assert_eq!(chain.len(), 2);
+        assert_eq!((chain[0].width, chain[0].height), (2, 2));
+        assert_eq!(chain[0].data, [25, 45, 105, 125]);
+        assert_eq!((chain[1].width, chain[1].height), (1, 1));
+        assert_eq!(chain[1].data, [75]);
+    }
+
+    #[test]
+    fn glyphs_are_downsampled_from_their_own_texels() {
+        // A glyph one texel wide, the next level would average it with the
+        // background next to it.
+        let data = vec![200, 0, 0, 0, 200, 0, 0, 0];
+        let bounds = Rect {
+            left: 0.0,
+            bottom: 0.0,
+            right: 1.0,
+            top: 2.0,
+        };
+        let chain = mip_chain(&image(4, 2, data), &[bounds], 1);
+        assert_eq!(chain[0].data, [200, 0]);
+    }
+
+    #[test]
+    fn float_atlases_keep_their_values() {
+        let data = [-1.0f32, 1.0, 3.0, 5.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let image = Image {
+            pixel_format: PixelFormat::Float32,
+            ..image(2, 2, data)
+        };
+        let chain = mip_chain(&image, &[], 1);
+        assert_eq!(chain[0].pixel_format, PixelFormat::Float32);
+        assert_eq!(chain[0].data, 2.0f32.to_le_bytes());
+    }
+}
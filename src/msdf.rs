@@ -0,0 +1,755 @@
+use artery_font::Rect;
+use nalgebra::Vector2;
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+type Point = Vector2<f64>;
+
+// Edge colors are bit masks of the channels an edge contributes to.
+const BLACK: u8 = 0b000;
+const RED: u8 = 0b001;
+const GREEN: u8 = 0b010;
+const BLUE: u8 = 0b100;
+const YELLOW: u8 = RED | GREEN;
+const MAGENTA: u8 = RED | BLUE;
+const CYAN: u8 = GREEN | BLUE;
+const WHITE: u8 = RED | GREEN | BLUE;
+
+/// Sine of the angle under which a contour turn counts as a corner.
+const CORNER_THRESHOLD: f64 = 0.141;
+
+fn cross(a: Point, b: Point) -> f64 {
+    a.x * b.y - a.y * b.x
+}
+
+fn non_zero_sign(n: f64) -> f64 {
+    if n > 0.0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Unit vector in the direction of `v`, or zero for a zero `v` instead of
+/// NaN.
+fn normalize_or_zero(v: Point) -> Point {
+    if v == Point::zeros() {
+        v
+    } else {
+        v.normalize()
+    }
+}
+
+fn median(a: f64, b: f64, c: f64) -> f64 {
+    a.min(b).max(a.max(b).min(c))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SignedDistance {
+    distance: f64,
+    // Tie breaker between edges which are equally far away.
+    dot: f64,
+}
+
+impl SignedDistance {
+    const INFINITE: Self = Self {
+        distance: f64::MAX,
+        dot: 1.0,
+    };
+
+    fn is_closer_than(&self, other: &Self) -> bool {
+        let (a, b) = (self.distance.abs(), other.distance.abs());
+        a < b || (a == b && self.dot < other.dot)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Segment {
+    Linear(Point, Point),
+    Quadratic(Point, Point, Point),
+    Cubic(Point, Point, Point, Point),
+}
+
+impl Segment {
+    fn start(&self) -> Point {
+        match *self {
+            Segment::Linear(a, _)
+            | Segment::Quadratic(a, _, _)
+            | Segment::Cubic(a, _, _, _) => a,
+        }
+    }
+
+    fn end(&self) -> Point {
+        match *self {
+            Segment::Linear(_, b) => b,
+            Segment::Quadratic(_, _, c) => c,
+            Segment::Cubic(_, _, _, d) => d,
+        }
+    }
+
+    /// Whether every point of the segment is the same, which leaves it
+    /// without a direction.
+    fn is_degenerate(&self) -> bool {
+        let a = self.start();
+        match *self {
+            Segment::Linear(_, b) => b == a,
+            Segment::Quadratic(_, b, c) => b == a && c == a,
+            Segment::Cubic(_, b, c, d) => b == a && c == a && d == a,
+        }
+    }
+
+    fn point(&self, t: f64) -> Point {
+        let s = 1.0 - t;
+        match *self {
+            Segment::Linear(a, b) => a * s + b * t,
+            Segment::Quadratic(a, b, c) => {
+                a * (s * s) + b * (2.0 * s * t) + c * (t * t)
+            }
+            Segment::Cubic(a, b, c, d) => {
+                a * (s * s * s)
+                    + b * (3.0 * s * s * t)
+                    + c * (3.0 * s * t * t)
+                    + d * (t * t * t)
+            }
+        }
+    }
+
+    fn direction(&self, t: f64) -> Point {
+        let s = 1.0 - t;
+        match *self {
+            Segment::Linear(a, b) => b - a,
+            Segment::Quadratic(a, b, c) => {
+                let d = (b - a) * (2.0 * s) + (c - b) * (2.0 * t);
+                if d == Point::zeros() {
+                    c - a
+                } else {
+                    d
+                }
+            }
+            Segment::Cubic(a, b, c, d) => {
+                let dir = (b - a) * (3.0 * s * s)
+                    + (c - b) * (6.0 * s * t)
+                    + (d - c) * (3.0 * t * t);
+                let fallback = if t < 0.5 { c - a } else { d - b };
+                if dir != Point::zeros() {
+                    dir
+                } else if fallback != Point::zeros() {
+                    fallback
+                } else {
+                    d - a
+                }
+            }
+        }
+    }
+
+    fn second_derivative(&self, t: f64) -> Point {
+        match *self {
+            Segment::Linear(..) => Point::zeros(),
+            Segment::Quadratic(a, b, c) => (a - b * 2.0 + c) * 2.0,
+            Segment::Cubic(a, b, c, d) => {
+                (c - b * 2.0 + a) * (6.0 * (1.0 - t))
+                    + (d - c * 2.0 + b) * (6.0 * t)
+            }
+        }
+    }
+
+    fn reversed(&self) -> Self {
+        match *self {
+            Segment::Linear(a, b) => Segment::Linear(b, a),
+            Segment::Quadratic(a, b, c) => Segment::Quadratic(c, b, a),
+            Segment::Cubic(a, b, c, d) => Segment::Cubic(d, c, b, a),
+        }
+    }
+
+    fn split_in_thirds(&self) -> [Segment; 3] {
+        // Control points of a sub-curve follow from the end tangents.
+        let part = |t0: f64, t1: f64| {
+            let (s, e) = (self.point(t0), self.point(t1));
+            match self {
+                Segment::Linear(..) => Segment::Linear(s, e),
+                Segment::Quadratic(..) => Segment::Quadratic(
+                    s,
+                    s + self.direction(t0) * ((t1 - t0) / 2.0),
+                    e,
+                ),
+                Segment::Cubic(..) => Segment::Cubic(
+                    s,
+                    s + self.direction(t0) * ((t1 - t0) / 3.0),
+                    e - self.direction(t1) * ((t1 - t0) / 3.0),
+                    e,
+                ),
+            }
+        };
+        [
+            part(0.0, 1.0 / 3.0),
+            part(1.0 / 3.0, 2.0 / 3.0),
+            part(2.0 / 3.0, 1.0),
+        ]
+    }
+
+    /// Parameter of the point on the segment closest to `p`.
+    fn closest_param(&self, p: Point) -> f64 {
+        let samples = match self {
+            Segment::Linear(a, b) => {
+                let ab = b - a;
+                if ab == Point::zeros() {
+                    return 0.0;
+                }
+                return ((p - a).dot(&ab) / ab.norm_squared()).clamp(0.0, 1.0);
+            }
+            Segment::Quadratic(..) => 8,
+            Segment::Cubic(..) => 16,
+        };
+
+        let mut t = 0.0;
+        let mut best = f64::MAX;
+        for i in 0..=samples {
+            let s = i as f64 / samples as f64;
+            let d = (self.point(s) - p).norm_squared();
+            if d < best {
+                best = d;
+                t = s;
+            }
+        }
+        // Newton's method on the derivative of the squared distance.
+        for _ in 0..4 {
+            let q = self.point(t) - p;
+            let d1 = self.direction(t);
+            let d2 = self.second_derivative(t);
+            let denominator = d1.dot(&d1) + q.dot(&d2);
+            if denominator.abs() < 1e-12 {
+                break;
+            }
+            t = (t - q.dot(&d1) / denominator).clamp(0.0, 1.0);
+        }
+        t
+    }
+
+    fn signed_distance(&self, p: Point) -> (SignedDistance, f64) {
+        let t = self.closest_param(p);
+        let q = self.point(t) - p;
+        let dir = self.direction(t);
+        let distance = non_zero_sign(cross(dir, q)) * q.norm();
+        let dot = if t > 0.0 && t < 1.0 || q == Point::zeros() {
+            0.0
+        } else {
+            normalize_or_zero(dir).dot(&q.normalize()).abs()
+        };
+        (SignedDistance { distance, dot }, t)
+    }
+
+    /// Extends the segment along its end tangents, which keeps corners
+    /// sharp when the channels are combined.
+    fn apply_pseudo_distance(
+        &self,
+        distance: &mut SignedDistance,
+        p: Point,
+        t: f64,
+    ) {
+        let (origin, dir, behind) = if t <= 0.0 {
+            let dir = normalize_or_zero(self.direction(0.0));
+            (self.start(), dir, (p - self.start()).dot(&dir) < 0.0)
+        } else if t >= 1.0 {
+            let dir = normalize_or_zero(self.direction(1.0));
+            (self.end(), dir, (p - self.end()).dot(&dir) > 0.0)
+        } else {
+            return;
+        };
+        if behind {
+            let pseudo = cross(p - origin, dir);
+            if pseudo.abs() <= distance.distance.abs() {
+                distance.distance = pseudo;
+                distance.dot = 0.0;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    segment: Segment,
+    color: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Shape {
+    contours: Vec<Vec<Edge>>,
+}
+
+impl Shape {
+    /// Loads the outline of a glyph in font units. Returns `None` for glyphs
+    /// without an outline, like the space.
+    pub fn from_glyph(face: &Face, glyph: GlyphId) -> Option<Self> {
+        let mut builder = ShapeBuilder::default();
+        face.outline_glyph(glyph, &mut builder)?;
+        builder.close();
+        Self::from_contours(builder.contours)
+    }
+
+    /// Colors the edges of the contours, after making them run clockwise.
+    /// Returns `None` if there are no edges.
+    fn from_contours(contours: Vec<Vec<Segment>>) -> Option<Self> {
+        let mut shape = Self {
+            contours: contours
+                .into_iter()
+                .filter(|c| !c.is_empty())
+                .map(|c| {
+                    c.into_iter()
+                        .map(|segment| Edge {
+                            segment,
+                            color: WHITE,
+                        })
+                        .collect()
+                })
+                .collect(),
+        };
+        if shape.contours.is_empty() {
+            return None;
+        }
+        shape.normalize_orientation();
+        shape.color_edges();
+        Some(shape)
+    }
+
+    fn polylines(&self) -> Vec<Vec<Point>> {
+        self.contours
+            .iter()
+            .map(|contour| {
+                let mut points = Vec::new();
+                for edge in contour {
+                    let steps = match edge.segment {
+                        Segment::Linear(..) => 1,
+                        _ => 8,
+                    };
+                    for i in 0..steps {
+                        points
+                            .push(edge.segment.point(i as f64 / steps as f64));
+                    }
+                }
+                points
+            })
+            .collect()
+    }
+
+    /// Makes outer contours run clockwise, so the inside of the shape is
+    /// always to the right of the edges and gets a positive distance.
+    fn normalize_orientation(&mut self) {
+        let area: f64 = self
+            .polylines()
+            .iter()
+            .map(|points| {
+                points
+                    .iter()
+                    .zip(points.iter().cycle().skip(1))
+                    .map(|(a, b)| cross(*a, *b))
+                    .sum::<f64>()
+            })
+            .sum();
+        if area > 0.0 {
+            for contour in &mut self.contours {
+                contour.reverse();
+                for edge in contour.iter_mut() {
+                    edge.segment = edge.segment.reversed();
+                }
+            }
+        }
+    }
+
+    /// Assigns channels to edges so that every corner is formed by two
+    /// edges of different colors.
+    fn color_edges(&mut self) {
+        for contour in &mut self.contours {
+            let corners: Vec<usize> = (0..contour.len())
+                .filter(|&i| {
+                    let prev = if i == 0 { contour.len() - 1 } else { i - 1 };
+                    let a =
+                        normalize_or_zero(contour[prev].segment.direction(1.0));
+                    let b =
+                        normalize_or_zero(contour[i].segment.direction(0.0));
+                    a.dot(&b) <= 0.0 || cross(a, b).abs() > CORNER_THRESHOLD
+                })
+                .collect();
+
+            match corners.len() {
+                0 => contour.iter_mut().for_each(|e| e.color = WHITE),
+                1 => {
+                    // A "teardrop" contour: split it into three colored
+                    // parts so the single corner stays sharp.
+                    let colors = [MAGENTA, WHITE, YELLOW];
+                    let corner = corners[0];
+                    if contour.len() < 3 {
+                        let mut parts = Vec::new();
+                        for e in contour
+                            .iter()
+                            .cycle()
+                            .skip(corner)
+                            .take(contour.len())
+                        {
+                            parts.extend(e.segment.split_in_thirds());
+                        }
+                        let n = parts.len();
+                        *contour = parts
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, segment)| Edge {
+                                segment,
+                                color: colors[3 * i / n],
+                            })
+                            .collect();
+                    } else {
+                        let n = contour.len();
+                        for i in 0..n {
+                            contour[(corner + i) % n].color = colors[3 * i / n];
+                        }
+                    }
+                }
+                count => {
+                    let n = contour.len();
+                    let mut color = switch_color(WHITE, BLACK);
+                    let initial = color;
+                    let mut spline = 0;
+                    for i in 0..n {
+                        let index = (corners[0] + i) % n;
+                        if spline + 1 < count && corners[spline + 1] == index {
+                            spline += 1;
+                            let banned = if spline == count - 1 {
+                                initial
+                            } else {
+                                BLACK
+                            };
+                            color = switch_color(color, banned);
+                        }
+                        contour[index].color = color;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Generates a multi-channel signed distance field of the shape as
+    /// RGBA8 pixels, bottom row first.
+    ///
+    /// A pixel at `(x, y)` samples the shape at `(x + 0.5, y + 0.5) / scale
+    /// - translate`, and `range` is the distance in shape units that spans
+    /// the whole `0..=255` value range.
+    pub fn generate(
+        &self,
+        width: usize,
+        height: usize,
+        scale: f64,
+        translate: Vector2<f64>,
+        range: f64,
+    ) -> Vec<u8> {
+        let polylines = self.polylines();
+        let edges: Vec<&Edge> = self.contours.iter().flatten().collect();
+        let to_byte =
+            |d: f64| ((d / range + 0.5).clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let p = Point::new(x as f64 + 0.5, y as f64 + 0.5) / scale
+                    - translate;
+
+                let mut channels = [(SignedDistance::INFINITE, None); 3];
+                let mut closest = SignedDistance::INFINITE;
+                for edge in &edges {
+                    let (distance, t) = edge.segment.signed_distance(p);
+                    if distance.is_closer_than(&closest) {
+                        closest = distance;
+                    }
+                    for (i, channel) in channels.iter_mut().enumerate() {
+                        if edge.color & (1 << i) != 0
+                            && distance.is_closer_than(&channel.0)
+                        {
+                            *channel = (distance, Some((edge, t)));
+                        }
+                    }
+                }
+
+                let mut rgb = [0.0; 3];
+                for (value, (mut distance, edge)) in
+                    rgb.iter_mut().zip(channels)
+                {
+                    if let Some((edge, t)) = edge {
+                        edge.segment.apply_pseudo_distance(&mut distance, p, t);
+                    }
+                    *value = distance.distance;
+                }
+
+                // Pixels where the channels disagree with the actual fill
+                // would produce artifacts, fall back to a plain SDF there.
+                let inside = winding(&polylines, p) != 0;
+                if (median(rgb[0], rgb[1], rgb[2]) > 0.0) != inside {
+                    let sdf = if inside {
+                        closest.distance.abs()
+                    } else {
+                        -closest.distance.abs()
+                    };
+                    rgb = [sdf; 3];
+                }
+
+                pixels.extend(rgb.iter().map(|d| to_byte(*d)));
+                pixels.push(255);
+            }
+        }
+        pixels
+    }
+}
+
+/// MSDF of a single glyph and the area it covers.
+pub struct GlyphBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA8 pixels, bottom row first.
+    pub pixels: Vec<u8>,
+    /// Area covered by the bitmap in ems, relative to the glyph origin.
+    pub plane_bounds: Rect,
+}
+
+impl GlyphBitmap {
+    /// Rasterizes a glyph at `px_per_em` with a distance range of `range`
+    /// pixels. Returns `None` for glyphs without an outline.
+    pub fn rasterize(
+        face: &Face,
+        glyph: GlyphId,
+        px_per_em: f32,
+        range: f32,
+    ) -> Option<Self> {
+        let shape = Shape::from_glyph(face, glyph)?;
+        let bbox = face.glyph_bounding_box(glyph)?;
+
+        let units_per_em = face.units_per_em() as f32;
+        let scale = px_per_em / units_per_em;
+        let width = (bbox.width() as f32 * scale + 2.0 * range).ceil() as u32;
+        let height = (bbox.height() as f32 * scale + 2.0 * range).ceil() as u32;
+
+        let translate = Vector2::new(
+            (range / scale - bbox.x_min as f32) as f64,
+            (range / scale - bbox.y_min as f32) as f64,
+        );
+        let pixels = shape.generate(
+            width as usize,
+            height as usize,
+            scale as f64,
+            translate,
+            (range / scale) as f64,
+        );
+
+        let left = bbox.x_min as f32 / units_per_em - range / px_per_em;
+        let bottom = bbox.y_min as f32 / units_per_em - range / px_per_em;
+        Some(Self {
+            width,
+            height,
+            pixels,
+            plane_bounds: Rect {
+                left,
+                bottom,
+                right: left + width as f32 / px_per_em,
+                top: bottom + height as f32 / px_per_em,
+            },
+        })
+    }
+
+    /// Cuts off the right and top of the bitmap so it is at most `width` x
+    /// `height` pixels big.
+    pub fn crop(&mut self, width: u32, height: u32) {
+        let (width, height) = (width.min(self.width), height.min(self.height));
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+        let row = 4 * width as usize;
+        let old_row = 4 * self.width as usize;
+        self.pixels = self
+            .pixels
+            .chunks_exact(old_row)
+            .take(height as usize)
+            .flat_map(|r| &r[..row])
+            .copied()
+            .collect();
+
+        let bounds = &mut self.plane_bounds;
+        bounds.right = bounds.left
+            + (bounds.right - bounds.left) * width as f32 / self.width as f32;
+        bounds.top = bounds.bottom
+            + (bounds.top - bounds.bottom) * height as f32 / self.height as f32;
+        self.width = width;
+        self.height = height;
+    }
+}
+
+fn switch_color(color: u8, banned: u8) -> u8 {
+    let combined = color & banned;
+    if combined == RED || combined == GREEN || combined == BLUE {
+        return combined ^ WHITE;
+    }
+    if color == BLACK || color == WHITE {
+        return CYAN;
+    }
+    let shifted = color << 1;
+    (shifted | shifted >> 3) & WHITE
+}
+
+/// Non-zero winding number of the flattened contours around `p`.
+fn winding(polylines: &[Vec<Point>], p: Point) -> i32 {
+    let mut winding = 0;
+    for points in polylines {
+        for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+            if a.y <= p.y {
+                if b.y > p.y && cross(b - a, p - a) > 0.0 {
+                    winding += 1;
+                }
+            } else if b.y <= p.y && cross(b - a, p - a) < 0.0 {
+                winding -= 1;
+            }
+        }
+    }
+    winding
+}
+
+#[derive(Default)]
+struct ShapeBuilder {
+    contours: Vec<Vec<Segment>>,
+    current: Vec<Segment>,
+    start: Point,
+    last: Point,
+}
+
+impl ShapeBuilder {
+    /// Adds `segment` unless it collapses to a single point.
+    fn push(&mut self, segment: Segment, to: Point) {
+        if !segment.is_degenerate() {
+            self.current.push(segment);
+        }
+        self.last = to;
+    }
+}
+
+impl OutlineBuilder for ShapeBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.close();
+        self.start = Point::new(x as f64, y as f64);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let to = Point::new(x as f64, y as f64);
+        self.push(Segment::Linear(self.last, to), to);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let to = Point::new(x as f64, y as f64);
+        let control = Point::new(x1 as f64, y1 as f64);
+        self.push(Segment::Quadratic(self.last, control, to), to);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let to = Point::new(x as f64, y as f64);
+        let c1 = Point::new(x1 as f64, y1 as f64);
+        let c2 = Point::new(x2 as f64, y2 as f64);
+        self.push(Segment::Cubic(self.last, c1, c2, to), to);
+    }
+
+    fn close(&mut self) {
+        if self.last != self.start {
+            self.current.push(Segment::Linear(self.last, self.start));
+            self.last = self.start;
+        }
+        if !self.current.is_empty() {
+            self.contours.push(std::mem::take(&mut self.current));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Square from 0 to 10, counterclockwise if `ccw`.
+    fn square(ccw: bool) -> Vec<Segment> {
+        let corners = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]
+            .map(|(x, y)| Point::new(x, y));
+        let mut builder = ShapeBuilder::default();
+        let mut points: Vec<_> = corners.to_vec();
+        if !ccw {
+            points.reverse();
+        }
+        builder.move_to(points[0].x as f32, points[0].y as f32);
+        for p in &points[1..] {
+            builder.line_to(p.x as f32, p.y as f32);
+        }
+        builder.close();
+        builder.contours.remove(0)
+    }
+
+    /// Median distance of the pixels in a 20 x 20 field around the shape,
+    /// with a range of 4 units.
+    fn field(shape: &Shape) -> Vec<u8> {
+        shape
+            .generate(20, 20, 1.0, Vector2::new(5.0, 5.0), 4.0)
+            .chunks_exact(4)
+            .map(|p| {
+                let (r, g, b) = (p[0] as f64, p[1] as f64, p[2] as f64);
+                median(r, g, b) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn square_is_inside_in_the_middle() {
+        let shape = Shape::from_contours(vec![square(false)]).unwrap();
+        let field = field(&shape);
+        let at = |x: usize, y: usize| field[y * 20 + x];
+
+        assert_eq!(at(10, 10), 255);
+        assert_eq!(at(0, 0), 0);
+        // Half a unit inside and outside of the left edge
+        assert_eq!(at(5, 10), 159);
+        assert_eq!(at(4, 10), 96);
+        // The corner stays sharp
+        assert!(at(5, 5) > 127 && at(4, 4) < 128);
+    }
+
+    #[test]
+    fn orientation_is_normalized() {
+        let clockwise = Shape::from_contours(vec![square(false)]).unwrap();
+        let counterclockwise =
+            Shape::from_contours(vec![square(true)]).unwrap();
+        assert_eq!(field(&clockwise), field(&counterclockwise));
+    }
+
+    #[test]
+    fn degenerate_segments_are_dropped() {
+        let mut builder = ShapeBuilder::default();
+        builder.move_to(0.0, 0.0);
+        builder.line_to(0.0, 10.0);
+        builder.quad_to(0.0, 10.0, 0.0, 10.0);
+        builder.curve_to(0.0, 10.0, 0.0, 10.0, 0.0, 10.0);
+        builder.line_to(10.0, 10.0);
+        builder.line_to(10.0, 0.0);
+        builder.close();
+        assert_eq!(builder.contours[0].len(), 4);
+        let shape = Shape::from_contours(builder.contours).unwrap();
+        let plain = Shape::from_contours(vec![square(false)]).unwrap();
+        assert_eq!(field(&shape), field(&plain));
+    }
+
+    #[test]
+    fn degenerate_segments_have_no_nan_distance() {
+        let p = Point::zeros();
+        let point = Point::new(3.0, 4.0);
+        for segment in [
+            Segment::Linear(point, point),
+            Segment::Quadratic(point, point, point),
+            Segment::Cubic(point, point, point, point),
+        ] {
+            let (mut distance, t) = segment.signed_distance(p);
+            assert_eq!(distance.distance.abs(), 5.0);
+            assert!(!distance.dot.is_nan());
+            segment.apply_pseudo_distance(&mut distance, p, t);
+            assert!(!distance.distance.is_nan());
+        }
+        // Only the end differs, the start has a direction all the same
+        let cubic = Segment::Cubic(point, point, point, Point::zeros());
+        assert_ne!(cubic.direction(0.0), Point::zeros());
+    }
+}
@@ -0,0 +1,791 @@
+//! Rectangle packing for atlas layouts. Independent of wgpu, so it can be
+//! used by atlas generators as well as at runtime.
+//!
+//! Packing is deterministic: the same input always produces the same layout.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Fast, keeps only the top outline of the placed rectangles.
+    Skyline,
+    /// Slower, tracks every free rectangle and usually packs tighter.
+    MaxRects(Heuristic),
+}
+
+/// How [`Strategy::MaxRects`] chooses between free rectangles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heuristic {
+    BestShortSideFit,
+    BestLongSideFit,
+    BestAreaFit,
+    BottomLeft,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    /// Every page has exactly this size.
+    Fixed { width: u32, height: u32 },
+    /// Pages start small and grow in powers of two up to `max`.
+    PowerOfTwo { max: u32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PackerSettings {
+    pub strategy: Strategy,
+    /// Minimal gap in pixels between two rectangles and between a rectangle
+    /// and the page border.
+    pub padding: u32,
+    pub page_size: PageSize,
+    /// Rectangles which don't fit into this many pages are left unplaced.
+    pub max_pages: Option<usize>,
+}
+
+impl Default for PackerSettings {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::MaxRects(Heuristic::BestShortSideFit),
+            padding: 1,
+            page_size: PageSize::PowerOfTwo { max: 4096 },
+            max_pages: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    /// Index of the rectangle in the slice given to [`pack`].
+    pub id: usize,
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PackResult {
+    pub pages: Vec<Page>,
+    /// Placements ordered by rectangle id.
+    pub placements: Vec<Placement>,
+    /// Ids of rectangles which didn't fit.
+    pub unplaced: Vec<usize>,
+}
+
+/// Packs rectangles given as `(width, height)` into one or more pages.
+pub fn pack(sizes: &[(u32, u32)], settings: &PackerSettings) -> PackResult {
+    let padding = settings.padding;
+    let (max_width, max_height) = match settings.page_size {
+        PageSize::Fixed { width, height } => (width, height),
+        PageSize::PowerOfTwo { max } => (max, max),
+    };
+
+    // Big rectangles first, ties are broken by id to stay deterministic.
+    let mut remaining: Vec<usize> = (0..sizes.len()).collect();
+    remaining.sort_by_key(|&id| {
+        let (w, h) = sizes[id];
+        (std::cmp::Reverse((w.max(h), w as u64 * h as u64)), id)
+    });
+
+    // In u64, so huge sizes or paddings end up unplaced instead of
+    // overflowing.
+    let padded = |size: u32| size as u64 + 2 * padding as u64;
+    let mut result = PackResult::default();
+    remaining.retain(|&id| {
+        let (w, h) = sizes[id];
+        let fits =
+            padded(w) <= max_width as u64 && padded(h) <= max_height as u64;
+        if !fits {
+            result.unplaced.push(id);
+        }
+        fits
+    });
+
+    let max_pages = settings.max_pages.unwrap_or(usize::MAX);
+    while !remaining.is_empty() && result.pages.len() < max_pages {
+        let (page, placed, leftover) = match settings.page_size {
+            PageSize::Fixed { width, height } => {
+                let (placed, leftover) =
+                    pack_page(width, height, sizes, &remaining, settings);
+                (Page { width, height }, placed, leftover)
+            }
+            PageSize::PowerOfTwo { max } => {
+                pack_growing_page(max, sizes, &remaining, settings)
+            }
+        };
+
+        let index = result.pages.len();
+        result.pages.push(page);
+        result
+            .placements
+            .extend(placed.into_iter().map(|(id, x, y)| Placement {
+                id,
+                page: index,
+                x,
+                y,
+                width: sizes[id].0,
+                height: sizes[id].1,
+            }));
+        remaining = leftover;
+    }
+
+    result.unplaced.extend(remaining);
+    result.unplaced.sort_unstable();
+    result.placements.sort_by_key(|p| p.id);
+    result
+}
+
+/// Packs a page which starts as the smallest power of two fitting the
+/// biggest rectangle and doubles its smaller side until everything fits.
+fn pack_growing_page(
+    max: u32,
+    sizes: &[(u32, u32)],
+    ids: &[usize],
+    settings: &PackerSettings,
+) -> (Page, Vec<(usize, u32, u32)>, Vec<usize>) {
+    let padding = 2 * settings.padding;
+    let widest = ids.iter().map(|&id| sizes[id].0 + padding).max().unwrap();
+    let tallest = ids.iter().map(|&id| sizes[id].1 + padding).max().unwrap();
+    let area: u64 = ids
+        .iter()
+        .map(|&id| {
+            (sizes[id].0 + padding) as u64 * (sizes[id].1 + padding) as u64
+        })
+        .sum();
+
+    let mut width = widest.next_power_of_two().min(max);
+    let mut height = tallest.next_power_of_two().min(max);
+    loop {
+        let at_max = width == max && height == max;
+        if width as u64 * height as u64 >= area || at_max {
+            let (placed, leftover) =
+                pack_page(width, height, sizes, ids, settings);
+            if leftover.is_empty() || at_max {
+                return (Page { width, height }, placed, leftover);
+            }
+        }
+        if width <= height && width < max || height == max {
+            width = (width * 2).min(max);
+        } else {
+            height = (height * 2).min(max);
+        }
+    }
+}
+
+fn pack_page(
+    width: u32,
+    height: u32,
+    sizes: &[(u32, u32)],
+    ids: &[usize],
+    settings: &PackerSettings,
+) -> (Vec<(usize, u32, u32)>, Vec<usize>) {
+    let padding = settings.padding;
+    // Every rectangle reserves the padding on its right and top, the bin is
+    // shifted by the padding so the left and bottom border get it as well.
+    let (bin_width, bin_height) = (width - padding, height - padding);
+    let mut bin: Box<dyn Bin> = match settings.strategy {
+        Strategy::Skyline => Box::new(SkylineBin::new(bin_width, bin_height)),
+        Strategy::MaxRects(heuristic) => {
+            Box::new(MaxRectsBin::new(bin_width, bin_height, heuristic))
+        }
+    };
+
+    let mut placed = Vec::new();
+    let mut leftover = Vec::new();
+    for &id in ids {
+        let (w, h) = sizes[id];
+        match bin.insert(w + padding, h + padding) {
+            Some((x, y)) => placed.push((id, x + padding, y + padding)),
+            None => leftover.push(id),
+        }
+    }
+    (placed, leftover)
+}
+
+/// A single page which rectangles are inserted into one by one.
+pub trait Bin {
+    /// Returns the position of the rectangle or `None` if it doesn't fit.
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)>;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SkylineNode {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+/// Bottom-left skyline packer.
+#[derive(Debug, Clone)]
+pub struct SkylineBin {
+    width: u32,
+    height: u32,
+    skyline: Vec<SkylineNode>,
+}
+
+impl SkylineBin {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            skyline: vec![SkylineNode { x: 0, y: 0, width }],
+        }
+    }
+
+    /// Height at which a rectangle starting at node `index` would sit.
+    fn fit(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.skyline[index].x;
+        if x + width > self.width {
+            return None;
+        }
+        let mut y = 0;
+        let mut covered = 0;
+        for node in &self.skyline[index..] {
+            if covered >= width {
+                break;
+            }
+            y = y.max(node.y);
+            covered += node.width - (x.max(node.x) - node.x);
+        }
+        (y + height <= self.height).then_some(y)
+    }
+}
+
+impl Bin for SkylineBin {
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (index, y) = (0..self.skyline.len())
+            .filter_map(|i| self.fit(i, width, height).map(|y| (i, y)))
+            .min_by_key(|&(i, y)| (y + height, self.skyline[i].x))?;
+        let x = self.skyline[index].x;
+
+        self.skyline.insert(
+            index,
+            SkylineNode {
+                x,
+                y: y + height,
+                width,
+            },
+        );
+        // Shrink or remove the nodes now covered by the new one.
+        let end = x + width;
+        let mut i = index + 1;
+        while i < self.skyline.len() && self.skyline[i].x < end {
+            let node = &mut self.skyline[i];
+            let node_end = node.x + node.width;
+            if node_end <= end {
+                self.skyline.remove(i);
+            } else {
+                node.width = node_end - end;
+                node.x = end;
+                i += 1;
+            }
+        }
+        // Merge neighbours of equal height.
+        let mut i = 0;
+        while i + 1 < self.skyline.len() {
+            if self.skyline[i].y == self.skyline[i + 1].y {
+                self.skyline[i].width += self.skyline[i + 1].width;
+                self.skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+        Some((x, y))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FreeRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl FreeRect {
+    fn contains(&self, other: &FreeRect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.x + other.width <= self.x + self.width
+            && other.y + other.height <= self.y + self.height
+    }
+
+    /// Free rectangles covering both `self` and `other` where they touch or
+    /// overlap, joined side by side and joined on top of each other.
+    fn joined(&self, other: &FreeRect) -> [Option<FreeRect>; 2] {
+        let (left, right) =
+            (self.x.min(other.x), self.right().max(other.right()));
+        let (bottom, top) = (self.y.min(other.y), self.top().max(other.top()));
+        let columns_meet = self.x <= other.right() && other.x <= self.right();
+        let rows_meet = self.y <= other.top() && other.y <= self.top();
+
+        // Rows and columns both rectangles cover.
+        let (y0, y1) = (self.y.max(other.y), self.top().min(other.top()));
+        let (x0, x1) = (self.x.max(other.x), self.right().min(other.right()));
+
+        let side_by_side = (columns_meet && y1 > y0).then(|| FreeRect {
+            x: left,
+            y: y0,
+            width: right - left,
+            height: y1 - y0,
+        });
+        let stacked = (rows_meet && x1 > x0).then(|| FreeRect {
+            x: x0,
+            y: bottom,
+            width: x1 - x0,
+            height: top - bottom,
+        });
+        [side_by_side, stacked]
+    }
+
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn top(&self) -> u32 {
+        self.y + self.height
+    }
+
+    fn intersects(&self, other: &FreeRect) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+}
+
+/// MaxRects packer, keeps a list of maximal free rectangles.
+#[derive(Debug, Clone)]
+pub struct MaxRectsBin {
+    heuristic: Heuristic,
+    free: Vec<FreeRect>,
+}
+
+impl MaxRectsBin {
+    pub fn new(width: u32, height: u32, heuristic: Heuristic) -> Self {
+        Self {
+            heuristic,
+            free: vec![FreeRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }],
+        }
+    }
+
+    /// Gives the space of a rectangle inserted before back to the bin,
+    /// merged with the free space around it.
+    pub fn free(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let mut pending = vec![FreeRect {
+            x,
+            y,
+            width,
+            height,
+        }];
+        while let Some(rect) = pending.pop() {
+            if self.free.iter().any(|f| f.contains(&rect)) {
+                continue;
+            }
+            for free in &self.free {
+                pending.extend(rect.joined(free).into_iter().flatten());
+            }
+            self.free.push(rect);
+        }
+        self.prune();
+    }
+
+    /// Lower is better.
+    fn score(&self, free: &FreeRect, width: u32, height: u32) -> (u64, u64) {
+        let leftover_x = (free.width - width) as u64;
+        let leftover_y = (free.height - height) as u64;
+        let (short, long) = if leftover_x < leftover_y {
+            (leftover_x, leftover_y)
+        } else {
+            (leftover_y, leftover_x)
+        };
+        match self.heuristic {
+            Heuristic::BestShortSideFit => (short, long),
+            Heuristic::BestLongSideFit => (long, short),
+            Heuristic::BestAreaFit => (
+                free.width as u64 * free.height as u64
+                    - width as u64 * height as u64,
+                short,
+            ),
+            Heuristic::BottomLeft => ((free.y + height) as u64, free.x as u64),
+        }
+    }
+
+    fn split(&mut self, used: &FreeRect) {
+        let mut i = 0;
+        while i < self.free.len() {
+            let free = self.free[i];
+            if !free.intersects(used) {
+                i += 1;
+                continue;
+            }
+            self.free.swap_remove(i);
+            if used.x > free.x {
+                self.free.push(FreeRect {
+                    width: used.x - free.x,
+                    ..free
+                });
+            }
+            if used.x + used.width < free.x + free.width {
+                self.free.push(FreeRect {
+                    x: used.x + used.width,
+                    width: free.x + free.width - used.x - used.width,
+                    ..free
+                });
+            }
+            if used.y > free.y {
+                self.free.push(FreeRect {
+                    height: used.y - free.y,
+                    ..free
+                });
+            }
+            if used.y + used.height < free.y + free.height {
+                self.free.push(FreeRect {
+                    y: used.y + used.height,
+                    height: free.y + free.height - used.y - used.height,
+                    ..free
+                });
+            }
+        }
+        self.prune();
+    }
+
+    /// Removes free rectangles contained in other ones.
+    fn prune(&mut self) {
+        let mut i = 0;
+        while i < self.free.len() {
+            let contained = (0..self.free.len()).any(|j| {
+                i != j
+                    && self.free[j].contains(&self.free[i])
+                    && (self.free[i] != self.free[j] || j < i)
+            });
+            if contained {
+                self.free.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        // Keeps the order of the free list, and so the packing, independent
+        // of how the rectangles were split.
+        self.free.sort_by_key(|r| (r.y, r.x, r.width, r.height));
+    }
+}
+
+impl Bin for MaxRectsBin {
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let best = self
+            .free
+            .iter()
+            .filter(|r| r.width >= width && r.height >= height)
+            .min_by_key(|r| self.score(r, width, height))
+            .copied()?;
+        let used = FreeRect {
+            x: best.x,
+            y: best.y,
+            width,
+            height,
+        };
+        self.split(&used);
+        Some((used.x, used.y))
+    }
+}
+
+/// Packing efficiency of a [`PackResult`].
+#[derive(Debug, Clone)]
+pub struct PackReport {
+    /// Share of each page covered by rectangles, between 0 and 1.
+    pub page_efficiency: Vec<f32>,
+    /// Share of the area of all pages covered by rectangles.
+    pub total_efficiency: f32,
+    pub placed: usize,
+    pub unplaced: usize,
+}
+
+impl PackResult {
+    pub fn report(&self) -> PackReport {
+        let mut used = vec![0u64; self.pages.len()];
+        for p in &self.placements {
+            used[p.page] += p.width as u64 * p.height as u64;
+        }
+        let areas: Vec<u64> = self
+            .pages
+            .iter()
+            .map(|p| p.width as u64 * p.height as u64)
+            .collect();
+        let total_area: u64 = areas.iter().sum();
+
+        PackReport {
+            page_efficiency: used
+                .iter()
+                .zip(&areas)
+                .map(|(&used, &area)| used as f32 / area as f32)
+                .collect(),
+            total_efficiency: if total_area == 0 {
+                0.0
+            } else {
+                used.iter().sum::<u64>() as f32 / total_area as f32
+            },
+            placed: self.placements.len(),
+            unplaced: self.unplaced.len(),
+        }
+    }
+}
+
+impl fmt::Display for PackReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Packed {} rectangles into {} pages ({} unplaced), efficiency: {:.1}%",
+            self.placed,
+            self.page_efficiency.len(),
+            self.unplaced,
+            self.total_efficiency * 100.0
+        )?;
+        for (i, efficiency) in self.page_efficiency.iter().enumerate() {
+            writeln!(f, "  page {}: {:.1}%", i, efficiency * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small deterministic generator, so tests don't need a dependency.
+    fn sizes(count: usize, seed: u32, max: u32) -> Vec<(u32, u32)> {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) % max + 1
+        };
+        (0..count).map(|_| (next(), next())).collect()
+    }
+
+    #[test]
+    fn freed_neighbours_merge() {
+        let mut bin = MaxRectsBin::new(100, 100, Heuristic::BottomLeft);
+        let quarters: Vec<_> =
+            (0..4).map(|_| bin.insert(50, 50).unwrap()).collect();
+        assert_eq!(bin.insert(1, 1), None);
+
+        for &(x, y) in &quarters {
+            bin.free(x, y, 50, 50);
+        }
+        assert_eq!(bin.insert(100, 100), Some((0, 0)));
+    }
+
+    #[test]
+    fn freeing_everything_restores_the_bin() {
+        let mut bin = MaxRectsBin::new(256, 256, Heuristic::BestShortSideFit);
+        let mut used = Vec::new();
+        for (w, h) in sizes(200, 7, 40) {
+            if let Some((x, y)) = bin.insert(w, h) {
+                used.push((x, y, w, h));
+            }
+        }
+        // Free every other rectangle first to fragment the bin.
+        let (odd, even): (Vec<_>, Vec<_>) =
+            used.iter().enumerate().partition(|(i, _)| i % 2 == 1);
+        for (_, &(x, y, w, h)) in odd.into_iter().chain(even) {
+            bin.free(x, y, w, h);
+        }
+        assert_eq!(bin.insert(256, 256), Some((0, 0)));
+    }
+
+    #[test]
+    fn free_space_never_covers_used_space() {
+        let mut bin = MaxRectsBin::new(128, 128, Heuristic::BestAreaFit);
+        let mut used = Vec::new();
+        for (i, (w, h)) in sizes(300, 3, 24).into_iter().enumerate() {
+            if let Some((x, y)) = bin.insert(w, h) {
+                used.push(FreeRect {
+                    x,
+                    y,
+                    width: w,
+                    height: h,
+                });
+            }
+            if i % 3 == 2 && !used.is_empty() {
+                let r = used.remove(i % used.len());
+                bin.free(r.x, r.y, r.width, r.height);
+            }
+            for free in &bin.free {
+                assert!(used.iter().all(|u| !free.intersects(u)));
+            }
+        }
+    }
+
+    #[test]
+    fn huge_sizes_are_unplaced_instead_of_overflowing() {
+        let sizes = [(u32::MAX, u32::MAX), (10, 10), (u32::MAX, 1)];
+        let result = pack(&sizes, &PackerSettings::default());
+        assert_eq!(result.unplaced, vec![0, 2]);
+        assert_eq!(result.placements.len(), 1);
+
+        let settings = PackerSettings {
+            padding: u32::MAX,
+            ..PackerSettings::default()
+        };
+        assert_eq!(pack(&sizes, &settings).unplaced, vec![0, 1, 2]);
+    }
+
+    fn strategies() -> Vec<Strategy> {
+        vec![
+            Strategy::Skyline,
+            Strategy::MaxRects(Heuristic::BestShortSideFit),
+            Strategy::MaxRects(Heuristic::BestLongSideFit),
+            Strategy::MaxRects(Heuristic::BestAreaFit),
+            Strategy::MaxRects(Heuristic::BottomLeft),
+        ]
+    }
+
+    #[test]
+    fn packing_is_deterministic() {
+        let sizes = sizes(150, 11, 60);
+        for strategy in strategies() {
+            let settings = PackerSettings {
+                strategy,
+                ..PackerSettings::default()
+            };
+            let first = pack(&sizes, &settings);
+            let second = pack(&sizes, &settings);
+            assert_eq!(first.pages, second.pages);
+            assert_eq!(first.placements, second.placements);
+            assert_eq!(first.unplaced, second.unplaced);
+        }
+    }
+
+    #[test]
+    fn placements_keep_the_padding_and_page_border() {
+        let sizes = sizes(200, 5, 50);
+        for strategy in strategies() {
+            for padding in [0, 1, 3] {
+                let settings = PackerSettings {
+                    strategy,
+                    padding,
+                    page_size: PageSize::Fixed {
+                        width: 256,
+                        height: 128,
+                    },
+                    max_pages: None,
+                };
+                let result = pack(&sizes, &settings);
+                assert!(result.unplaced.is_empty());
+                assert_eq!(result.placements.len(), sizes.len());
+
+                // Grown by the padding to the right and top, rectangles
+                // still don't overlap and stay off the border.
+                let padded: Vec<_> = result
+                    .placements
+                    .iter()
+                    .map(|p| {
+                        let page = result.pages[p.page];
+                        assert!(p.x >= padding && p.y >= padding);
+                        assert!(p.x + p.width + padding <= page.width);
+                        assert!(p.y + p.height + padding <= page.height);
+                        assert_eq!((p.width, p.height), sizes[p.id]);
+                        let rect = FreeRect {
+                            x: p.x,
+                            y: p.y,
+                            width: p.width + padding,
+                            height: p.height + padding,
+                        };
+                        (p.page, rect)
+                    })
+                    .collect();
+                for (i, (page, rect)) in padded.iter().enumerate() {
+                    for (other_page, other) in &padded[i + 1..] {
+                        assert!(page != other_page || !rect.intersects(other));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_rectangles_are_unplaced() {
+        for strategy in strategies() {
+            let settings = PackerSettings {
+                strategy,
+                padding: 1,
+                page_size: PageSize::Fixed {
+                    width: 32,
+                    height: 32,
+                },
+                max_pages: None,
+            };
+            // 30 pixels and the padding on both sides fill the page
+            let sizes = [(30, 30), (31, 4), (4, 31), (40, 40), (8, 8)];
+            let result = pack(&sizes, &settings);
+            assert_eq!(result.unplaced, vec![1, 2, 3]);
+            let ids: Vec<_> = result.placements.iter().map(|p| p.id).collect();
+            assert_eq!(ids, vec![0, 4]);
+        }
+    }
+
+    #[test]
+    fn rectangles_beyond_max_pages_are_unplaced() {
+        let settings = PackerSettings {
+            padding: 0,
+            page_size: PageSize::Fixed {
+                width: 16,
+                height: 16,
+            },
+            max_pages: Some(2),
+            ..PackerSettings::default()
+        };
+        let result = pack(&[(16, 16); 3], &settings);
+        assert_eq!(result.pages.len(), 2);
+        assert_eq!(result.unplaced, vec![2]);
+    }
+
+    #[test]
+    fn report_efficiency() {
+        let settings = PackerSettings {
+            padding: 0,
+            page_size: PageSize::Fixed {
+                width: 32,
+                height: 32,
+            },
+            ..PackerSettings::default()
+        };
+        // Four fill the first page, the fifth covers a quarter of the second
+        let report = pack(&[(16, 16); 5], &settings).report();
+        assert_eq!(report.page_efficiency, vec![1.0, 0.25]);
+        assert_eq!(report.total_efficiency, 0.625);
+        assert_eq!((report.placed, report.unplaced), (5, 0));
+
+        let empty = pack(&[], &settings).report();
+        assert!(empty.page_efficiency.is_empty());
+        assert_eq!(empty.total_efficiency, 0.0);
+    }
+
+    #[test]
+    fn growing_pages_fit_exactly() {
+        let settings = PackerSettings {
+            padding: 0,
+            ..PackerSettings::default()
+        };
+        let result = pack(&[(16, 16); 8], &settings);
+        assert_eq!(
+            result.pages,
+            vec![Page {
+                width: 64,
+                height: 32
+            }]
+        );
+        assert_eq!(result.report().total_efficiency, 1.0);
+    }
+}
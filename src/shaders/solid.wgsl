@@ -0,0 +1,42 @@
+// Flat colored rectangles, for selections and carets.
+
+struct VertexInput {
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) top_left: vec3<f32>,
+    @location(1) bottom_right: vec2<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) transform: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+struct Matrix {
+    v: mat4x4<f32>,
+}
+
+@group(0) @binding(2)
+var<uniform> global: Matrix;
+
+@group(0) @binding(4)
+var<storage, read> transforms: array<mat4x4<f32>>;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+
+    let corner = vec2<f32>(f32(in.vertex_index & 1u), f32(in.vertex_index >> 1u));
+    let pos = mix(in.top_left.xy, in.bottom_right, corner);
+
+    out.clip_position = global.v * transforms[in.transform] * vec4<f32>(pos, in.top_left.z, 1.0);
+    out.color = in.color;
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -4,21 +4,64 @@ struct VertexInput {
     @location(1) bottom_right: vec2<f32>,
     @location(2) tex_top_left: vec2<f32>,
     @location(3) tex_bottom_right: vec2<f32>,
+    @location(4) page: u32,
+    @location(5) flags: u32,
+    @location(6) thickness: f32,
+    @location(7) shear: f32,
+    @location(8) baseline: f32,
+    @location(9) anchor: vec3<f32>,
+    @location(10) transform: u32,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_pos: vec2<f32>,
     @location(1) color: vec3<f32>,
+    @location(2) @interpolate(flat) page: u32,
+    @location(3) @interpolate(flat) thickness: f32,
 }
 
 struct Matrix {
     v: mat4x4<f32>,
+    eye: vec4<f32>,
+    right: vec4<f32>,
+    up: vec4<f32>,
+    viewport: vec4<f32>,
 }
 
 @group(0) @binding(2)
 var<uniform> global: Matrix;
 
+// Moves texts from their own space to the world, slot 0 is the identity.
+@group(0) @binding(4)
+var<storage, read> transforms: array<mat4x4<f32>>;
+
+let QUAD_SIDEWAYS: u32 = 1u;
+let QUAD_BILLBOARD: u32 = 2u;
+let QUAD_CYLINDRICAL: u32 = 4u;
+let QUAD_FIXED_SIZE: u32 = 8u;
+
+// World position of a billboarded quad corner `offset` from its anchor, in
+// the plane facing the camera.
+fn billboard(anchor: vec3<f32>, offset: vec2<f32>, flags: u32) -> vec3<f32> {
+    var right = global.right.xyz;
+    var up = global.up.xyz;
+    let to_eye = global.eye.xyz - anchor;
+    if ((flags & QUAD_CYLINDRICAL) != 0u && length(to_eye.xz) > 0.0) {
+        up = vec3<f32>(0.0, 1.0, 0.0);
+        right = cross(up, normalize(vec3<f32>(to_eye.x, 0.0, to_eye.z)));
+    }
+
+    // Offsets are in pixels, scaled by the size of a pixel at the anchor.
+    var scale = 1.0;
+    if ((flags & QUAD_FIXED_SIZE) != 0u) {
+        let a = global.v * vec4<f32>(anchor, 1.0);
+        let b = global.v * vec4<f32>(anchor + global.up.xyz, 1.0);
+        scale = 2.0 / (global.viewport.y * abs(b.y / b.w - a.y / a.w));
+    }
+    return anchor + scale * (offset.x * right + offset.y * up);
+}
+
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
@@ -53,39 +96,95 @@ fn vs_main(in: VertexInput) -> VertexOutput {
         default: {}
     }
 
-    out.clip_position = global.v * vec4<f32>(pos, in.top_left.z, 1.0);
+    // Sideways glyphs in vertical text are turned a quarter clockwise.
+    if ((in.flags & QUAD_SIDEWAYS) != 0u) {
+        let corner = vec2<f32>(f32(in.vertex_index & 1u), f32(in.vertex_index >> 1u));
+        out.tex_pos = mix(in.tex_top_left, in.tex_bottom_right, vec2<f32>(corner.y, 1.0 - corner.x));
+    }
+
+    // Faux italic leans the glyph over, pivoting on its baseline.
+    if ((in.flags & QUAD_SIDEWAYS) != 0u) {
+        pos.y -= in.shear * (pos.x - in.baseline);
+    } else {
+        pos.x += in.shear * (pos.y - in.baseline);
+    }
+
+    let transform = transforms[in.transform];
+    out.clip_position = global.v * transform * vec4<f32>(pos, in.top_left.z, 1.0);
+    if ((in.flags & QUAD_BILLBOARD) != 0u) {
+        let anchor = transform * vec4<f32>(in.anchor, 1.0);
+        out.clip_position = global.v * vec4<f32>(billboard(anchor.xyz / anchor.w, pos, in.flags), 1.0);
+    }
     out.color = vec3<f32>(0.7, 0.2, 0.1);
+    out.page = in.page;
+    out.thickness = in.thickness;
 
     return out;
 }
 
 @group(0) @binding(0)
-var texture: texture_2d<f32>;
+var texture: texture_2d_array<f32>;
 @group(0) @binding(1)
 var tex_sampler: sampler;
 
+struct AtlasInfo {
+    distance_range: f32,
+    channels: u32,
+    supersample: u32,
+}
+
+@group(0) @binding(3)
+var<uniform> atlas: AtlasInfo;
+
 fn median(r: f32, g: f32, b: f32) -> f32 {
     return max(min(r, g), min(max(r, g), b));
 }
 
+// Single channel atlases hold a plain SDF.
+fn fieldDistance(texel: vec4<f32>) -> f32 {
+    if (atlas.channels == 1u) {
+        return texel.r;
+    }
+    return median(texel.r, texel.g, texel.b);
+}
+
 // Bigger the text, bigger the screenPxRange.
 fn screenPxRange(texCoord: vec2<f32>) -> f32 {
-    let unitRange = vec2<f32>(6.0) / vec2<f32>(textureDimensions(texture));
+    let unitRange = vec2<f32>(atlas.distance_range) / vec2<f32>(textureDimensions(texture));
     let screenTexSize = vec2<f32>(1.0) / fwidth(texCoord);
     return max(0.5 * dot(unitRange, screenTexSize), 1.0);
 }
 
+// Body opacity of a single sample of the full resolution atlas.
+fn sampleOpacity(texCoord: vec2<f32>, page: i32, pxRange: f32, thickness: f32, softness: f32) -> f32 {
+    let texel = textureSampleLevel(texture, tex_sampler, texCoord, page, 0.0);
+    let pxDist = (fieldDistance(texel) - 0.5 + thickness) * pxRange;
+    return smoothstep(0.5 - thickness - softness * pxRange, 0.5 - thickness + softness * pxRange, pxDist + 0.5 - thickness);
+}
+
+// Averages four samples a quarter pixel apart, each of them treated as a
+// pixel of half the size.
+fn supersampledOpacity(texCoord: vec2<f32>, page: i32, thickness: f32, softness: f32) -> f32 {
+    let pxRange = 2.0 * screenPxRange(texCoord);
+    let dx = 0.25 * dpdx(texCoord);
+    let dy = 0.25 * dpdy(texCoord);
+    return 0.25 * (sampleOpacity(texCoord - dx - dy, page, pxRange, thickness, softness)
+        + sampleOpacity(texCoord + dx - dy, page, pxRange, thickness, softness)
+        + sampleOpacity(texCoord - dx + dy, page, pxRange, thickness, softness)
+        + sampleOpacity(texCoord + dx + dy, page, pxRange, thickness, softness));
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     // variables
     let outline_thickness = 0.0;    // 0.0 - 0.5
-    let thickness = 0.0;    // 0.0 - 0.5
+    let thickness = in.thickness;    // 0.0 - 0.5
     let softness = 0.3;     
 
     // current texel, current distance, 
     // screen pixel range factor used for scaling the distance for valid anti-aliasing
-    let texel = textureSample(texture, tex_sampler, in.tex_pos).rgba;
-    let d = median(texel.r, texel.g, texel.b) - 0.5 + thickness;
+    let texel = textureSample(texture, tex_sampler, in.tex_pos, i32(in.page)).rgba;
+    let d = fieldDistance(texel) - 0.5 + thickness;
     let px_range = screenPxRange(in.tex_pos);
 
     var fg_color = vec4<f32>(0.8, 0.4, 0.1, 1.0);
@@ -96,7 +195,10 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let px_dist = d * px_range;
 
     //let opacity = clamp(px_dist + 0.5 - thickness, 0.0, 1.0);
-    let opacity = smoothstep(0.5 - thickness - softness * px_range, 0.5 - thickness + softness * px_range, px_dist + 0.5 - thickness);
+    var opacity = smoothstep(0.5 - thickness - softness * px_range, 0.5 - thickness + softness * px_range, px_dist + 0.5 - thickness);
+    if (atlas.supersample == 1u) {
+        opacity = supersampledOpacity(in.tex_pos, i32(in.page), thickness, softness);
+    }
 
     // Outline opacity
     let o_px_dist = (d + outline_thickness) * px_range;
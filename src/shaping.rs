@@ -0,0 +1,317 @@
+//! OpenType shaping of text into positioned glyphs.
+//!
+//! Shaping applies the substitutions (`GSUB`) and positioning (`GPOS`) of
+//! the font, which turn "fi" into a ligature, pick the contextual forms of
+//! Arabic letters and reorder Devanagari vowel signs. The result refers to
+//! glyphs by their index in the font, so they have to be looked up with
+//! [`GlyphKey::Index`].
+
+use std::ops::Range;
+
+use artery_font::FontMetric;
+use rustybuzz::{Direction, Face, Feature, UnicodeBuffer};
+use ttf_parser::GlyphId;
+
+use crate::text::GlyphKey;
+
+/// A glyph placed by the shaper. Advances and offsets are in ems.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShapedGlyph {
+    pub glyph_id: u16,
+    /// Byte offset of the first character in the source text which produced
+    /// this glyph. Glyphs of a ligature share the cluster of its first
+    /// character.
+    pub cluster: usize,
+    pub x_advance: f32,
+    pub y_advance: f32,
+    pub x_offset: f32,
+    pub y_offset: f32,
+}
+
+impl ShapedGlyph {
+    pub fn key(&self) -> GlyphKey {
+        GlyphKey::Index(self.glyph_id)
+    }
+}
+
+/// Vertical font metrics in ems, y pointing up from the baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineMetrics {
+    pub ascender: f32,
+    /// Negative below the baseline.
+    pub descender: f32,
+    /// Distance between the baselines of two lines.
+    pub line_height: f32,
+    /// Top edge of the underline, negative below the baseline.
+    pub underline_y: f32,
+    pub underline_thickness: f32,
+    /// Top edge of the strikethrough.
+    pub strikeout_y: f32,
+    pub strikeout_thickness: f32,
+}
+
+impl LineMetrics {
+    pub fn from_face(face: &ttf_parser::Face) -> Self {
+        let scale = 1.0 / face.units_per_em() as f32;
+        let ascender = face.ascender() as f32 * scale;
+        let descender = face.descender() as f32 * scale;
+        let line = |m: ttf_parser::LineMetrics| {
+            (m.position as f32 * scale, m.thickness as f32 * scale)
+        };
+        Self::new(
+            ascender,
+            descender,
+            ascender - descender + face.line_gap() as f32 * scale,
+            face.underline_metrics().map(line),
+            face.strikeout_metrics().map(line),
+        )
+    }
+
+    /// Metrics of an atlas font variant, in the same units as its glyphs.
+    /// Atlases have no strikeout metrics.
+    pub fn from_font(metrics: &FontMetric) -> Self {
+        Self::new(
+            metrics.ascender,
+            metrics.descender,
+            metrics.line_height,
+            Some((metrics.underline_y, metrics.underline_thickness)),
+            None,
+        )
+    }
+
+    /// Guesses `(position, thickness)` decoration metrics which are missing
+    /// or zero from the size of the font.
+    fn new(
+        ascender: f32,
+        descender: f32,
+        line_height: f32,
+        underline: Option<(f32, f32)>,
+        strikeout: Option<(f32, f32)>,
+    ) -> Self {
+        let thickness = (ascender - descender) / 20.0;
+        let (underline_y, underline_thickness) = underline
+            .filter(|&(_, t)| t > 0.0)
+            .unwrap_or((0.5 * descender, thickness));
+        let (strikeout_y, strikeout_thickness) = strikeout
+            .filter(|&(_, t)| t > 0.0)
+            .unwrap_or((0.3 * ascender, underline_thickness));
+        Self {
+            ascender,
+            descender,
+            line_height,
+            underline_y,
+            underline_thickness,
+            strikeout_y,
+            strikeout_thickness,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ShapingError {
+    /// The font data couldn't be parsed.
+    Font,
+}
+
+impl std::fmt::Display for ShapingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShapingError::Font => write!(f, "The font couldn't be parsed"),
+        }
+    }
+}
+
+impl std::error::Error for ShapingError {}
+
+pub struct Shaper {
+    font_data: Vec<u8>,
+    features: Vec<Feature>,
+}
+
+impl Shaper {
+    pub fn new(font_data: Vec<u8>) -> Result<Self, ShapingError> {
+        Face::from_slice(&font_data, 0).ok_or(ShapingError::Font)?;
+        Ok(Self {
+            font_data,
+            features: Vec::new(),
+        })
+    }
+
+    /// Turns OpenType features on or off for the whole text, in the
+    /// `"liga=0"` or `"+smcp"` syntax of HarfBuzz. Features which don't
+    /// parse are ignored.
+    pub fn set_features<'a>(
+        &mut self,
+        features: impl IntoIterator<Item = &'a str>,
+    ) {
+        self.features = features
+            .into_iter()
+            .filter_map(|f| f.parse().ok())
+            .collect();
+    }
+
+    /// Shapes `text` as a single run. Script, language and direction are
+    /// guessed from its content.
+    pub fn shape(&self, text: &str) -> Vec<ShapedGlyph> {
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(text);
+        buffer.guess_segment_properties();
+        self.shape_buffer(buffer, 0)
+    }
+
+    /// Shapes `range` of `text` as a run of a single direction, as split up
+    /// by the bidi algorithm. Glyphs come in visual order, so right-to-left
+    /// runs start with their last character. Clusters are offsets into the
+    /// whole `text`.
+    pub fn shape_run(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        rtl: bool,
+    ) -> Vec<ShapedGlyph> {
+        let start = range.start;
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(&text[range]);
+        buffer.set_direction(if rtl {
+            Direction::RightToLeft
+        } else {
+            Direction::LeftToRight
+        });
+        buffer.guess_segment_properties();
+        self.shape_buffer(buffer, start)
+    }
+
+    /// Shapes `range` of `text` top to bottom, for upright glyphs in
+    /// vertical text. Advances point down, so `y_advance` is negative, and
+    /// offsets move the horizontal glyph origin below the pen, centered on
+    /// it. The font's vertical alternates are used.
+    pub fn shape_vertical_run(
+        &self,
+        text: &str,
+        range: Range<usize>,
+    ) -> Vec<ShapedGlyph> {
+        let start = range.start;
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(&text[range]);
+        buffer.set_direction(Direction::TopToBottom);
+        buffer.guess_segment_properties();
+        self.shape_buffer(buffer, start)
+    }
+
+    /// Left and right edge of the outline of a glyph in ems, `None` for
+    /// glyphs without one.
+    pub fn glyph_extent(&self, glyph_id: u16) -> Option<(f32, f32)> {
+        let face = self.face();
+        let scale = 1.0 / face.units_per_em() as f32;
+        let bbox = face.glyph_bounding_box(GlyphId(glyph_id))?;
+        Some((bbox.x_min as f32 * scale, bbox.x_max as f32 * scale))
+    }
+
+    /// Vertical metrics of the font.
+    pub fn metrics(&self) -> LineMetrics {
+        LineMetrics::from_face(&self.face())
+    }
+
+    fn face(&self) -> Face<'_> {
+        // Parsing the face is cheap and checked in `new`.
+        Face::from_slice(&self.font_data, 0).unwrap()
+    }
+
+    fn shape_buffer(
+        &self,
+        buffer: UnicodeBuffer,
+        cluster_offset: usize,
+    ) -> Vec<ShapedGlyph> {
+        let face = self.face();
+        let scale = 1.0 / face.units_per_em() as f32;
+        let output = rustybuzz::shape(&face, &self.features, buffer);
+
+        output
+            .glyph_infos()
+            .iter()
+            .zip(output.glyph_positions())
+            .map(|(info, pos)| ShapedGlyph {
+                glyph_id: info.glyph_id as u16,
+                cluster: cluster_offset + info.cluster as usize,
+                x_advance: pos.x_advance as f32 * scale,
+                y_advance: pos.y_advance as f32 * scale,
+                x_offset: pos.x_offset as f32 * scale,
+                y_offset: pos.y_offset as f32 * scale,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shaper() -> Shaper {
+        let font_data = include_bytes!("../fonts/monserat.ttf").to_vec();
+        Shaper::new(font_data).unwrap()
+    }
+
+    fn clusters(glyphs: &[ShapedGlyph]) -> Vec<usize> {
+        glyphs.iter().map(|g| g.cluster).collect()
+    }
+
+    #[test]
+    fn ligatures_share_the_cluster_of_their_first_character() {
+        let mut shaper = shaper();
+        let glyphs = shaper.shape("fi x");
+        assert_eq!(clusters(&glyphs), [0, 2, 3]);
+
+        shaper.set_features(["liga=0", "not a feature"]);
+        let glyphs = shaper.shape("fi x");
+        assert_eq!(clusters(&glyphs), [0, 1, 2, 3]);
+        assert!(glyphs.iter().all(|g| g.x_advance > 0.0));
+    }
+
+    #[test]
+    fn runs_keep_clusters_of_the_whole_text() {
+        let shaper = shaper();
+        let ltr = shaper.shape_run("xabc", 1..3, false);
+        assert_eq!(clusters(&ltr), [1, 2]);
+        let rtl = shaper.shape_run("xabc", 1..3, true);
+        assert_eq!(clusters(&rtl), [2, 1]);
+        assert_eq!(rtl[0].glyph_id, ltr[1].glyph_id);
+
+        let vertical = shaper.shape_vertical_run("xabc", 1..3);
+        assert_eq!(clusters(&vertical), [1, 2]);
+        assert!(vertical.iter().all(|g| g.y_advance < 0.0));
+    }
+
+    #[test]
+    fn metrics_are_in_ems() {
+        let shaper = shaper();
+        let metrics = shaper.metrics();
+        assert!(metrics.ascender > 0.5 && metrics.ascender < 1.5);
+        assert!(metrics.descender < 0.0);
+        let extent = metrics.ascender - metrics.descender;
+        assert!(metrics.line_height >= extent);
+        assert!(metrics.underline_thickness > 0.0);
+
+        let space = shaper.shape(" ")[0].glyph_id;
+        assert_eq!(shaper.glyph_extent(space), None);
+        let (left, right) =
+            shaper.glyph_extent(shaper.shape("x")[0].glyph_id).unwrap();
+        assert!(left < right && right < 1.0);
+    }
+
+    #[test]
+    fn missing_decorations_are_guessed() {
+        let metrics = LineMetrics::new(0.8, -0.2, 1.2, Some((-0.1, 0.0)), None);
+        assert_eq!(metrics.underline_y, -0.1);
+        assert_eq!(metrics.underline_thickness, 0.05);
+        assert_eq!(metrics.strikeout_y, 0.3 * 0.8);
+        assert_eq!(metrics.strikeout_thickness, 0.05);
+    }
+
+    #[test]
+    fn invalid_fonts_are_rejected() {
+        assert!(matches!(
+            Shaper::new(b"not a font".to_vec()),
+            Err(ShapingError::Font)
+        ));
+    }
+}
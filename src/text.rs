@@ -1,15 +1,95 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range};
 
 use artery_font::Rect;
 use wgpu::util::DeviceExt;
 
-use crate::{util::Quad, Graphics};
+use crate::{
+    layout::{
+        Affinity, DecorationLine, GlyphSource, Layout, LayoutSettings,
+        PositionedGlyph,
+    },
+    util::{
+        check_transform_slot, Quad, Requisites, SolidRect, TransformSlotError,
+        QUAD_BILLBOARD, QUAD_CYLINDRICAL, QUAD_FIXED_SIZE, QUAD_SIDEWAYS,
+    },
+    Graphics,
+};
+
+/// Lines drawn along a span of a [`Text`], each in its own color.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoration {
+    /// Byte range of the span.
+    pub range: Range<usize>,
+    pub underline: Option<[f32; 4]>,
+    pub strikethrough: Option<[f32; 4]>,
+    pub overline: Option<[f32; 4]>,
+    /// Leaves gaps in the underline where descenders cross it, in
+    /// horizontal text.
+    pub skip_descenders: bool,
+}
+
+/// Bold and italic synthesized for a span of a [`Text`], for fonts which
+/// come without those faces. Glyphs keep their regular advances.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FauxStyle {
+    /// Byte range of the span.
+    pub range: Range<usize>,
+    /// How far outlines grow, as offset of the distance threshold between
+    /// 0.0 and 0.5. Around 0.1 looks bold, the distance range of the atlas
+    /// limits how much can show.
+    pub bold: f32,
+    /// Horizontal shift per unit of height, 0.2 leans glyphs by about 11°.
+    pub italic: f32,
+}
+
+/// Turns the glyphs of a [`Text`] to the camera, for labels like name tags
+/// above objects in 3D. The position of the text is the anchor they turn
+/// around. Selections, carets and decorations don't turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Billboard {
+    pub facing: BillboardFacing,
+    /// Keeps the text the same size on screen at any distance,
+    /// [`Text::set_size`] is then in pixels per em.
+    pub fixed_size: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BillboardFacing {
+    /// Faces the camera from every direction.
+    #[default]
+    Spherical,
+    /// Only turns around the y axis, staying upright.
+    Cylindrical,
+}
+
+impl Billboard {
+    fn flags(&self) -> u32 {
+        let mut flags = QUAD_BILLBOARD;
+        if self.facing == BillboardFacing::Cylindrical {
+            flags |= QUAD_CYLINDRICAL;
+        }
+        if self.fixed_size {
+            flags |= QUAD_FIXED_SIZE;
+        }
+        flags
+    }
+}
 
 pub struct Text {
     x: f32,
     y: f32,
     z: f32,
+    /// Units per em.
+    size: f32,
+    /// Physical pixels per unit which baselines are snapped to.
+    pixel_snap: Option<f32>,
+    billboard: Option<Billboard>,
+    /// Slot of [`Requisites::transform_buffer`].
+    transform: u32,
     text: String,
+    settings: LayoutSettings,
+    decorations: Vec<Decoration>,
+    styles: Vec<FauxStyle>,
 }
 
 impl Text {
@@ -18,72 +98,463 @@ impl Text {
             x: pos.0,
             y: pos.1,
             z: pos.2,
+            size: 1.0,
+            pixel_snap: None,
+            billboard: None,
+            transform: 0,
             text: text.to_owned(),
+            settings: LayoutSettings::default(),
+            decorations: Vec::new(),
+            styles: Vec::new(),
         }
     }
 
+    /// Sets the font size in units per em. Text in screen space, see
+    /// [`crate::camera::ScreenProjection`], is sized in logical pixels.
+    pub fn set_size(&mut self, size: f32) {
+        self.size = size;
+    }
+
+    pub fn size(&self) -> f32 {
+        self.size
+    }
+
+    /// Moves baselines, or the center lines of columns in vertical text, onto
+    /// the nearest physical pixel so glyphs are rendered the same on every
+    /// line. `None` places them exactly, as needed for text in 3D.
+    pub fn set_pixel_snap(&mut self, pixels_per_unit: Option<f32>) {
+        self.pixel_snap = pixels_per_unit;
+    }
+
+    pub fn set_billboard(&mut self, billboard: Option<Billboard>) {
+        self.billboard = billboard;
+    }
+
+    /// Moves the text by the transform in `slot`, set with
+    /// [`Requisites::write_transform`], after placing it at its position.
+    /// Rotating, scaling or moving it then takes no new quads. Slot 0 is the
+    /// identity.
+    pub fn set_transform(
+        &mut self,
+        slot: u32,
+    ) -> Result<(), TransformSlotError> {
+        check_transform_slot(slot)?;
+        self.transform = slot;
+        Ok(())
+    }
+
+    pub fn transform(&self) -> u32 {
+        self.transform
+    }
+
+    /// Lays the text out with the glyphs of `reqs`, without shaping, and
+    /// creates its quads.
     pub fn create_buffer(
         &self,
         gfx: &Graphics,
-        glyphs: &HashMap<u32, Glyph>,
+        reqs: &Requisites,
     ) -> (wgpu::Buffer, u32) {
-        let mut result = Vec::new();
-        let mut chars = self.text.chars();
-
-        let mut temp_right: f32;
-
-        let glyph = glyphs.get(&(chars.next().unwrap() as u32)).unwrap();
-        let x1 = self.x + glyph.plane_bounds.left;
-        let y1 = self.y + glyph.plane_bounds.top;
-        let x2 = self.x + glyph.plane_bounds.right;
-        let y2 = self.y + glyph.plane_bounds.bottom;
-        let tex_x1 = glyph.atlas_bounds.left;
-        let tex_y1 = glyph.atlas_bounds.top;
-        let tex_x2 = glyph.atlas_bounds.right;
-        let tex_y2 = glyph.atlas_bounds.bottom;
-        temp_right = x2;
-        let vertex = Quad {
-            top_left: [x1, y1, self.z],
-            bottom_right: [x2, y2],
-            tex_top_left: [tex_x1, tex_y1],
-            tex_bottom_right: [tex_x2, tex_y2],
+        let layout = self.layout(reqs.glyph_source());
+        self.create_layout_buffer(gfx, &reqs.glyphs, &layout)
+    }
+
+    /// Lays out the text, see [`Layout`]. Pass a
+    /// [`crate::shaping::Shaper`] as source to shape it.
+    pub fn layout(&self, source: GlyphSource) -> Layout {
+        Layout::new(&self.text, source, &self.settings)
+    }
+
+    /// Creates quads for the glyphs of `layout`, with the faux style of
+    /// their span. Glyphs missing from `glyphs` are skipped.
+    pub fn create_layout_buffer(
+        &self,
+        gfx: &Graphics,
+        glyphs: &HashMap<GlyphKey, Glyph>,
+        layout: &Layout,
+    ) -> (wgpu::Buffer, u32) {
+        let mut result = Vec::with_capacity(layout.glyphs.len());
+        // Billboarded quads are placed relative to their anchor.
+        let (billboard, origin) = match self.billboard {
+            Some(billboard) => (billboard.flags(), (self.x, self.y)),
+            None => (0, (0.0, 0.0)),
         };
-        result.push(vertex);
-
-        for _ in 1..self.text.len() {
-            let glyph = glyphs.get(&(chars.next().unwrap() as u32)).unwrap();
-            let x1 = temp_right + glyph.advance_x;
-            let y1 = self.y + glyph.plane_bounds.top;
-            let x2 = temp_right + glyph.advance_x + glyph.plane_bounds.right;
-            let y2 = self.y + glyph.plane_bounds.bottom;
-            let tex_x1 = glyph.atlas_bounds.left;
-            let tex_y1 = glyph.atlas_bounds.top;
-            let tex_x2 = glyph.atlas_bounds.right;
-            let tex_y2 = glyph.atlas_bounds.bottom;
-            temp_right = x2;
-            let vertex = Quad {
-                top_left: [x1, y1, self.z],
-                bottom_right: [x2, y2],
-                tex_top_left: [tex_x1, tex_y1],
-                tex_bottom_right: [tex_x2, tex_y2],
-            };
-            result.push(vertex);
+
+        for g in &layout.glyphs {
+            if let Some(glyph) = glyphs.get(&g.key) {
+                let (pen_x, pen_y) = self.pen_position(layout, g);
+                let (pen_x, pen_y) = (pen_x - origin.0, pen_y - origin.1);
+                let x = pen_x + g.offset[0] * self.size;
+                let y = pen_y + g.offset[1] * self.size;
+                let b = glyph.plane_bounds.scaled(self.size, self.size);
+                // Turned a quarter clockwise, glyph space (x, y) is screen
+                // space (y, -x).
+                let (top_left, bottom_right, flags, baseline) = if g.sideways {
+                    (
+                        [x + b.bottom, y - b.left, self.z],
+                        [x + b.top, y - b.right],
+                        QUAD_SIDEWAYS,
+                        pen_x,
+                    )
+                } else {
+                    (
+                        [x + b.left, y + b.top, self.z],
+                        [x + b.right, y + b.bottom],
+                        0,
+                        pen_y,
+                    )
+                };
+                let style = self
+                    .styles
+                    .iter()
+                    .rev()
+                    .find(|s| s.range.contains(&g.cluster));
+                result.push(Quad {
+                    top_left,
+                    bottom_right,
+                    tex_top_left: [
+                        glyph.atlas_bounds.left,
+                        glyph.atlas_bounds.top,
+                    ],
+                    tex_bottom_right: [
+                        glyph.atlas_bounds.right,
+                        glyph.atlas_bounds.bottom,
+                    ],
+                    page: glyph.page,
+                    flags: flags | billboard,
+                    thickness: style.map_or(0.0, |s| s.bold),
+                    shear: style.map_or(0.0, |s| s.italic),
+                    baseline,
+                    anchor: [self.x, self.y, self.z],
+                    transform: self.transform,
+                });
+            }
+        }
+
+        create_vertex_buffer(gfx, &result)
+    }
+
+    /// Where the pen of a glyph of `layout` is in the space of the quads,
+    /// snapped to the pixel grid if set.
+    fn pen_position(&self, layout: &Layout, g: &PositionedGlyph) -> (f32, f32) {
+        let mut x = self.x + g.x * self.size;
+        let mut y = self.y + g.y * self.size;
+        if let Some(pixels) = self.pixel_snap {
+            let snap = |v: f32| (v * pixels).round() / pixels;
+            if layout.vertical {
+                x = snap(x);
+            } else {
+                y = snap(y);
+            }
         }
+        (x, y)
+    }
+
+    /// Caret position closest to `point`, see [`Layout::hit_test`]. Takes
+    /// and returns positions in the same space as the quads, before their
+    /// transform, for a `layout` of this text.
+    pub fn hit_test(
+        &self,
+        layout: &Layout,
+        point: (f32, f32),
+    ) -> (usize, Affinity) {
+        layout.hit_test(
+            (point.0 - self.x) / self.size,
+            (point.1 - self.y) / self.size,
+        )
+    }
 
-        let buffer =
-            gfx.device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Text Buffer"),
-                    contents: bytemuck::cast_slice(&result),
-                    usage: wgpu::BufferUsages::VERTEX,
+    pub fn caret_rect(
+        &self,
+        layout: &Layout,
+        offset: usize,
+        affinity: Affinity,
+    ) -> Rect {
+        self.offset_rect(layout.caret_rect(offset, affinity))
+    }
+
+    pub fn selection_rects(
+        &self,
+        layout: &Layout,
+        range: Range<usize>,
+    ) -> Vec<Rect> {
+        layout
+            .selection_rects(range)
+            .into_iter()
+            .map(|r| self.offset_rect(r))
+            .collect()
+    }
+
+    /// Moves a rectangle of the layout to the position and size of the
+    /// text.
+    fn offset_rect(&self, rect: Rect) -> Rect {
+        let rect = rect.scaled(self.size, self.size);
+        Rect {
+            left: rect.left + self.x,
+            bottom: rect.bottom + self.y,
+            right: rect.right + self.x,
+            top: rect.top + self.y,
+        }
+    }
+
+    /// Creates rectangles behind the characters of `range`, for
+    /// [`crate::util::solid_pipeline`].
+    pub fn create_selection_buffer(
+        &self,
+        gfx: &Graphics,
+        layout: &Layout,
+        range: Range<usize>,
+        color: [f32; 4],
+    ) -> (wgpu::Buffer, u32) {
+        let rects: Vec<_> = self
+            .selection_rects(layout, range)
+            .into_iter()
+            .map(|r| self.solid_rect(r, color))
+            .collect();
+        create_vertex_buffer(gfx, &rects)
+    }
+
+    /// Creates a caret `width` ems wide at `offset`, see
+    /// [`crate::caret::Blink`] for when to draw it.
+    pub fn create_caret_buffer(
+        &self,
+        gfx: &Graphics,
+        layout: &Layout,
+        offset: usize,
+        affinity: Affinity,
+        width: f32,
+        color: [f32; 4],
+    ) -> (wgpu::Buffer, u32) {
+        let mut rect = self.caret_rect(layout, offset, affinity);
+        let half = 0.5 * width * self.size;
+        if layout.vertical {
+            rect.top += half;
+            rect.bottom -= half;
+        } else {
+            rect.left -= half;
+            rect.right += half;
+        }
+        create_vertex_buffer(gfx, &[self.solid_rect(rect, color)])
+    }
+
+    /// Creates lines along the bottom of the lines of each range, as many
+    /// ems thick as given with it. Vertical text is marked on the left of a
+    /// column.
+    pub fn create_underline_buffer(
+        &self,
+        gfx: &Graphics,
+        layout: &Layout,
+        ranges: &[(Range<usize>, f32)],
+        color: [f32; 4],
+    ) -> (wgpu::Buffer, u32) {
+        let mut rects = Vec::new();
+        for (range, thickness) in ranges {
+            let thickness = thickness * self.size;
+            for mut rect in self.selection_rects(layout, range.clone()) {
+                if layout.vertical {
+                    rect.right = rect.left + thickness;
+                } else {
+                    rect.top = rect.bottom + thickness;
+                }
+                rects.push(self.solid_rect(rect, color));
+            }
+        }
+        create_vertex_buffer(gfx, &rects)
+    }
+
+    /// Creates the lines of the decorations, to be drawn after the text
+    /// with [`crate::util::solid_pipeline`]. `glyphs` are the glyphs the
+    /// text was laid out with, descenders are skipped by their bounds.
+    pub fn create_decoration_buffer(
+        &self,
+        gfx: &Graphics,
+        glyphs: &HashMap<GlyphKey, Glyph>,
+        layout: &Layout,
+    ) -> (wgpu::Buffer, u32) {
+        let mut rects = Vec::new();
+        for decoration in &self.decorations {
+            let lines = [
+                (DecorationLine::Underline, decoration.underline),
+                (DecorationLine::Strikethrough, decoration.strikethrough),
+                (DecorationLine::Overline, decoration.overline),
+            ];
+            for (line, color) in lines {
+                let color = match color {
+                    Some(color) => color,
+                    None => continue,
+                };
+                let skip = decoration.skip_descenders
+                    && line == DecorationLine::Underline
+                    && !layout.vertical;
+                let range = decoration.range.clone();
+                for rect in layout.decoration_rects(range.clone(), line) {
+                    let rect = self.offset_rect(rect);
+                    let pieces = if skip {
+                        self.skip_descenders(rect, glyphs, layout, &range)
+                    } else {
+                        vec![rect]
+                    };
+                    rects.extend(
+                        pieces.into_iter().map(|r| self.solid_rect(r, color)),
+                    );
+                }
+            }
+        }
+        create_vertex_buffer(gfx, &rects)
+    }
+
+    /// Splits an underline of `range` around the glyphs which reach into
+    /// it, leaving gaps as wide as their quads.
+    fn skip_descenders(
+        &self,
+        rect: Rect,
+        glyphs: &HashMap<GlyphKey, Glyph>,
+        layout: &Layout,
+        range: &Range<usize>,
+    ) -> Vec<Rect> {
+        let mut gaps: Vec<(f32, f32)> = layout
+            .glyphs
+            .iter()
+            .filter(|g| range.contains(&g.cluster))
+            .filter_map(|g| {
+                let b = glyphs.get(&g.key)?.plane_bounds;
+                let b = b.scaled(self.size, self.size);
+                let (pen_x, pen_y) = self.pen_position(layout, g);
+                let x = pen_x + g.offset[0] * self.size;
+                let y = pen_y + g.offset[1] * self.size;
+                let crosses = y + b.bottom < rect.top
+                    && y + b.top > rect.bottom
+                    && b.left < b.right;
+                crosses.then_some((x + b.left, x + b.right))
+            })
+            .collect();
+        gaps.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut pieces = Vec::new();
+        let mut left = rect.left;
+        for (start, end) in gaps {
+            if left < start.min(rect.right) {
+                pieces.push(Rect {
+                    left,
+                    right: start.min(rect.right),
+                    ..rect
                 });
+            }
+            left = left.max(end);
+        }
+        if left < rect.right {
+            pieces.push(Rect { left, ..rect });
+        }
+        pieces
+    }
+
+    fn solid_rect(&self, rect: Rect, color: [f32; 4]) -> SolidRect {
+        SolidRect {
+            top_left: [rect.left, rect.top, self.z],
+            bottom_right: [rect.right, rect.bottom],
+            color,
+            transform: self.transform,
+        }
+    }
+
+    pub fn settings(&self) -> &LayoutSettings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut LayoutSettings {
+        &mut self.settings
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn decorations(&self) -> &[Decoration] {
+        &self.decorations
+    }
+
+    pub fn decorations_mut(&mut self) -> &mut Vec<Decoration> {
+        &mut self.decorations
+    }
+
+    /// Faux styles of spans, later ones win where they overlap.
+    pub fn styles(&self) -> &[FauxStyle] {
+        &self.styles
+    }
+
+    pub fn styles_mut(&mut self) -> &mut Vec<FauxStyle> {
+        &mut self.styles
+    }
+
+    /// Replaces the bytes of `range` with `with`, layouts of the text have
+    /// to be made again. Decorations and styles keep covering the same
+    /// characters, and all of `with` if they overlap `range`.
+    pub fn replace_range(&mut self, range: Range<usize>, with: &str) {
+        for decoration in &mut self.decorations {
+            decoration.range = moved_span(&decoration.range, &range, with);
+        }
+        for style in &mut self.styles {
+            style.range = moved_span(&style.range, &range, with);
+        }
+        self.text.replace_range(range, with);
+    }
+}
+
+/// Where `span` ends up when `replaced` is replaced with `with`.
+fn moved_span(
+    span: &Range<usize>,
+    replaced: &Range<usize>,
+    with: &str,
+) -> Range<usize> {
+    let moved = |offset: usize, inside: usize| {
+        if offset <= replaced.start {
+            offset
+        } else if offset >= replaced.end {
+            offset - replaced.len() + with.len()
+        } else {
+            inside
+        }
+    };
+    moved(span.start, replaced.start)
+        ..moved(span.end, replaced.start + with.len())
+}
+
+fn create_vertex_buffer<T: bytemuck::Pod>(
+    gfx: &Graphics,
+    instances: &[T],
+) -> (wgpu::Buffer, u32) {
+    let buffer =
+        gfx.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Text Buffer"),
+                contents: bytemuck::cast_slice(instances),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+    (buffer, instances.len() as u32)
+}
+
+/// What atlas glyphs are looked up by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GlyphKey {
+    /// Unicode codepoint, for text laid out character by character.
+    Codepoint(u32),
+    /// Glyph index in the font, for shaped text.
+    Index(u16),
+}
 
-        (buffer, result.len() as u32)
+impl GlyphKey {
+    pub fn char(c: char) -> Self {
+        GlyphKey::Codepoint(c as u32)
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct Glyph {
     pub advance_x: f32,
+    /// Advance in vertical text, 0 if the font has no vertical metrics.
+    pub advance_y: f32,
     pub plane_bounds: Rect,
     pub atlas_bounds: Rect,
+    /// Index of the atlas page (texture array layer) holding the glyph.
+    pub page: u32,
 }
@@ -0,0 +1,224 @@
+//! Uploads to and readbacks from atlas textures.
+//!
+//! `queue.write_texture` takes tightly packed rows, buffer to texture copies
+//! and readbacks need every row padded to
+//! [`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`]. [`PaddedRows`] converts between
+//! the two.
+
+use std::num::NonZeroU32;
+
+use wgpu::util::DeviceExt;
+
+use crate::Graphics;
+
+/// Part of one layer of a texture array, in pixels of its mip level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub layer: u32,
+    pub mip_level: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// The whole layer of a `width` x `height` texture.
+    pub fn layer(layer: u32, width: u32, height: u32) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            layer,
+            mip_level: 0,
+            width,
+            height,
+        }
+    }
+
+    fn copy_texture<'a>(
+        &self,
+        texture: &'a wgpu::Texture,
+    ) -> wgpu::ImageCopyTexture<'a> {
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: self.mip_level,
+            origin: wgpu::Origin3d {
+                x: self.x,
+                y: self.y,
+                z: self.layer,
+            },
+            aspect: wgpu::TextureAspect::All,
+        }
+    }
+
+    fn extent(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        }
+    }
+}
+
+/// Pixel rows padded to [`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`].
+pub struct PaddedRows {
+    pub data: Vec<u8>,
+    /// Bytes of a row without padding.
+    pub unpadded_bytes_per_row: u32,
+    pub bytes_per_row: u32,
+}
+
+impl PaddedRows {
+    /// Pads tightly packed rows. Rows which are already aligned are copied
+    /// as they are.
+    pub fn new(data: &[u8], unpadded_bytes_per_row: u32) -> Self {
+        let bytes_per_row = padded_bytes_per_row(unpadded_bytes_per_row);
+        let data = if bytes_per_row == unpadded_bytes_per_row {
+            data.to_vec()
+        } else {
+            let rows = data.len() / unpadded_bytes_per_row as usize;
+            let mut padded = vec![0; rows * bytes_per_row as usize];
+            for (src, dst) in data
+                .chunks_exact(unpadded_bytes_per_row as usize)
+                .zip(padded.chunks_exact_mut(bytes_per_row as usize))
+            {
+                dst[..src.len()].copy_from_slice(src);
+            }
+            padded
+        };
+
+        Self {
+            data,
+            unpadded_bytes_per_row,
+            bytes_per_row,
+        }
+    }
+
+    /// Removes the padding again.
+    pub fn into_unpadded(self) -> Vec<u8> {
+        if self.bytes_per_row == self.unpadded_bytes_per_row {
+            return self.data;
+        }
+        self.data
+            .chunks_exact(self.bytes_per_row as usize)
+            .flat_map(|row| &row[..self.unpadded_bytes_per_row as usize])
+            .copied()
+            .collect()
+    }
+}
+
+pub fn padded_bytes_per_row(unpadded: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    unpadded.div_ceil(align) * align
+}
+
+fn bytes_per_row(texture_format: wgpu::TextureFormat, width: u32) -> u32 {
+    texture_format.describe().block_size as u32 * width
+}
+
+/// Writes tightly packed pixels into `region` of `texture`.
+pub fn write_region(
+    gfx: &Graphics,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+    region: Region,
+    data: &[u8],
+) {
+    let bytes_per_row = bytes_per_row(format, region.width);
+    debug_assert_eq!(data.len(), (bytes_per_row * region.height) as usize);
+
+    gfx.queue.write_texture(
+        region.copy_texture(texture),
+        data,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(bytes_per_row),
+            rows_per_image: None,
+        },
+        region.extent(),
+    );
+}
+
+/// Records a copy of tightly packed pixels into `region` of `texture`
+/// through a staging buffer. Many regions can be recorded into the same
+/// encoder and submitted at once.
+pub fn copy_region(
+    gfx: &Graphics,
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+    region: Region,
+    data: &[u8],
+) {
+    let rows = PaddedRows::new(data, bytes_per_row(format, region.width));
+    let staging =
+        gfx.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Atlas Staging Buffer"),
+                contents: &rows.data,
+                usage: wgpu::BufferUsages::COPY_SRC,
+            });
+
+    encoder.copy_buffer_to_texture(
+        wgpu::ImageCopyBuffer {
+            buffer: &staging,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(rows.bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        region.copy_texture(texture),
+        region.extent(),
+    );
+}
+
+/// Reads `region` of `texture` back, blocking until the GPU is done. The
+/// texture needs `COPY_SRC` usage.
+pub fn read_region(
+    gfx: &Graphics,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+    region: Region,
+) -> Vec<u8> {
+    let unpadded = bytes_per_row(format, region.width);
+    let padded = padded_bytes_per_row(unpadded);
+    let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Atlas Readback Buffer"),
+        size: (padded * region.height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder =
+        gfx.device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Atlas Readback Encoder"),
+            });
+    encoder.copy_texture_to_buffer(
+        region.copy_texture(texture),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded),
+                rows_per_image: None,
+            },
+        },
+        region.extent(),
+    );
+    gfx.queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    gfx.device.poll(wgpu::Maintain::Wait);
+    let data = slice.get_mapped_range().to_vec();
+    buffer.unmap();
+
+    PaddedRows {
+        data,
+        unpadded_bytes_per_row: unpadded,
+        bytes_per_row: padded,
+    }
+    .into_unpadded()
+}
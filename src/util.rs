@@ -1,67 +1,302 @@
 use std::collections::HashMap;
 
-use artery_font::ArteryFont;
+use artery_font::{ArteryFont, CodepointType, Image, PixelFormat};
+use nalgebra::Matrix4;
+use wgpu::util::DeviceExt;
 
-use crate::{text::Glyph, Graphics};
+use crate::{
+    dynamic::{DynamicAtlas, DynamicAtlasError, DynamicAtlasSettings},
+    layout::{GlyphSource, Layout},
+    mipmap,
+    shaping::LineMetrics,
+    text::{Glyph, GlyphKey},
+    upload::{self, Region},
+    Graphics,
+};
 
 pub struct Requisites {
     pub atlas_texture: wgpu::Texture,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
 
-    pub glyphs: HashMap<u32, Glyph>,
+    pub glyphs: HashMap<GlyphKey, Glyph>,
+    pub metrics: LineMetrics,
     pub matrix_buffer: wgpu::Buffer,
+    /// Transforms of texts, see [`Requisites::write_transform`].
+    pub transform_buffer: wgpu::Buffer,
+    pub dynamic_atlas: Option<DynamicAtlas>,
+
+    texture_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    atlas_info_buffer: wgpu::Buffer,
+}
+
+/// Contents of the matrix buffer, which all pipelines share.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Globals {
+    /// Projection and view, from world to clip space.
+    pub matrix: [[f32; 4]; 4],
+    /// Camera position in world space, `w` is unused.
+    pub eye: [f32; 4],
+    /// Directions of the x and y axes of the screen in world space, which
+    /// billboards are laid out along.
+    pub right: [f32; 4],
+    pub up: [f32; 4],
+    /// Width and height of the surface in pixels, `z` and `w` are unused.
+    pub viewport: [f32; 4],
+}
+
+/// Number of transforms in [`Requisites::transform_buffer`].
+pub const TRANSFORM_SLOTS: u32 = 256;
+
+/// Matrix of its own for text of a [`Requisites`] atlas, see
+/// [`Requisites::create_matrix_binding`].
+pub struct MatrixBinding {
+    pub matrix_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
 }
 
 impl Requisites {
+    /// Uploads every atlas image of `arfont` as a layer of the atlas
+    /// texture. 8-bit images with two or more channels become RGBA, single
+    /// channel ones `R8Unorm`, float images `Rgba32Float` where the adapter
+    /// can filter it and `Rgba16Float` otherwise.
     pub fn init(
         gfx: &Graphics,
         arfont: &ArteryFont,
-    ) -> Self {
-        let image = arfont.images.first().unwrap();
-        let image_data = &image.data;
-        let variants = arfont.variants.first().unwrap();
-        let texels = image.width * image.height;
-
-        //////// INSERTS MISSING Alpha Channel IF THE TEXTURE IS RGB ////////
-        //let mut image_data = image_data.clone();
-        //for v in 1..=texels {
-        //    image_data.insert((v * 4 - 1) as usize, 0);
-        //}
-
-        let mut glyphs: HashMap<u32, Glyph> = HashMap::new();
+    ) -> Result<Self, AtlasError> {
+        Self::init_with(gfx, arfont, Minification::default())
+    }
+
+    /// Like [`Requisites::init`], choosing how far away text is kept from
+    /// shimmering.
+    pub fn init_with(
+        gfx: &Graphics,
+        arfont: &ArteryFont,
+        minification: Minification,
+    ) -> Result<Self, AtlasError> {
+        let variants = arfont.variants.first().ok_or(AtlasError::NoVariants)?;
+        let first = arfont.images.first().ok_or(AtlasError::NoImages)?;
+        if arfont.images.iter().any(|i| {
+            i.pixel_format != first.pixel_format || i.channels != first.channels
+        }) {
+            return Err(AtlasError::MixedFormats);
+        }
+        let format = texture_format(gfx, first)?;
+
+        // Every atlas page becomes a layer of a single texture array, so the
+        // array has to be big enough to hold the largest page.
+        let width = arfont.images.iter().map(|i| i.width).max().unwrap();
+        let height = arfont.images.iter().map(|i| i.height).max().unwrap();
+        let pages = arfont.images.len() as u32;
+
+        let mut glyphs: HashMap<GlyphKey, Glyph> = HashMap::new();
         for g in &variants.glyphs {
             let glyph = Glyph {
                 advance_x: g.advance.horizontal,
+                advance_y: g.advance.vertical,
                 plane_bounds: g.plane_bounds,
-                atlas_bounds: g.image_bounds.scaled(
-                    1.0 / image.width as f32,
-                    1.0 / image.height as f32,
-                ),
+                atlas_bounds: g
+                    .image_bounds
+                    .scaled(1.0 / width as f32, 1.0 / height as f32),
+                page: g.image,
+            };
+            let key = match variants.codepoint_type {
+                CodepointType::Indexed => GlyphKey::Index(g.codepoint as u16),
+                _ => GlyphKey::Codepoint(g.codepoint),
             };
-            glyphs.insert(g.codepoint, glyph);
+            glyphs.insert(key, glyph);
         }
 
         let size = wgpu::Extent3d {
-            width: image.width,
-            height: image.height,
-            depth_or_array_layers: 1,
+            width,
+            height,
+            depth_or_array_layers: pages,
         };
 
-        let texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Glyph Texture"),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::RENDER_ATTACHMENT,
-        });
+        let levels = match minification {
+            Minification::Mipmaps { levels } => {
+                mipmap::level_count(width, height, levels)
+            }
+            _ => 0,
+        };
+        let texture = create_atlas_texture(gfx, size, format, levels + 1);
+        let mut encoder =
+            gfx.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Atlas Upload Encoder"),
+                });
+        for (layer, image) in arfont.images.iter().enumerate() {
+            upload::copy_region(
+                gfx,
+                &mut encoder,
+                &texture,
+                format,
+                Region::layer(layer as u32, image.width, image.height),
+                &texture_data(image, format),
+            );
+
+            let bounds: Vec<_> = variants
+                .glyphs
+                .iter()
+                .filter(|g| g.image == layer as u32)
+                .map(|g| g.image_bounds)
+                .collect();
+            let chain = mipmap::mip_chain(image, &bounds, levels);
+            for (level, mip) in chain.iter().enumerate() {
+                upload::copy_region(
+                    gfx,
+                    &mut encoder,
+                    &texture,
+                    format,
+                    Region {
+                        mip_level: level as u32 + 1,
+                        ..Region::layer(layer as u32, mip.width, mip.height)
+                    },
+                    &texture_data(mip, format),
+                );
+            }
+        }
+        gfx.queue.submit(std::iter::once(encoder.finish()));
+
+        let info = AtlasInfo {
+            distance_range: variants.metrics.distance_range,
+            channels: format.describe().components as u32,
+            supersample: (minification == Minification::Supersample) as u32,
+            _padding: 0,
+        };
+        let metrics = LineMetrics::from_font(&variants.metrics);
+        Ok(Self::with_atlas(gfx, texture, info, glyphs, metrics, None))
+    }
+
+    /// Creates requisites with an empty [`DynamicAtlas`] which gets filled
+    /// by [`Requisites::prepare`].
+    pub fn init_dynamic(
+        gfx: &Graphics,
+        font_data: Vec<u8>,
+        settings: DynamicAtlasSettings,
+    ) -> Result<Self, DynamicAtlasError> {
+        // Glyphs come and go, so the dynamic atlas has no mip levels.
+        let info = AtlasInfo {
+            distance_range: settings.distance_range,
+            channels: 4,
+            supersample: 0,
+            _padding: 0,
+        };
+        let atlas = DynamicAtlas::new(font_data, settings)?;
+        let texture = create_atlas_texture(
+            gfx,
+            atlas.texture_size(),
+            wgpu::TextureFormat::Rgba8Unorm,
+            1,
+        );
+        let metrics = atlas.metrics();
+        Ok(Self::with_atlas(
+            gfx,
+            texture,
+            info,
+            HashMap::new(),
+            metrics,
+            Some(atlas),
+        ))
+    }
+
+    /// Starts a new frame of a dynamic atlas, glyphs not prepared since may
+    /// be evicted. Does nothing for pre-baked atlases.
+    pub fn next_frame(&mut self) {
+        if let Some(atlas) = &mut self.dynamic_atlas {
+            atlas.next_frame();
+        }
+    }
+
+    /// Rasterizes the glyphs of `text` missing from a dynamic atlas and
+    /// returns the keys of the glyphs evicted to make room, see
+    /// [`DynamicAtlas`]. Does nothing for pre-baked atlases.
+    pub fn prepare(
+        &mut self,
+        gfx: &Graphics,
+        text: &str,
+    ) -> Result<Vec<GlyphKey>, DynamicAtlasError> {
+        match &mut self.dynamic_atlas {
+            Some(atlas) => atlas.prepare(
+                gfx,
+                &self.atlas_texture,
+                &mut self.glyphs,
+                text,
+            ),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Lays out text with the glyphs of the atlas, without shaping.
+    pub fn glyph_source(&self) -> GlyphSource<'_> {
+        GlyphSource::Atlas {
+            glyphs: &self.glyphs,
+            metrics: self.metrics,
+        }
+    }
 
+    /// Rasterizes the glyphs of `layout` missing from a dynamic atlas.
+    /// Does nothing for pre-baked atlases.
+    ///
+    /// Making room may evict glyphs not prepared in the current frame, which
+    /// breaks vertex buffers of other text still using them. Their keys are
+    /// returned, see [`DynamicAtlas`].
+    pub fn prepare_layout(
+        &mut self,
+        gfx: &Graphics,
+        layout: &Layout,
+    ) -> Result<Vec<GlyphKey>, DynamicAtlasError> {
+        match &mut self.dynamic_atlas {
+            Some(atlas) => atlas.prepare_keys(
+                gfx,
+                &self.atlas_texture,
+                &mut self.glyphs,
+                layout.glyphs.iter().map(|g| g.key),
+            ),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Makes the glyphs of a codepoint keyed atlas reachable by their glyph
+    /// index in `font_data` as well, so shaped text can use them. Glyphs
+    /// only reachable through shaping, like ligatures, need an atlas made
+    /// by [`crate::generator::generate_indexed`].
+    pub fn index_glyphs(
+        &mut self,
+        font_data: &[u8],
+    ) -> Result<(), ttf_parser::FaceParsingError> {
+        let face = ttf_parser::Face::from_slice(font_data, 0)?;
+        let indexed: Vec<_> = self
+            .glyphs
+            .iter()
+            .filter_map(|(key, glyph)| match *key {
+                GlyphKey::Codepoint(c) => char::from_u32(c)
+                    .and_then(|c| face.glyph_index(c))
+                    .map(|id| (GlyphKey::Index(id.0), *glyph)),
+                GlyphKey::Index(_) => None,
+            })
+            .collect();
+        for (key, glyph) in indexed {
+            self.glyphs.entry(key).or_insert(glyph);
+        }
+        Ok(())
+    }
+
+    fn with_atlas(
+        gfx: &Graphics,
+        texture: wgpu::Texture,
+        info: AtlasInfo,
+        glyphs: HashMap<GlyphKey, Glyph>,
+        metrics: LineMetrics,
+        dynamic_atlas: Option<DynamicAtlas>,
+    ) -> Self {
         let texture_view =
-            texture.create_view(&wgpu::TextureViewDescriptor::default());
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            });
 
         let sampler = gfx.device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -73,29 +308,27 @@ impl Requisites {
             ..Default::default()
         });
 
-        gfx.queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &image_data,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * image.width),
-                rows_per_image: None,
-            },
-            size,
-        );
+        let matrix_buffer = create_matrix_buffer(gfx);
 
-        let matrix_buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Matrix Buffer"),
-            size: std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress,
-            usage: wgpu::BufferUsages::COPY_DST
-            | wgpu::BufferUsages::UNIFORM,
-            mapped_at_creation: false,
-        });
+        let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+        let transform_buffer =
+            gfx.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Transform Buffer"),
+                    contents: bytemuck::cast_slice(
+                        &[identity; TRANSFORM_SLOTS as usize],
+                    ),
+                    usage: wgpu::BufferUsages::COPY_DST
+                        | wgpu::BufferUsages::STORAGE,
+                });
+
+        let atlas_info_buffer =
+            gfx.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Atlas Info Buffer"),
+                    contents: bytemuck::bytes_of(&info),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
 
         let bind_group_layout = gfx.device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
@@ -108,7 +341,7 @@ impl Requisites {
                             sample_type: wgpu::TextureSampleType::Float {
                                 filterable: true,
                             },
-                            view_dimension: wgpu::TextureViewDimension::D2,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
                             multisampled: false,
                         },
                         count: None,
@@ -131,31 +364,41 @@ impl Requisites {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             },
         );
 
-        let bind_group =
-            gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Bind Group"),
-                layout: &bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(
-                            &texture_view,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: matrix_buffer.as_entire_binding(),
-                    },
-                ],
-            });
+        let bind_group = create_bind_group(
+            gfx,
+            &bind_group_layout,
+            &texture_view,
+            &sampler,
+            &matrix_buffer,
+            &atlas_info_buffer,
+            &transform_buffer,
+        );
 
         Self {
             atlas_texture: texture,
@@ -163,11 +406,356 @@ impl Requisites {
             bind_group,
 
             glyphs,
+            metrics,
+            matrix_buffer,
+            transform_buffer,
+            dynamic_atlas,
+
+            texture_view,
+            sampler,
+            atlas_info_buffer,
+        }
+    }
+
+    /// Creates a matrix buffer and a bind group which uses it with this
+    /// atlas. Text drawn with the bind group instead of
+    /// [`Requisites::bind_group`] can have another projection, like a
+    /// [`crate::camera::ScreenProjection`] next to the 3D camera.
+    pub fn create_matrix_binding(&self, gfx: &Graphics) -> MatrixBinding {
+        let matrix_buffer = create_matrix_buffer(gfx);
+        let bind_group = create_bind_group(
+            gfx,
+            &self.bind_group_layout,
+            &self.texture_view,
+            &self.sampler,
+            &matrix_buffer,
+            &self.atlas_info_buffer,
+            &self.transform_buffer,
+        );
+        MatrixBinding {
             matrix_buffer,
+            bind_group,
+        }
+    }
+
+    /// Sets the transform in `slot`, which moves texts using it from their
+    /// own space to the world, see [`crate::text::Text::set_transform`].
+    /// Slot 0 is the identity of texts without a transform and can't be
+    /// written, slots go up to [`TRANSFORM_SLOTS`]. Texts don't have to be
+    /// rebuilt when it changes.
+    pub fn write_transform(
+        &self,
+        gfx: &Graphics,
+        slot: u32,
+        transform: &Matrix4<f32>,
+    ) -> Result<(), TransformSlotError> {
+        if slot == 0 {
+            return Err(TransformSlotError::Identity);
+        }
+        check_transform_slot(slot)?;
+        let matrix: &[[f32; 4]; 4] = transform.as_ref();
+        gfx.queue.write_buffer(
+            &self.transform_buffer,
+            (slot as usize * std::mem::size_of::<[[f32; 4]; 4]>())
+                as wgpu::BufferAddress,
+            bytemuck::cast_slice(matrix),
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum TransformSlotError {
+    /// Slot 0 always holds the identity.
+    Identity,
+    /// The slot isn't below [`TRANSFORM_SLOTS`].
+    OutOfRange(u32),
+}
+
+impl std::fmt::Display for TransformSlotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransformSlotError::Identity => {
+                write!(f, "Transform slot 0 is reserved for the identity")
+            }
+            TransformSlotError::OutOfRange(slot) => write!(
+                f,
+                "Transform slot {} is out of range, there are {} slots",
+                slot, TRANSFORM_SLOTS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransformSlotError {}
+
+/// Fails for slots past the end of [`Requisites::transform_buffer`].
+pub fn check_transform_slot(slot: u32) -> Result<(), TransformSlotError> {
+    if slot < TRANSFORM_SLOTS {
+        Ok(())
+    } else {
+        Err(TransformSlotError::OutOfRange(slot))
+    }
+}
+
+fn create_matrix_buffer(gfx: &Graphics) -> wgpu::Buffer {
+    gfx.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Matrix Buffer"),
+        size: std::mem::size_of::<Globals>() as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_bind_group(
+    gfx: &Graphics,
+    layout: &wgpu::BindGroupLayout,
+    texture_view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    matrix_buffer: &wgpu::Buffer,
+    atlas_info_buffer: &wgpu::Buffer,
+    transform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: matrix_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: atlas_info_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: transform_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_atlas_texture(
+    gfx: &Graphics,
+    size: wgpu::Extent3d,
+    format: wgpu::TextureFormat,
+    mip_level_count: u32,
+) -> wgpu::Texture {
+    gfx.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Glyph Texture"),
+        size,
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+    })
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+    /// The font has no atlas images.
+    NoImages,
+    /// The font has no variants, which hold the glyphs.
+    NoVariants,
+    UnsupportedFormat {
+        pixel_format: PixelFormat,
+        channels: u32,
+    },
+    /// The atlas images differ in pixel format or channel count.
+    MixedFormats,
+}
+
+impl std::fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasError::NoImages => write!(f, "The font has no atlas images"),
+            AtlasError::NoVariants => write!(f, "The font has no variants"),
+            AtlasError::UnsupportedFormat {
+                pixel_format,
+                channels,
+            } => write!(
+                f,
+                "Atlas images with {} channels of {:?} aren't supported",
+                channels, pixel_format
+            ),
+            AtlasError::MixedFormats => {
+                write!(f, "Atlas images have different pixel formats")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+/// Tells the fragment shaders how to read the atlas.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct AtlasInfo {
+    distance_range: f32,
+    /// Channels of the texture format. Single channel atlases hold a plain
+    /// SDF, all others an MSDF in red, green and blue.
+    channels: u32,
+    /// Boolean, see [`Minification::Supersample`].
+    supersample: u32,
+    _padding: u32,
+}
+
+/// How text smaller than the atlas resolution is kept from shimmering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Minification {
+    /// Sample the full resolution atlas only.
+    None,
+    /// Sample a mip chain with up to `levels` downsampled levels. Only for
+    /// atlases packed with [`crate::mipmap::mip_padding`] between glyphs,
+    /// like those of [`crate::generator`] with enough
+    /// [`GeneratorSettings::mip_levels`], otherwise neighbouring glyphs
+    /// bleed into each other.
+    ///
+    /// [`GeneratorSettings::mip_levels`]:
+    /// crate::generator::GeneratorSettings::mip_levels
+    Mipmaps { levels: u32 },
+    /// Average four samples per pixel in the fragment shader. Costs more
+    /// per pixel but keeps the atlas sharp at a distance.
+    Supersample,
+}
+
+/// Atlases made elsewhere rarely leave room for mipmaps between glyphs.
+impl Default for Minification {
+    fn default() -> Self {
+        Minification::None
+    }
+}
+
+fn texture_format(
+    gfx: &Graphics,
+    image: &Image,
+) -> Result<wgpu::TextureFormat, AtlasError> {
+    match (image.pixel_format, image.channels) {
+        (PixelFormat::Unsigned8, 1) => Ok(wgpu::TextureFormat::R8Unorm),
+        (PixelFormat::Unsigned8, 2..=4) => Ok(wgpu::TextureFormat::Rgba8Unorm),
+        (PixelFormat::Float32, 1..=4) => {
+            // Rgba32Float can only be filtered with an adapter specific
+            // feature.
+            let adapter_specific =
+                wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+            let filterable = gfx.device.features().contains(adapter_specific)
+                && gfx
+                    .adapter
+                    .get_texture_format_features(
+                        wgpu::TextureFormat::Rgba32Float,
+                    )
+                    .flags
+                    .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE);
+            Ok(if filterable {
+                wgpu::TextureFormat::Rgba32Float
+            } else {
+                wgpu::TextureFormat::Rgba16Float
+            })
         }
+        (pixel_format, channels) => Err(AtlasError::UnsupportedFormat {
+            pixel_format,
+            channels,
+        }),
     }
 }
 
+/// Converts the pixels of `image` to `format`, see [`texture_format`].
+/// Grayscale images with alpha are spread over red, green and blue, so the
+/// median taken by the shaders is the grayscale value.
+fn texture_data(image: &Image, format: wgpu::TextureFormat) -> Vec<u8> {
+    let channels = image.channels as usize;
+    match format {
+        wgpu::TextureFormat::R8Unorm => image.data.clone(),
+        wgpu::TextureFormat::Rgba8Unorm => {
+            if channels == 4 {
+                return image.data.clone();
+            }
+            image
+                .data
+                .chunks_exact(channels)
+                .flat_map(|p| to_rgba(p, 255))
+                .collect()
+        }
+        _ => {
+            let floats = image
+                .data
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect::<Vec<f32>>();
+            let rgba =
+                floats.chunks_exact(channels).flat_map(|p| to_rgba(p, 1.0));
+            if format == wgpu::TextureFormat::Rgba32Float {
+                rgba.flat_map(f32::to_le_bytes).collect()
+            } else {
+                rgba.flat_map(|v| f32_to_f16(v).to_le_bytes()).collect()
+            }
+        }
+    }
+}
+
+fn to_rgba<T: Copy>(pixel: &[T], opaque: T) -> [T; 4] {
+    match *pixel {
+        [v] => [v, v, v, opaque],
+        [v, a] => [v, v, v, a],
+        [r, g, b] => [r, g, b, opaque],
+        [r, g, b, a] => [r, g, b, a],
+        _ => unreachable!(),
+    }
+}
+
+/// Converts to a half precision float, rounding to nearest even.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays NaN.
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal or zero.
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = 1 << (shift - 1);
+        let rest = mantissa & ((1 << shift) - 1);
+        let mut result = (mantissa >> shift) as u16;
+        if rest > half || (rest == half && result & 1 == 1) {
+            result += 1;
+        }
+        return sign | result;
+    }
+
+    let rest = mantissa & 0x1fff;
+    let mut result = ((exponent as u32) << 10 | mantissa >> 13) as u16;
+    // Rounding may carry into the exponent, which is still correct.
+    if rest > 0x1000 || (rest == 0x1000 && result & 1 == 1) {
+        result += 1;
+    }
+    sign | result
+}
+
 pub fn pipeline1(gfx: &Graphics, reqs: &Requisites) -> wgpu::RenderPipeline {
     let shader = gfx
         .device
@@ -336,6 +924,55 @@ pub fn pipeline4(gfx: &Graphics, reqs: &Requisites) -> wgpu::RenderPipeline {
         })
 }
 
+/// Pipeline for [`SolidRect`]s like selections and carets. It shares the
+/// bind group, and with it the matrix, of the text pipelines. There is no
+/// depth buffer, so selections are drawn before the text they are behind
+/// and carets after it.
+pub fn solid_pipeline(
+    gfx: &Graphics,
+    reqs: &Requisites,
+) -> wgpu::RenderPipeline {
+    let shader = gfx
+        .device
+        .create_shader_module(wgpu::include_wgsl!("shaders/solid.wgsl"));
+    let layout =
+        gfx.device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Solid Pipeline Layout"),
+                bind_group_layouts: &[&reqs.bind_group_layout],
+                push_constant_ranges: &[],
+            });
+    gfx.device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Solid Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[SolidRect::buffer_layout()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                strip_index_format: Some(wgpu::IndexFormat::Uint16),
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: gfx.config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Quad {
@@ -343,8 +980,35 @@ pub struct Quad {
     pub bottom_right: [f32; 2],
     pub tex_top_left: [f32; 2],
     pub tex_bottom_right: [f32; 2],
+    pub page: u32,
+    /// Combination of the `QUAD_*` flags.
+    pub flags: u32,
+    /// Offset of the distance threshold, greater than 0 makes the glyph
+    /// bolder.
+    pub thickness: f32,
+    /// Horizontal shift per unit above the baseline, for faux italics.
+    pub shear: f32,
+    /// Baseline the glyph is sheared around, its y or for sideways glyphs
+    /// its x.
+    pub baseline: f32,
+    /// Point billboarded quads turn around, their bounds are relative to
+    /// it. Unused by other quads.
+    pub anchor: [f32; 3],
+    /// Slot of [`Requisites::transform_buffer`] the quad is moved by.
+    pub transform: u32,
 }
 
+/// The glyph is turned a quarter clockwise, for sideways glyphs in
+/// vertical text. The quad bounds are those of the turned glyph.
+pub const QUAD_SIDEWAYS: u32 = 1;
+/// The quad faces the camera, see [`Globals::right`].
+pub const QUAD_BILLBOARD: u32 = 2;
+/// With [`QUAD_BILLBOARD`], the quad only turns around the y axis.
+pub const QUAD_CYLINDRICAL: u32 = 4;
+/// With [`QUAD_BILLBOARD`], bounds are in pixels and the quad keeps its
+/// size on screen at any distance.
+pub const QUAD_FIXED_SIZE: u32 = 8;
+
 impl Quad {
     fn buffer_layout() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
@@ -374,7 +1038,152 @@ impl Quad {
                         as wgpu::BufferAddress,
                     shader_location: 3,
                 },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Uint32,
+                    offset: std::mem::size_of::<[f32; 9]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 4,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Uint32,
+                    offset: std::mem::size_of::<[f32; 10]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 5,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32,
+                    offset: std::mem::size_of::<[f32; 11]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 6,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32,
+                    offset: std::mem::size_of::<[f32; 12]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 7,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32,
+                    offset: std::mem::size_of::<[f32; 13]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 8,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x3,
+                    offset: std::mem::size_of::<[f32; 14]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 9,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Uint32,
+                    offset: std::mem::size_of::<[f32; 17]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 10,
+                },
+            ],
+        }
+    }
+}
+
+/// Flat colored rectangle, drawn with [`solid_pipeline`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SolidRect {
+    pub top_left: [f32; 3],
+    pub bottom_right: [f32; 2],
+    /// Non-premultiplied RGBA.
+    pub color: [f32; 4],
+    /// Slot of [`Requisites::transform_buffer`] the rectangle is moved by.
+    pub transform: u32,
+}
+
+impl SolidRect {
+    fn buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SolidRect>()
+                as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x3,
+                    offset: 0,
+                    shader_location: 0,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x2,
+                    offset: std::mem::size_of::<[f32; 3]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 1,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 5]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 2,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Uint32,
+                    offset: std::mem::size_of::<[f32; 9]>()
+                        as wgpu::BufferAddress,
+                    shader_location: 3,
+                },
             ],
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::Text;
+
+    #[test]
+    fn half_floats_round_to_nearest_even() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        // Halfway between 1 and the next half float, and past it.
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        // Rounding up carries into the exponent.
+        assert_eq!(f32_to_f16(2047.5), 0x6800);
+    }
+
+    #[test]
+    fn half_floats_keep_subnormals_and_specials() {
+        assert_eq!(f32_to_f16(2f32.powi(-14)), 0x0400);
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7fff, 0x7e00);
+    }
+
+    #[test]
+    fn transform_slots_stop_at_the_buffer_end() {
+        assert!(check_transform_slot(0).is_ok());
+        assert!(check_transform_slot(TRANSFORM_SLOTS - 1).is_ok());
+        assert!(matches!(
+            check_transform_slot(TRANSFORM_SLOTS),
+            Err(TransformSlotError::OutOfRange(TRANSFORM_SLOTS))
+        ));
+        assert!(matches!(
+            check_transform_slot(u32::MAX),
+            Err(TransformSlotError::OutOfRange(u32::MAX))
+        ));
+    }
+
+    #[test]
+    fn texts_keep_their_slot_when_it_is_rejected() {
+        let mut text = Text::new("a", (0.0, 0.0, 0.0));
+        assert!(text.set_transform(3).is_ok());
+        assert!(text.set_transform(TRANSFORM_SLOTS).is_err());
+        assert_eq!(text.transform(), 3);
+        assert!(text.set_transform(0).is_ok());
+        assert_eq!(text.transform(), 0);
+    }
+}